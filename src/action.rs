@@ -1,6 +1,21 @@
 
-use std::{default, marker::PhantomData, path::PathBuf,fs};
+use std::{collections::HashMap, default, ffi::OsString, marker::PhantomData, path::{Path, PathBuf},fs, sync::{Arc, LazyLock, Mutex}, time::Duration};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::command;
+use crate::filesystem::FileSystem;
+use crate::hash::HashCache;
+use crate::mapping::Mapping;
+use crate::metadata::{FileMeta, TimeZoneChoice};
+use crate::script::{Program, ScriptInput};
+use crate::validate::{self, TargetProfile};
+use crate::token::{self, expand_tokens, Segment, TokenError, TokenValues};
+
+/// Characters that are never allowed in a new file name.
+pub const INVALID_CHARS: [char; 10] = ['<','>',':','"','/','\\','|','?','*','.'];
 
 
 #[derive(Debug)]
@@ -13,9 +28,196 @@ pub struct Affix {
 pub struct Replace {
         operation: Operation,
         pattern: String,
+        /// `pattern` compiled once, `None` when it is not a valid regex.
+        regex: Option<Regex>,
         replace_str: String,
 }
 
+#[derive(Debug)]
+pub struct Template {
+        template: String,
+        pattern: String,
+        regex: Option<Regex>,
+        segments: Result<Vec<Segment>, Vec<TokenError>>,
+}
+
+#[derive(Debug)]
+pub struct Unicode {
+        options: UnicodeOptions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum NormalForm {
+        #[default]
+        Nfc,
+        Nfd,
+        Nfkc,
+        Nfkd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UnicodeOptions {
+        pub form: NormalForm,
+        /// Replace non ASCII characters with their closest ASCII spelling.
+        pub transliterate: bool,
+        /// Lowercase ASCII words joined by hyphens, implies `transliterate`.
+        pub slugify: bool,
+}
+
+#[derive(Debug)]
+pub struct MakeSafe {
+        profile: TargetProfile,
+}
+
+#[derive(Debug)]
+pub struct Cleanup {
+        options: CleanupOptions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum SpaceConversion {
+        #[default]
+        Keep,
+        /// `_`, `.` and `%20` become spaces.
+        ToSpaces,
+        /// Spaces become `_`.
+        ToUnderscores,
+        /// Spaces become `.`.
+        ToDots,
+        /// Spaces become `%20`.
+        ToPercent20,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CleanupOptions {
+        pub collapse_whitespace: bool,
+        pub trim: bool,
+        pub spaces: SpaceConversion,
+        /// ` - Copy`, `Copy of ` and `(1)` markers added by file managers.
+        pub strip_copy_markers: bool,
+        pub remove_empty_brackets: bool,
+        pub collapse_separators: bool,
+}
+
+impl Default for CleanupOptions {
+        fn default() -> Self {
+                Self {
+                        collapse_whitespace: true,
+                        trim: true,
+                        spaces: SpaceConversion::Keep,
+                        strip_copy_markers: false,
+                        remove_empty_brackets: false,
+                        collapse_separators: false,
+                }
+        }
+}
+
+#[derive(Debug)]
+pub struct Truncate {
+        options: TruncateOptions,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum LengthUnit {
+        /// User perceived characters (grapheme clusters).
+        #[default]
+        Characters,
+        /// UTF-8 bytes, as counted by most Linux file systems.
+        Bytes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TruncateOptions {
+        /// Maximum length of the stem, `0` leaves names untouched.
+        pub max_length: usize,
+        pub unit: LengthUnit,
+        /// Cut at the last space or separator instead of inside a word.
+        pub word_boundary: bool,
+        /// End shortened names with `…`, counted in the maximum length.
+        pub ellipsis: bool,
+}
+
+#[derive(Debug)]
+pub struct Renumber {
+        options: RenumberOptions,
+        /// Digits of the largest number in the batch, set by `prepare`.
+        widest: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum NumberTarget {
+        #[default]
+        First,
+        Last,
+        All,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum NumberPadding {
+        /// Keep as many digits as the original number.
+        #[default]
+        Keep,
+        /// Zero-pad to `RenumberOptions::width` digits.
+        Fixed,
+        /// Zero-pad to the digits of the largest number in the batch.
+        Widest,
+        /// Remove leading zeros.
+        Strip,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenumberOptions {
+        pub target: NumberTarget,
+        pub padding: NumberPadding,
+        pub width: usize,
+        /// Added to every number, `12` turns episode 1 into 13.
+        pub offset: i64,
+}
+
+/// New names imported from a CSV/TSV file.
+#[derive(Debug)]
+pub struct Import {
+        mapping: Mapping,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandOptions {
+        /// One process for all names, line by line, instead of one per file.
+        pub batch: bool,
+        /// Send the full path instead of the file name.
+        pub full_paths: bool,
+        pub timeout_secs: u64,
+}
+
+impl Default for CommandOptions {
+        fn default() -> Self {
+                Self { batch: false, full_paths: false, timeout_secs: 10 }
+        }
+}
+
+/// Output of a command for each input path, shared by the actions built for the same step.
+pub type CommandResults = Arc<Mutex<HashMap<PathBuf, Result<String, String>>>>;
+
+/// New names printed by an external command.
+#[derive(Debug)]
+pub struct External {
+        command: String,
+        options: CommandOptions,
+        /// Output for each input path, filled by `prepare`.
+        results: CommandResults,
+}
+
+/// New names returned by a user script.
+#[derive(Debug)]
+pub struct Script {
+        program: Program,
+}
+
 /*#[derive(Debug)]
 pub struct LetterCase {
 
@@ -38,16 +240,57 @@ pub enum InsertMode {
 pub enum StateValue {
     AffixValue(String),
     ReplaceValue(String, String),
+    TemplateValue(String, String),
+    UnicodeValue(UnicodeOptions),
+    ProfileValue(TargetProfile),
+    CleanupValue(CleanupOptions),
+    TruncateValue(TruncateOptions),
+    RenumberValue(RenumberOptions),
+    MappingValue(Mapping),
+    ScriptValue(String),
+    CommandValue(String, CommandOptions),
+}
+/// Per-file information available to actions while building the new name.
+#[derive(Debug, Default)]
+pub struct FileContext<'a> {
+        /// Path of the file before any action ran.
+        pub original: Option<&'a Path>,
+        /// Position of the file in the list, used by counters.
+        pub index: usize,
+        pub meta: Option<&'a FileMeta>,
+        pub time_zone: TimeZoneChoice,
+        /// Content hashes computed so far, keyed by original path.
+        pub hashes: Option<&'a HashCache>,
 }
+
 pub trait Action {
         fn action(&self, file_name: &PathBuf) -> PathBuf;
 
+        /// Same as `action`, for actions that use tokens from the file context.
+        fn action_with(&self, file_name: &PathBuf, _ctx: &FileContext) -> PathBuf {
+                self.action(file_name)
+        }
+
+        /// Same as `action_with`, with the reason when the step cannot rename this file.
+        fn try_action(&self, file_name: &PathBuf, ctx: &FileContext) -> Result<PathBuf, String> {
+                Ok(self.action_with(file_name, ctx))
+        }
+
         fn update_values(&mut self,data: StateValue);
 
-        fn rename_file(&self, old_name: &PathBuf,new_name: &PathBuf) {//blocking
-                if !old_name.exists()
+        /// Called with the names entering this step for the whole batch before `action_with`,
+        /// for actions that depend on the other files.
+        fn prepare(&mut self, _file_names: &[&Path]) {}
+
+        /// Problems with the current input, shown under the action.
+        fn validation_errors(&self) -> Vec<String> {
+                Vec::new()
+        }
+
+        fn rename_file(&self, fs: &dyn FileSystem, old_name: &PathBuf,new_name: &PathBuf) {//blocking
+                if !fs.exists(old_name)
                         {return;}
-                std::fs::rename(old_name, new_name);
+                let _ = fs.rename(old_name, new_name);
         }
 }
 
@@ -64,11 +307,13 @@ impl Modify<Replace> {
                 Self {
                 state:Replace { operation: Operation::ReplaceString,
                         pattern: String::new(),
+                        regex: None,
                         replace_str:String::new() }
                 }
         }
         pub fn set_pattern(&mut self,pattern: &str) -> &mut Self {
                 self.state.pattern = pattern.to_owned();
+                self.state.regex = Regex::new(pattern).ok();
                 self
         }
 
@@ -96,8 +341,7 @@ impl Modify<Replace> {
                 match self.state.operation {
                     Operation::ReplaceString => { value = value.replacen(&self.state.pattern, &self.state.replace_str,1);},
                     Operation::ReplaceRegex => {
-                        let re = Regex::new(&self.state.pattern);
-                        if let Some(re) = re.ok() {
+                        if let Some(re) = &self.state.regex {
                                  let result = re.replace(&value, &self.state.replace_str);
                                  value = result.into_owned();
                         }//do nothing if regex fail
@@ -106,10 +350,7 @@ impl Modify<Replace> {
                     Operation::Remove => {value = value.replace(&self.state.pattern,"");},
                     _ => println!("String modification failed"),
                 }
-                path.set_file_name(value);
-                if let Some(ext) = extension {
-                        path.set_extension(ext);
-                }
+                set_stem(path, value, extension);
 
         }
 }
@@ -124,7 +365,7 @@ impl Action for Modify<Replace> {
         fn update_values(&mut self,data: StateValue) {
 
                 if let StateValue::ReplaceValue(pattern,value) = data {
-                        self.state.pattern = pattern;
+                        self.set_pattern(&pattern);
                         self.state.replace_str = value;
 
                 };
@@ -150,22 +391,21 @@ impl Modify<Affix> {
                 self.state.mode = InsertMode::Suffix;
         }
 
-        fn add_affix(&self,path: &mut PathBuf) {
+        fn add_affix(&self,path: &mut PathBuf, ctx: &FileContext) {
                 let extension = path.extension().map(|ext| ext.to_os_string());
                 let mut value = String::new();
                 if let Some(file_name) = path.file_stem() {
                         value = file_name.to_string_lossy().into_owned();
                 } else{ return;/*Do not modify if its none*/ };
 
+                let values = TokenValues { ctx, current: path, captures: None };
+                let str_to_add = expand_tokens(&self.state.str_to_add, &values);
                 match self.state.mode {
-                        InsertMode::Prefix => { value.insert_str(0, &self.state.str_to_add)},
-                        InsertMode::Suffix => { value.push_str(&self.state.str_to_add);},
+                        InsertMode::Prefix => { value.insert_str(0, &str_to_add)},
+                        InsertMode::Suffix => { value.push_str(&str_to_add);},
                         _ => {println!("Error adding affix");},
                 }
-                path.set_file_name(value);
-                if let Some(ext) = extension {
-                        path.set_extension(ext);
-                }
+                set_stem(path, value, extension);
         }
         
 }
@@ -173,8 +413,12 @@ impl Modify<Affix> {
 impl Action for Modify<Affix> {
         
          fn action(&self,file_name: &PathBuf ) ->PathBuf {
+                self.action_with(file_name, &FileContext::default())
+        }
+
+        fn action_with(&self,file_name: &PathBuf, ctx: &FileContext) -> PathBuf {
                 let mut new_name = file_name.to_owned();
-                self.add_affix(&mut new_name);
+                self.add_affix(&mut new_name, ctx);
                 new_name
         }
 
@@ -185,6 +429,660 @@ impl Action for Modify<Affix> {
         }
 }
 
+impl Modify<Template> {
+        pub fn new_template() -> Modify<Template> {
+                let mut modify = Self {
+                state: Template { template: String::new(),
+                        pattern: String::new(),
+                        regex: None,
+                        segments: Ok(Vec::new()) }
+                };
+                modify.set_template("", "{stem}");
+                modify
+        }
+
+        /// `pattern` is an optional regex whose capture groups can be used as `{1}` or `{name}`.
+        pub fn set_template(&mut self, pattern: &str, template: &str) -> &mut Self {
+                self.state.pattern = pattern.to_owned();
+                self.state.template = template.to_owned();
+                self.state.regex = if pattern.is_empty() { None } else { Regex::new(pattern).ok() };
+                self.state.segments = token::parse(template).and_then(|segments| {
+                        let errors = token::validate(&segments, self.state.regex.as_ref());
+                        if errors.is_empty() { Ok(segments) } else { Err(errors) }
+                });
+                self
+        }
+
+        fn apply_template(&self, path: &mut PathBuf, ctx: &FileContext) -> Result<(), String> {
+                let Ok(segments) = &self.state.segments else { return Ok(()); /*Do not modify if the template is invalid*/ };
+                if segments.is_empty() {
+                        return Ok(());
+                }
+                let extension = path.extension().map(|ext| ext.to_os_string());
+                let stem = match path.file_stem() {
+                        Some(file_stem) => file_stem.to_string_lossy().into_owned(),
+                        None => return Ok(()),
+                };
+
+                let current = path.clone();
+                let values = TokenValues {
+                        ctx,
+                        current: &current,
+                        captures: self.state.regex.as_ref().and_then(|re| re.captures(&stem)),
+                };
+                if let Some(error) = token::render_error(segments, &values) {
+                        return Err(error);
+                }
+                let value = token::render(segments, &values);
+                if value.is_empty() {
+                        return Err(String::from("the template gives an empty name"));
+                }
+                set_stem(path, value, extension);
+                Ok(())
+        }
+}
+
+impl Action for Modify<Template> {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                self.action_with(file_name, &FileContext::default())
+        }
+
+        /// Files the template fails on keep their name.
+        fn action_with(&self,file_name: &PathBuf, ctx: &FileContext) -> PathBuf {
+                self.try_action(file_name, ctx).unwrap_or_else(|_| file_name.to_owned())
+        }
+
+        fn try_action(&self, file_name: &PathBuf, ctx: &FileContext) -> Result<PathBuf, String> {
+                let mut new_name = file_name.to_owned();
+                self.apply_template(&mut new_name, ctx)?;
+                Ok(new_name)
+        }
+
+        fn update_values(&mut self,data: StateValue) {
+                if let StateValue::TemplateValue(pattern,template) = data {
+                        self.set_template(&pattern, &template);
+                };
+        }
+
+        fn validation_errors(&self) -> Vec<String> {
+                let mut errors = Vec::new();
+                if !self.state.pattern.is_empty() && self.state.regex.is_none() {
+                        errors.push(String::from("Regex: invalid pattern"));
+                }
+                if let Err(token_errors) = &self.state.segments {
+                        errors.extend(token_errors.iter().map(|error| format!("{}: {}", error.token, error.message)));
+                }
+                errors
+        }
+}
+
+impl Modify<Unicode> {
+        pub fn new_unicode(options: UnicodeOptions) -> Modify<Unicode> {
+                Self {
+                state: Unicode { options }
+                }
+        }
+
+        fn convert(&self, path: &mut PathBuf) {
+                let extension = path.extension().map(|ext| ext.to_os_string());
+                let value = match path.file_stem() {
+                        Some(file_stem) => file_stem.to_string_lossy().into_owned(),
+                        None => return,
+                };
+
+                let options = self.state.options;
+                let mut value: String = match options.form {
+                        NormalForm::Nfc => value.nfc().collect(),
+                        NormalForm::Nfd => value.nfd().collect(),
+                        NormalForm::Nfkc => value.nfkc().collect(),
+                        NormalForm::Nfkd => value.nfkd().collect(),
+                };
+                if options.transliterate || options.slugify {
+                        value = transliterate(&value);
+                }
+                if options.slugify {
+                        value = slugify(&value);
+                }
+                set_stem(path, value, extension);
+        }
+}
+
+impl Action for Modify<Unicode> {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                let mut new_name = file_name.to_owned();
+                self.convert(&mut new_name);
+                new_name
+        }
+
+        fn update_values(&mut self,data: StateValue) {
+                if let StateValue::UnicodeValue(options) = data {
+                        self.state.options = options;
+                };
+        }
+}
+
+impl Modify<MakeSafe> {
+        pub fn new_make_safe(profile: TargetProfile) -> Modify<MakeSafe> {
+                Self {
+                state: MakeSafe { profile }
+                }
+        }
+}
+
+impl Action for Modify<MakeSafe> {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                let mut new_name = file_name.to_owned();
+                if let Some(name) = file_name.file_name() {
+                        new_name.set_file_name(validate::make_safe(self.state.profile, &name.to_string_lossy()));
+                }
+                new_name
+        }
+
+        fn update_values(&mut self,data: StateValue) {
+                if let StateValue::ProfileValue(profile) = data {
+                        self.state.profile = profile;
+                };
+        }
+}
+
+static COPY_MARKERS: LazyLock<Regex> = LazyLock::new(|| {
+        Regex::new(r"(?i)(\s*-\s*copy(\s*\(\d+\))?|\s*\(\d+\))+$").unwrap()
+});
+static COPY_OF: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"(?i)^copy( \(\d+\))? of ").unwrap());
+static EMPTY_BRACKETS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\(\s*\)|\[\s*\]|\{\s*\}").unwrap());
+static REPEATED_SEPARATORS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"_{2,}|-{2,}|\.{2,}").unwrap());
+static WHITESPACE: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s{2,}").unwrap());
+
+impl Modify<Cleanup> {
+        pub fn new_cleanup(options: CleanupOptions) -> Modify<Cleanup> {
+                Self {
+                state: Cleanup { options }
+                }
+        }
+
+        fn clean(&self, path: &mut PathBuf) {
+                let extension = path.extension().map(|ext| ext.to_os_string());
+                let mut value = match path.file_stem() {
+                        Some(file_stem) => file_stem.to_string_lossy().into_owned(),
+                        None => return,
+                };
+
+                let options = self.state.options;
+                if options.strip_copy_markers {
+                        value = COPY_OF.replace(&value, "").into_owned();
+                        value = COPY_MARKERS.replace(&value, "").into_owned();
+                }
+                if options.remove_empty_brackets {
+                        // Removing `[()]` leaves `[]`, repeat until nothing changes
+                        while EMPTY_BRACKETS.is_match(&value) {
+                                value = EMPTY_BRACKETS.replace_all(&value, "").into_owned();
+                        }
+                }
+                if options.collapse_separators {
+                        value = REPEATED_SEPARATORS.replace_all(&value, |caps: &regex::Captures| caps[0][..1].to_owned()).into_owned();
+                }
+                let tidy = |value: String| {
+                        let value = if options.collapse_whitespace { WHITESPACE.replace_all(&value, " ").into_owned() } else { value };
+                        if options.trim { value.trim().to_owned() } else { value }
+                };
+                value = tidy(value);
+                // Converted last, so trimmed ends and collapsed runs never become underscores,
+                // spaces made from separators are tidied again
+                match options.spaces {
+                        SpaceConversion::Keep => {},
+                        SpaceConversion::ToSpaces => value = tidy(value.replace("%20", " ").replace(['_', '.'], " ")),
+                        SpaceConversion::ToUnderscores => value = value.replace(' ', "_"),
+                        SpaceConversion::ToDots => value = value.replace(' ', "."),
+                        SpaceConversion::ToPercent20 => value = value.replace(' ', "%20"),
+                }
+                set_stem(path, value, extension);
+        }
+}
+
+impl Action for Modify<Cleanup> {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                let mut new_name = file_name.to_owned();
+                self.clean(&mut new_name);
+                new_name
+        }
+
+        fn update_values(&mut self,data: StateValue) {
+                if let StateValue::CleanupValue(options) = data {
+                        self.state.options = options;
+                };
+        }
+}
+
+const ELLIPSIS: &str = "…";
+
+impl Modify<Truncate> {
+        pub fn new_truncate(options: TruncateOptions) -> Modify<Truncate> {
+                Self {
+                state: Truncate { options }
+                }
+        }
+
+        fn measure(&self, value: &str) -> usize {
+                match self.state.options.unit {
+                        LengthUnit::Characters => value.graphemes(true).count(),
+                        LengthUnit::Bytes => value.len(),
+                }
+        }
+
+        fn truncate(&self, path: &mut PathBuf) {
+                let options = self.state.options;
+                let extension = path.extension().map(|ext| ext.to_os_string());
+                let value = match path.file_stem() {
+                        Some(file_stem) => file_stem.to_string_lossy().into_owned(),
+                        None => return,
+                };
+                if options.max_length == 0 || self.measure(&value) <= options.max_length {
+                        return;
+                }
+
+                let marker = if options.ellipsis { ELLIPSIS } else { "" };
+                let budget = options.max_length.saturating_sub(self.measure(marker));
+                let mut short = String::new();
+                let mut used = 0;
+                let mut graphemes = value.graphemes(true).peekable();
+                while let Some(grapheme) = graphemes.peek() {
+                        let size = self.measure(grapheme);
+                        if used + size > budget {
+                                break;
+                        }
+                        short.push_str(grapheme);
+                        used += size;
+                        graphemes.next();
+                }
+
+                let is_boundary = |c: char| c.is_whitespace() || matches!(c, '_' | '-' | '.');
+                let cut_inside_word = graphemes.peek().is_some_and(|next| !next.starts_with(is_boundary));
+                if options.word_boundary && cut_inside_word {
+                        if let Some(last_boundary) = short.rfind(is_boundary).filter(|pos| *pos > 0) {
+                                short.truncate(last_boundary);
+                        }
+                }
+                let mut short = short.trim_end_matches(is_boundary).to_owned();
+                short.push_str(marker);
+                set_stem(path, short, extension);
+        }
+}
+
+impl Action for Modify<Truncate> {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                let mut new_name = file_name.to_owned();
+                self.truncate(&mut new_name);
+                new_name
+        }
+
+        fn update_values(&mut self,data: StateValue) {
+                if let StateValue::TruncateValue(options) = data {
+                        self.state.options = options;
+                };
+        }
+}
+
+static NUMBERS: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\d+").unwrap());
+
+impl Modify<Renumber> {
+        pub fn new_renumber(options: RenumberOptions) -> Modify<Renumber> {
+                Self {
+                state: Renumber { options, widest: 0 }
+                }
+        }
+
+        /// Numeric runs of the stem that the options target.
+        fn targets<'s>(&self, stem: &'s str) -> Vec<regex::Match<'s>> {
+                let mut runs: Vec<regex::Match> = NUMBERS.find_iter(stem).collect();
+                match self.state.options.target {
+                        NumberTarget::First => runs.truncate(1),
+                        NumberTarget::Last => { runs.drain(..runs.len().saturating_sub(1)); },
+                        NumberTarget::All => {},
+                }
+                runs
+        }
+
+        /// The run with the offset applied, `None` when it does not fit or drops below zero.
+        fn shifted(&self, run: &str) -> Option<u64> {
+                let value: u64 = run.parse().ok()?;
+                value.checked_add_signed(self.state.options.offset)
+        }
+
+        fn renumber(&self, path: &mut PathBuf) {
+                let options = self.state.options;
+                let extension = path.extension().map(|ext| ext.to_os_string());
+                let value = match path.file_stem() {
+                        Some(file_stem) => file_stem.to_string_lossy().into_owned(),
+                        None => return,
+                };
+
+                let mut output = String::new();
+                let mut last = 0;
+                for run in self.targets(&value) {
+                        let Some(number) = self.shifted(run.as_str()) else { continue; };
+                        let width = match options.padding {
+                                NumberPadding::Keep => run.len(),
+                                NumberPadding::Fixed => options.width,
+                                NumberPadding::Widest => self.state.widest,
+                                NumberPadding::Strip => 0,
+                        };
+                        output.push_str(&value[last..run.start()]);
+                        output.push_str(&format!("{:0width$}", number, width = width));
+                        last = run.end();
+                }
+                output.push_str(&value[last..]);
+                set_stem(path, output, extension);
+        }
+}
+
+impl Action for Modify<Renumber> {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                let mut new_name = file_name.to_owned();
+                self.renumber(&mut new_name);
+                new_name
+        }
+
+        fn update_values(&mut self,data: StateValue) {
+                if let StateValue::RenumberValue(options) = data {
+                        self.state.options = options;
+                };
+        }
+
+        fn prepare(&mut self, file_names: &[&Path]) {
+                self.state.widest = file_names.iter()
+                        .filter_map(|path| path.file_stem())
+                        .flat_map(|stem| {
+                                let stem = stem.to_string_lossy();
+                                self.targets(&stem).iter()
+                                        .filter_map(|run| self.shifted(run.as_str()))
+                                        .map(|number| number.to_string().len())
+                                        .collect::<Vec<_>>()
+                        })
+                        .max()
+                        .unwrap_or(0);
+        }
+}
+
+impl Modify<Import> {
+        pub fn new_import(mapping: Mapping) -> Modify<Import> {
+                Self {
+                state: Import { mapping }
+                }
+        }
+
+        /// Rows are matched against `original`, the new name replaces the whole file name.
+        fn import(&self, path: &mut PathBuf, original: &Path) {
+                if let Some(new_name) = self.state.mapping.lookup(original) {
+                        set_stem(path, new_name.to_owned(), None);
+                }
+        }
+}
+
+impl Action for Modify<Import> {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                let mut new_name = file_name.to_owned();
+                self.import(&mut new_name, file_name);
+                new_name
+        }
+
+        fn action_with(&self,file_name: &PathBuf, ctx: &FileContext) -> PathBuf {
+                let mut new_name = file_name.to_owned();
+                self.import(&mut new_name, ctx.original.unwrap_or(file_name));
+                new_name
+        }
+
+        fn update_values(&mut self,data: StateValue) {
+                if let StateValue::MappingValue(mapping) = data {
+                        self.state.mapping = mapping;
+                };
+        }
+}
+
+impl Modify<Script> {
+        pub fn new_script(source: &str) -> Modify<Script> {
+                Self {
+                state: Script { program: Program::new(source) }
+                }
+        }
+
+        fn run_script(&self, path: &PathBuf, ctx: &FileContext) -> Result<PathBuf, String> {
+                let Some(stem) = path.file_stem() else { return Ok(path.to_owned()); };
+                let ext = path.extension().map(|ext| ext.to_string_lossy());
+                let original = ctx.original.unwrap_or(path).file_name().unwrap_or_default().to_string_lossy();
+                let input = ScriptInput {
+                        stem: &stem.to_string_lossy(),
+                        ext: ext.as_deref(),
+                        original: &original,
+                        index: ctx.index,
+                        meta: ctx.meta,
+                        time_zone: ctx.time_zone,
+                };
+                let (new_stem, new_ext) = self.state.program.run(&input)?;
+                let extension = match new_ext {
+                        Some(new_ext) if new_ext.is_empty() => None,
+                        Some(new_ext) => Some(OsString::from(new_ext)),
+                        None => path.extension().map(|ext| ext.to_os_string()),
+                };
+                let mut new_name = path.to_owned();
+                set_stem(&mut new_name, new_stem, extension);
+                Ok(new_name)
+        }
+}
+
+impl Action for Modify<Script> {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                self.action_with(file_name, &FileContext::default())
+        }
+
+        /// Files the script fails on keep their name.
+        fn action_with(&self,file_name: &PathBuf, ctx: &FileContext) -> PathBuf {
+                self.run_script(file_name, ctx).unwrap_or_else(|_| file_name.to_owned())
+        }
+
+        fn try_action(&self, file_name: &PathBuf, ctx: &FileContext) -> Result<PathBuf, String> {
+                self.run_script(file_name, ctx)
+        }
+
+        fn update_values(&mut self,data: StateValue) {
+                if let StateValue::ScriptValue(source) = data {
+                        self.state.program = Program::new(&source);
+                };
+        }
+
+        fn validation_errors(&self) -> Vec<String> {
+                self.state.program.error().map(|error| format!("Script: {}", error)).into_iter().collect()
+        }
+}
+
+impl Modify<External> {
+        pub fn new_command(command: &str, options: CommandOptions) -> Modify<External> {
+                Self {
+                state: External { command: command.trim().to_owned(), options, results: CommandResults::default() }
+                }
+        }
+
+        /// Reuses the output of an earlier action of the same step, so a command only runs once per name.
+        pub fn with_results(mut self, results: CommandResults) -> Modify<External> {
+                self.state.results = results;
+                self
+        }
+
+        fn timeout(&self) -> Duration {
+                Duration::from_secs(self.state.options.timeout_secs.max(1))
+        }
+
+        /// What the command reads for `path`.
+        fn input(&self, path: &Path) -> String {
+                if self.state.options.full_paths {
+                        path.to_string_lossy().into_owned()
+                } else {
+                        path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+                }
+        }
+
+        fn run_one(&self, path: &Path) -> Result<String, String> {
+                let input = self.input(path);
+                command::run_each(&self.state.command, &[(input + "\n", path.to_string_lossy().into_owned())], self.timeout())
+                        .remove(0)
+        }
+
+        /// The output is the new file name, sub folders included. An absolute path, like the input
+        /// with `full_paths`, is only taken when it stays inside the file's folder.
+        fn apply_output(path: &Path, output: &str) -> Result<PathBuf, String> {
+                let output = output.trim();
+                if output.is_empty() {
+                        return Err(String::from("command printed no name"));
+                }
+                let folder = path.parent().unwrap_or(Path::new(""));
+                let name = match Path::new(output) {
+                        absolute if absolute.is_absolute() => absolute.strip_prefix(folder)
+                                .map_err(|_| format!("command printed `{}`, outside the file's folder", output))?
+                                .to_string_lossy()
+                                .into_owned(),
+                        _ => output.to_owned(),
+                };
+                let mut new_name = path.to_owned();
+                set_stem(&mut new_name, name, None);
+                Ok(new_name)
+        }
+}
+
+impl Action for Modify<External> {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                self.action_with(file_name, &FileContext::default())
+        }
+
+        /// Files the command fails on keep their name.
+        fn action_with(&self,file_name: &PathBuf, ctx: &FileContext) -> PathBuf {
+                self.try_action(file_name, ctx).unwrap_or_else(|_| file_name.to_owned())
+        }
+
+        fn try_action(&self, file_name: &PathBuf, _ctx: &FileContext) -> Result<PathBuf, String> {
+                if self.state.command.is_empty() {
+                        return Ok(file_name.to_owned());
+                }
+                let cached = self.state.results.lock().unwrap().get(file_name).cloned();
+                let output = match cached {
+                        Some(result) => result?,
+                        None => self.run_one(file_name)?,
+                };
+                Self::apply_output(file_name, &output)
+        }
+
+        fn update_values(&mut self,data: StateValue) {
+                if let StateValue::CommandValue(command, options) = data {
+                        *self = Self::new_command(&command, options);
+                };
+        }
+
+        /// Runs the command for the names it has no output for yet, a batch runs again whenever the names change.
+        fn prepare(&mut self, file_names: &[&Path]) {
+                if self.state.command.is_empty() {
+                        return;
+                }
+                let timeout = self.timeout();
+                let mut results = self.state.results.lock().unwrap();
+                if self.state.options.batch {
+                        if file_names.len() == results.len() && file_names.iter().all(|path| results.contains_key(*path)) {
+                                return;
+                        }
+                        let inputs: Vec<String> = file_names.iter().map(|path| self.input(path)).collect();
+                        results.clear();
+                        let (lines, broken): (Vec<usize>, Vec<usize>) = (0..inputs.len()).partition(|i| !inputs[*i].contains('\n'));
+                        for i in broken {
+                                results.insert(file_names[i].to_path_buf(), Err(String::from("name contains a line break")));
+                        }
+                        let batch: Vec<String> = lines.iter().map(|i| inputs[*i].clone()).collect();
+                        let outputs = command::run_batch(&self.state.command, &batch, timeout);
+                        for (n, i) in lines.into_iter().enumerate() {
+                                let result = outputs.as_ref().map(|outputs| outputs[n].clone()).map_err(Clone::clone);
+                                results.insert(file_names[i].to_path_buf(), result);
+                        }
+                } else {
+                        let missing: Vec<&Path> = file_names.iter().copied()
+                                .filter(|path| !results.contains_key(*path))
+                                .collect();
+                        let inputs: Vec<(String, String)> = missing.iter()
+                                .map(|path| (self.input(path) + "\n", path.to_string_lossy().into_owned()))
+                                .collect();
+                        let outputs = command::run_each(&self.state.command, &inputs, timeout);
+                        for (path, result) in missing.into_iter().zip(outputs) {
+                                results.insert(path.to_path_buf(), result);
+                        }
+                }
+        }
+}
+
+/// ASCII spelling of `value`, e.g. `é` to `e`, `ß` to `ss` and `東京` to `Dongjing`.
+fn transliterate(value: &str) -> String {
+        let mut output = String::new();
+        let mut in_syllables = false;
+        for c in value.chars() {
+                if c.is_ascii() {
+                        output.push(c);
+                        in_syllables = false;
+                        continue;
+                }
+                let ascii = deunicode::deunicode_char(c).unwrap_or("");
+                // CJK characters come back as capitalized syllables followed by a space,
+                // consecutive ones are joined into one word.
+                let syllable = ascii.len() > 1 && ascii.ends_with(' ');
+                let ascii = if syllable && in_syllables {
+                        ascii.trim_end().to_lowercase()
+                } else {
+                        ascii.trim_end_matches(' ').to_owned()
+                };
+                output.extend(ascii.chars().filter(|c| !INVALID_CHARS.contains(c)));
+                in_syllables = syllable;
+        }
+        output
+}
+
+fn slugify(value: &str) -> String {
+        let mut slug = String::new();
+        for c in value.chars() {
+                if c.is_ascii_alphanumeric() {
+                        slug.push(c.to_ascii_lowercase());
+                } else if !slug.is_empty() && !slug.ends_with('-') {
+                        slug.push('-');
+                }
+        }
+        slug.trim_end_matches('-').to_owned()
+}
+
 /*impl Modify<LetterCase> {
     
 }*/
+
+/// Replaces the file stem while keeping the extension, dots inside the stem are preserved.
+/// A stem containing `/` moves the file into sub folders relative to its current folder.
+pub fn set_stem(path: &mut PathBuf, stem: String, extension: Option<OsString>) {
+        let mut folders: Vec<&str> = stem
+                .split('/')
+                .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+                .collect();
+        let mut file_name = OsString::from(folders.pop().unwrap_or_default());
+        if let Some(ext) = extension {
+                file_name.push(".");
+                file_name.push(ext);
+        }
+        path.pop();
+        for folder in folders {
+                path.push(folder);
+        }
+        path.push(file_name);
+}
+
+/// Why `after`, the result of a step run on `before`, cannot be used: a step that leaves no
+/// stem would hide the file as `.ext`, or name it after its folder.
+pub fn empty_stem_error(before: &Path, after: &Path) -> Option<String> {
+        let is_hidden = |path: &Path| path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if after.as_os_str().to_string_lossy().ends_with(std::path::is_separator) || after.file_name().is_none() {
+                return Some(String::from("the new name is empty"));
+        }
+        if is_hidden(after) && !is_hidden(before) {
+                let name = after.file_name().unwrap_or_default().to_string_lossy();
+                return Some(format!("the new name `{}` has no stem", name));
+        }
+        None
+}
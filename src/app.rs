@@ -1,30 +1,84 @@
 use iced::event::{self, Event};
 use iced::keyboard::{self, key};
+use iced::window;
 use iced::Length::FillPortion;
 use iced::font;
 use iced::wgpu::naga::back::hlsl::Options;
 use iced::widget::{operation};
-use iced::widget::{container, rule, scrollable, space};
-use iced::widget::{button, column, pick_list, row, table, text, text_input, toggler, tooltip};
+use iced::widget::{container, rule, scrollable, space, stack};
+use iced::widget::{button, checkbox, column, mouse_area, pick_list, progress_bar, row, table, text, text_editor, text_input, toggler, tooltip};
 use iced::{Center,Element,Fill,Font, Padding, Task, Theme, Renderer,Subscription};
-use std::path::PathBuf;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::fs;
-use tokio::task::JoinSet;
 use rfd::{AsyncFileDialog, MessageDialogResult};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 
-use crate::action::{Action,Modify, Replace, StateValue};
+use crate::companion::{self, Companion, CompanionRules};
+use crate::condition::{Condition, ConditionKind};
+use crate::duplicate::{self, Duplicate, DuplicateHandling};
+use crate::action::{CleanupOptions,CommandOptions,LengthUnit, NormalForm, NumberPadding, NumberTarget, RenumberOptions, SpaceConversion, TruncateOptions, UnicodeOptions, INVALID_CHARS};
+use crate::mapping::{self, Mapping};
+use crate::hash::{self, HashAlgorithm, HashCache, HashFailures, CachedHash, HashEvent};
+use crate::metadata::{self, FileMeta, TimeField, TimeZoneChoice};
+use crate::pipeline::{Pipeline, Step};
+use crate::plugin::{self, Field};
+use crate::preview::{self, PreviewCache, PreviewJob, PreviewOutput, PreviewRow};
+use crate::filesystem::{self, RealFs};
+use crate::plan::{self, ApplyEvent, ApplyOptions, ApplyReport, ScriptKind};
+use crate::sort::{self, SortKey};
+use crate::token;
+use crate::validate::{self, TargetProfile};
+use crate::watch::{self, WatchEvent};
 
 
 
 #[derive(Debug, Clone)]
 pub enum Message {
     AddAction,
-    UpdateAction(usize,ActionOptions),
+    UpdateAction(usize,ActionChoice),
     RemoveAction(usize),
     ApplyChange,
-    ChangeApplied(Vec<RenameError>),
+    ConfirmApply(bool),
+    Apply(ApplyEvent),
+    CancelApply,
+    DryRun,
+    ReportClosed(bool),
     PatternChange(usize,String),
     TextChange(usize,String),
+    UnicodeChange(usize,UnicodeOptions),
+    ProfileChange(usize,TargetProfile),
+    CleanupChange(usize,CleanupOptions),
+    TruncateChange(usize,TruncateOptions),
+    RenumberChange(usize,RenumberOptions),
+    OpenMappingPicker(usize),
+    /// Carries the id of the step, which may have moved or been removed while the dialog was open.
+    MappingLoaded(u64,Option<Result<Mapping,String>>),
+    ScriptEdit(usize,text_editor::Action),
+    CommandInput(usize,String),
+    CommandSubmit(usize),
+    CommandChange(usize,CommandOptions),
+    PluginFieldChange(usize,usize,String),
+    PreviewDue(u64),
+    PreviewDone(u64,Option<PreviewOutput>),
+    PreviewFinished(Option<PreviewOutput>,AfterPreview),
+    ShowPage(usize),
+    ExportPreview,
+    ExportScript,
+    SavePipeline,
+    LoadPipeline,
+    PipelineLoaded(Option<Result<Pipeline,String>>),
+    ToggleWatch(bool),
+    WatchFolderSelected(Option<PathBuf>),
+    Watch(WatchEvent),
+    Exported(Option<Result<(),String>>),
+    ConditionChange(usize,ConditionKind,String,bool),
+    TargetProfileChange(TargetProfile),
+    Hash(HashEvent),
     OpenFolderPicker,
     FolderSelected(Option<PathBuf>),
     OpenMultiPicker,
@@ -32,32 +86,233 @@ pub enum Message {
     PopulateTable(Vec<PathBuf>),
     Preview,
     TogglePreview(bool),
+    ToggleRemoveEmptyFolders(bool),
+    ToggleVerifyMoves(bool),
+    ToggleCompanions(bool),
+    ToggleDuplicates(bool),
+    DuplicateHandlingChange(DuplicateHandling),
+    CompanionExtensionsChange(String),
+    CompanionRulesChange(String),
+    ToggleColumn(MetaColumn, bool),
+    TimeZoneChange(String),
+    SortBy(SortKey, bool),
+    DragStart(usize),
+    DragOver(usize),
+    DragEnd,
     Event(Event),
+    DropFinished,
+    OpenAddFiles,
+    OpenAddFolder,
+    FilesAdded(Option<Vec<PathBuf>>),
+    FolderAdded(Option<PathBuf>),
+    SelectRow(usize, bool),
+    SelectAll(bool),
+    RemoveSelected,
+    ClearList,
+    /// Entries read in the background for the list of the given generation.
+    EntriesRead(u64, Vec<FileEntry>),
+    ExifRead(Vec<(PathBuf, Option<chrono::NaiveDateTime>)>),
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Default)]
+/// What runs once the names it needs are previewed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AfterPreview {
+        Apply,
+        DryRun,
+        ExportPreview,
+        ExportScript,
+}
+
+/// Optional metadata columns of the file table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetaColumn {
+        Modified,
+        Created,
+        Accessed,
+        Size,
+        Owner,
+}
+
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+        pub old_path: PathBuf,
+        /// `old_path` with links and `..` resolved, so the same file is only listed once.
+        pub canonical: PathBuf,
+        pub new_path: PathBuf,
+        pub meta: Option<FileMeta>,
+        /// Set when the file follows another file's new stem.
+        pub companion: Option<Companion>,
+        /// Violations of the target file system rules for the new name, and step errors.
+        pub problems: Vec<String>,
+        /// Steps that failed on this file, which then kept the name it had before the step.
+        pub errors: Vec<String>,
+        /// Set when another loaded file has the same content.
+        pub duplicate: Option<Duplicate>,
+        /// Checked in the table, for removing rows.
+        pub selected: bool,
+        /// Date the photo was taken, read in the background when sorting by EXIF date.
+        pub exif_date: Option<chrono::NaiveDateTime>,
+        /// Set once `exif_date` is being read, files without EXIF data keep `None`.
+        pub exif_read: bool,
+}
+
+impl FileEntry {
+        /// Reads the metadata of `path`, which blocks, see `read_entries`.
+        pub(crate) fn new(path: PathBuf) -> Self {
+                Self {
+                        meta: FileMeta::read(&path),
+                        canonical: std::fs::canonicalize(&path).unwrap_or_else(|_| path.clone()),
+                        companion: None,
+                        problems: Vec::new(),
+                        errors: Vec::new(),
+                        duplicate: None,
+                        selected: false,
+                        exif_date: None,
+                        exif_read: false,
+                        old_path: path.clone(),
+                        new_path: path,
+                }
+        }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
 pub enum ActionOptions {
         #[default]
         MatchAndReplace,
         RegexReplace,
         Prefix,
         Suffix,
+        Template,
+        Unicode,
+        MakeSafe,
+        Cleanup,
+        Truncate,
+        Renumber,
+        Import,
+        Script,
+        Command,
+        /// Action type from the plugin registry, see `TextState::plugin`.
+        Plugin,
         //UpperCase,
         //LowerCase,
 }
 
+/// Entry of the action picker.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionChoice {
+        Builtin(ActionOptions),
+        Plugin { id: String, name: String },
+}
+
+impl std::fmt::Display for ActionChoice {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                        ActionChoice::Builtin(action) => action.fmt(f),
+                        ActionChoice::Plugin { name, .. } => f.write_str(name),
+                }
+        }
+}
+
 pub struct TextState {
+        /// Stays the same while steps are added and removed, for results of dialogs.
+        id: u64,
         pattern: String,
         value: String,
+        unicode: UnicodeOptions,
+        profile: TargetProfile,
+        cleanup: CleanupOptions,
+        truncate: TruncateOptions,
+        renumber: RenumberOptions,
+        mapping: Mapping,
+        mapping_error: Option<String>,
+        /// Editor of a script step, its text is kept in `value`.
+        script: text_editor::Content,
+        command: CommandOptions,
+        /// Command line last run with Enter, `value` holds what is being typed.
+        submitted_command: String,
+        plugin: String,
+        fields: Vec<String>,
+        condition: Condition,
         action_option: Option<ActionOptions>,
+        /// Errors of the step built from the current settings.
+        validation_errors: Vec<String>,
+}
+
+impl TextState {
+        fn step(&self) -> Step {
+                let action = self.action_option.unwrap_or_default();
+                Step {
+                        action,
+                        pattern: self.pattern.clone(),
+                        value: if action == ActionOptions::Command { self.submitted_command.clone() } else { self.value.clone() },
+                        unicode: self.unicode,
+                        profile: self.profile,
+                        cleanup: self.cleanup,
+                        truncate: self.truncate,
+                        renumber: self.renumber,
+                        mapping: self.mapping.rows.clone(),
+                        command: self.command,
+                        plugin: self.plugin.clone(),
+                        fields: self.fields.clone(),
+                        condition: self.condition.kind,
+                        condition_input: self.condition.input.clone(),
+                        condition_negate: self.condition.negate,
+                }
+        }
+
+        /// A loaded command step shows its command but only runs it after Enter.
+        fn from_step(step: &Step) -> TextState {
+                let mut text_state = TextState {
+                        id: 0,
+                        pattern: step.pattern.clone(),
+                        value: step.value.clone(),
+                        unicode: step.unicode,
+                        profile: step.profile,
+                        cleanup: step.cleanup,
+                        truncate: step.truncate,
+                        renumber: step.renumber,
+                        mapping: Mapping::new(step.mapping.clone()),
+                        mapping_error: None,
+                        script: text_editor::Content::with_text(&step.value),
+                        command: step.command,
+                        submitted_command: String::new(),
+                        plugin: step.plugin.clone(),
+                        fields: step.fields.clone(),
+                        condition: step.condition(),
+                        action_option: Some(step.action),
+                        validation_errors: Vec::new(),
+                };
+                text_state.validation_errors = text_state.step().build().validation_errors();
+                text_state
+        }
+
+        /// The command text changed since it last ran.
+        fn command_edited(&self) -> bool {
+                self.value != self.submitted_command
+        }
 }
 
 impl Default for TextState {
     fn default() -> Self {
         Self {
+            id: 0,
             pattern: String::new(),
             value: String::new(),
+            unicode: UnicodeOptions::default(),
+            profile: TargetProfile::default(),
+            cleanup: CleanupOptions::default(),
+            truncate: TruncateOptions::default(),
+            renumber: RenumberOptions::default(),
+            mapping: Mapping::default(),
+            mapping_error: None,
+            script: text_editor::Content::new(),
+            command: CommandOptions::default(),
+            submitted_command: String::new(),
+            plugin: String::new(),
+            fields: Vec::new(),
+            condition: Condition::default(),
             action_option: Some(ActionOptions::default()),
+            validation_errors: Vec::new(),
         }
     }
 }
@@ -66,20 +321,142 @@ impl Default for TextState {
 pub struct FileRenamerApp {
         //current_file_names: Vec<String>,
         //modified_file_names: Vec<String>,
-        //current , modified
-        file_names: Vec<(PathBuf,PathBuf)>,
+        file_names: Vec<FileEntry>,
         texts_state: Vec<TextState>,
         live_preview: bool,
-        actions: Vec<Box<dyn Action>>,
+        remove_empty_folders: bool,
+        /// Hash files copied to another drive before deleting the original.
+        verify_moves: bool,
+        group_companions: bool,
+        companion_extensions: String,
+        companion_rules: String,
+        companion_error: Option<String>,
+        meta_columns: Vec<MetaColumn>,
+        time_zone_input: String,
+        time_zone: Option<TimeZoneChoice>,
+        target_profile: TargetProfile,
+        hash_cache: HashCache,
+        hash_failures: HashFailures,
+        hashing: Option<HashProgress>,
+        applying: Option<ApplyProgress>,
+        /// `(old, new, status)` rows of the last applied plan, for saving as CSV.
+        report_rows: Vec<(PathBuf, PathBuf, String)>,
+        find_duplicates: bool,
+        duplicate_handling: DuplicateHandling,
+        sort_key: SortKey,
+        sort_descending: bool,
+        /// Row being dragged in manual order.
+        dragging: Option<usize>,
+        /// Folder watched for new files and the flag that stops the watcher.
+        watching: Option<(PathBuf, Arc<AtomicBool>)>,
+        watch_log: Vec<String>,
+        /// Set by `preview_new_filename`, the preview starts once edits pause.
+        preview_requested: bool,
+        /// Number of the latest preview request, older runs are dropped.
+        preview_generation: u64,
+        /// Bumped whenever the list is replaced, entries read for an older list are dropped.
+        list_generation: u64,
+        /// Last id given to a step.
+        step_id: u64,
+        preview_run: Option<PreviewRun>,
+        preview_cache: Option<Arc<PreviewCache>>,
+        preview_rows: Arc<Vec<PreviewRow>>,
+        /// Bumped whenever `hash_cache` changes, `hash_snapshot` is the copy sent to the preview.
+        hash_version: u64,
+        hash_snapshot: Option<Arc<HashCache>>,
+        /// Page of the file table.
+        page: usize,
+        /// Files are being dragged over the window.
+        drop_hover: bool,
+        /// Paths of the current drop, collected until the last one arrived.
+        dropped: Vec<PathBuf>,
+        /// Bumped when files start hovering the window, every path dropped until the next
+        /// hover belongs to the same drop, even when it arrives after `DROP_SETTLE`.
+        drop_gesture: u64,
+        /// Drop whose folders replaced the list, later paths of that drop are added to it.
+        replaced_by_drop: Option<u64>,
+}
+
+/// Preview computed in the background.
+struct PreviewRun {
+        cancel: Arc<AtomicBool>,
+        handle: iced::task::Handle,
+}
+
+/// Background hashing of the loaded files for hash tokens and the duplicate finder.
+struct HashProgress {
+        done: usize,
+        total: usize,
+        handle: iced::task::Handle,
+}
+
+/// Renames running in the background.
+struct ApplyProgress {
+        done: usize,
+        total: usize,
+        current: Option<PathBuf>,
+        started: std::time::Instant,
+        cancel: Arc<AtomicBool>,
+        dry_run: bool,
 }
 
+/// Pause in editing before the preview runs.
+const PREVIEW_DEBOUNCE: std::time::Duration = std::time::Duration::from_millis(150);
+
+/// Wait for the rest of the paths of a drop.
+const DROP_SETTLE: std::time::Duration = std::time::Duration::from_millis(50);
+
+/// Rows of the file table shown at once.
+const PAGE_SIZE: usize = 500;
+
+/// Lines of the watch log kept on screen.
+const WATCH_LOG_LINES: usize = 8;
+
+/// Fast hash used to compare file contents.
+const DUPLICATE_HASH: HashAlgorithm = HashAlgorithm::Blake3;
+
 impl Default for FileRenamerApp {
     fn default() -> Self {
         Self {
             file_names: Vec::new(),
             texts_state: Vec::new(),
             live_preview: true,
-            actions: Vec::new(),
+            remove_empty_folders: false,
+            verify_moves: false,
+            group_companions: false,
+            companion_extensions: String::from(companion::DEFAULT_PRIMARY_EXTENSIONS),
+            companion_rules: String::from(companion::DEFAULT_RULES),
+            companion_error: None,
+            meta_columns: Vec::new(),
+            time_zone_input: String::from("Local"),
+            time_zone: Some(TimeZoneChoice::Local),
+            target_profile: TargetProfile::default(),
+            hash_cache: HashCache::new(),
+            hash_failures: HashFailures::new(),
+            hashing: None,
+            applying: None,
+            report_rows: Vec::new(),
+            find_duplicates: false,
+            duplicate_handling: DuplicateHandling::default(),
+            sort_key: SortKey::default(),
+            sort_descending: false,
+            dragging: None,
+            watching: None,
+            watch_log: Vec::new(),
+            preview_requested: false,
+            preview_generation: 0,
+            list_generation: 0,
+            step_id: 0,
+            preview_run: None,
+            preview_cache: None,
+            preview_rows: Arc::new(Vec::new()),
+            hash_version: 0,
+            hash_snapshot: None,
+            page: 0,
+            drop_hover: false,
+            dropped: Vec::new(),
+            drop_gesture: 0,
+            replaced_by_drop: None,
         }
     }
 }
@@ -93,7 +470,13 @@ impl FileRenamerApp {
                 let mut content = column![
                 row![button("Choose Files").on_press(Message::OpenMultiPicker),
                 button("Choose Folder").on_press(Message::OpenFolderPicker),
-                button("Add").on_press(Message::AddAction)].spacing(10)
+                button("Add Step").on_press(Message::AddAction),
+                button("Load Pipeline").on_press(Message::LoadPipeline),
+                button("Save Pipeline").on_press(Message::SavePipeline)].spacing(10),
+                self.list_ui(),
+                self.companion_ui(),
+                self.duplicate_ui(),
+                self.watch_ui()
                 ]
                 .spacing(20)
                 .padding(20).align_x(Center);
@@ -106,119 +489,354 @@ impl FileRenamerApp {
                 content = content.push(scrollable(scrollable_content).height(Fill));
                 content = content.push(rule::horizontal(1.0));
                 //scrollable(content).width(FillPortion(1)).into()
-                content = content.push(row![space::horizontal(),
+                let hash_status = match &self.hashing {
+                        Some(progress) => text!("Hashing {}/{}", progress.done, progress.total),
+                        None => text(""),
+                };
+                if let Some(progress) = &self.applying {
+                        content = content.push(self.apply_progress_ui(progress));
+                }
+                content = content.push(row![hash_status, space::horizontal(),
+                tooltip(
+                        toggler(self.remove_empty_folders).label("Remove empty folders").on_toggle(Message::ToggleRemoveEmptyFolders),
+                        "Delete source folders left empty after files are moved",
+                        tooltip::Position::Top
+                ),
+                tooltip(
+                        toggler(self.verify_moves).label("Verify moves").on_toggle(Message::ToggleVerifyMoves),
+                        "Compare content hashes of files copied to another drive before deleting the originals",
+                        tooltip::Position::Top
+                ),
                 tooltip(
                         toggler(self.live_preview).label("Live Preview").on_toggle(Message::TogglePreview),
-                        "Turn off live preview if its laggy for large amount of files",
+                        "Turn off live preview to only update names when Preview is pressed",
                         tooltip::Position::Top
                 ),
                 if self.live_preview {button(text("Preview").align_x(Center))
                         } else {
                         button(text("Preview").align_x(Center))
                         .on_press(Message::Preview)},
+                button(text("Export CSV").align_x(Center))
+                .on_press(Message::ExportPreview),
+                tooltip(
+                        button(text("Export Script").align_x(Center)).on_press(Message::ExportScript),
+                        "Save the renames as a .sh or .ps1 script, with an undo script next to it",
+                        tooltip::Position::Top
+                ),
+                tooltip(
+                        button(text("Dry Run").align_x(Center)).on_press_maybe(self.can_apply().then_some(Message::DryRun)),
+                        "Run the renames against a copy of the folders in memory and report what would fail",
+                        tooltip::Position::Top
+                ),
                 button(text("Apply").align_x(Center))
-                .on_press(Message::ApplyChange)
+                .on_press_maybe(self.can_apply().then_some(Message::ApplyChange))
                 .width(80)].spacing(10).align_y(Center));
 
+                let files: Element<'_, Message> = if self.drop_hover {
+                        stack![scrollable(self.display_ui()), self.drop_target_ui()].into()
+                } else {
+                        scrollable(self.display_ui()).into()
+                };
                 row! [
                 content.width(FillPortion(8)),
-                scrollable(self.display_ui()).width(FillPortion(13))]
+                container(files).width(FillPortion(13))]
                 .into()
                 //scrollable(self.display_ui()).width(Fill).into()
 
                 //content.into()
     } 
 
-        pub fn update(&mut self, message: Message) -> Task<Message>{
+        pub fn update(&mut self, message: Message) -> Task<Message> {
+                let task = self.handle_message(message);
+                if !std::mem::take(&mut self.preview_requested) {
+                        return task;
+                }
+                // Every edit restarts the wait, only the last one starts a run
+                self.preview_generation += 1;
+                let generation = self.preview_generation;
+                let due = Task::perform(tokio::time::sleep(PREVIEW_DEBOUNCE), move |_| Message::PreviewDue(generation));
+                Task::batch([task, due])
+        }
+
+        /// The listed files, in table order.
+        pub(crate) fn files(&self) -> &[FileEntry] {
+                &self.file_names
+        }
+
+        fn handle_message(&mut self, message: Message) -> Task<Message>{
                 match message {
                         Message::AddAction => {
-                                self.texts_state.push(TextState::default());
-                                self.actions.push(Box::new(Modify::new_op()));
+                                self.step_id += 1;
+                                self.texts_state.push(TextState { id: self.step_id, ..Default::default() });
+                                self.step_changed(self.texts_state.len() - 1);
                                 Task::none()
                         },
-                        Message::UpdateAction(i,selected_action) =>{
-                                self.texts_state[i].action_option = Some(selected_action);
-                                let new_action: Box<dyn Action>;
-
-                                match self.texts_state[i].action_option {
-                                        Some(ActionOptions::MatchAndReplace) => {
-                                                let mut action = Modify::new_op();
-                                                action
-                                                .set_pattern(&self.texts_state[i].pattern)
-                                                .find_and_replace_op(&self.texts_state[i].value);
-                                                new_action = Box::new(action);
-                                        },
-                                        Some(ActionOptions::RegexReplace) => {
-                                                let mut action = Modify::new_op();
-                                                action
-                                                .set_pattern(&self.texts_state[i].pattern)
-                                                .regex_op(&self.texts_state[i].value);
-                                                new_action = Box::new(action);
-                                        },
-                                        Some(ActionOptions::Prefix) => {
-                                                let mut action = Modify::new_affix();
-                                                action.prefix_mode(&self.texts_state[i].value);
-                                                new_action = Box::new(action);
+                        Message::UpdateAction(i,choice) =>{
+                                let selected_action = match choice {
+                                        ActionChoice::Builtin(action) => action,
+                                        ActionChoice::Plugin { id, .. } => {
+                                                self.texts_state[i].fields = plugin::find(&id)
+                                                        .map(|plugin| plugin.fields().iter().map(Field::default_value).collect())
+                                                        .unwrap_or_default();
+                                                self.texts_state[i].plugin = id;
+                                                ActionOptions::Plugin
                                         },
-                                        Some(ActionOptions::Suffix) => {
-                                                let mut action = Modify::new_affix();
-                                                action.suffix_mode(&self.texts_state[i].value);
-                                                new_action = Box::new(action);
-                                        },
-                                        _ =>{ panic!("Should never have None ActionOptions");}
+                                };
+                                self.texts_state[i].action_option = Some(selected_action);
+                                if selected_action == ActionOptions::MakeSafe {
+                                        self.texts_state[i].profile = self.target_profile;
+                                }
+                                if selected_action == ActionOptions::Command {
+                                        // Never run text typed for another action as a command
+                                        self.texts_state[i].value.clear();
+                                        self.texts_state[i].submitted_command.clear();
+                                }
+                                if selected_action == ActionOptions::Script {
+                                        self.texts_state[i].script = text_editor::Content::with_text(&self.texts_state[i].value);
                                 }
-                                self.actions[i] = new_action;
+                                self.step_changed(i);
                                 if self.live_preview {self.preview_new_filename()};
-                                Task::none()
+                                self.ensure_hashes()
                         },
                         Message::RemoveAction(i) => {
                                 self.texts_state.remove(i);
-                                self.actions.remove(i);
                                 if self.live_preview{self.preview_new_filename()};
                                 Task::none()
                         },
-                        Message::ApplyChange => {
-                                self.preview_new_filename();
-                                Task::perform(rename_files(self.file_names.clone()), Message::ChangeApplied)
+                        Message::ApplyChange if !self.can_apply() => Task::none(),
+                        Message::ApplyChange => self.finish_preview(AfterPreview::Apply),
+                        Message::ConfirmApply(confirmed) => {
+                                if !confirmed {
+                                        return Task::none();
+                                }
+                                self.start_apply(false)
                         },
-                        Message::ChangeApplied(rename_error) => {
-                                for (old, new) in self.file_names.iter_mut() {
-                                        *old = new.clone();
+                        Message::DryRun if !self.can_apply() => Task::none(),
+                        Message::DryRun => self.finish_preview(AfterPreview::DryRun),
+                        Message::Apply(ApplyEvent::Progress { done, total, current }) => {
+                                if let Some(progress) = &mut self.applying {
+                                        progress.done = done;
+                                        progress.total = total;
+                                        progress.current = Some(current);
                                 }
-                                Task::future(ok_dialog()).discard()
-                        }
-                        Message::PatternChange(i,pattern) => {
-                                self.texts_state[i].pattern = pattern.clone();
-                                let value = self.texts_state[i].value.clone();
-
-                                match self.texts_state[i].action_option {
-                                        Some(ActionOptions::MatchAndReplace) | Some(ActionOptions::RegexReplace) => {
-                                                self.actions[i].update_values(
-                                                        StateValue::ReplaceValue(pattern,value));
-                                                },
-                                                _ =>{}
+                                Task::none()
+                        },
+                        Message::Apply(ApplyEvent::Finished(report)) => {
+                                self.applying = None;
+                                let summary = report_summary(&report);
+                                self.report_rows = self.report_rows(&report);
+                                if report.dry_run {
+                                        return Task::perform(report_dialog(summary), Message::ReportClosed);
+                                }
+                                // Files stuck under a temporary name are listed where they are
+                                let renamed: HashMap<&Path, &Path> = report.renamed.iter().chain(&report.moved).chain(&report.stranded)
+                                        .map(|(old, new)| (old.as_path(), new.as_path()))
+                                        .collect();
+                                for entry in self.file_names.iter_mut() {
+                                        let Some(new_path) = renamed.get(entry.old_path.as_path()) else { continue; };
+                                        // Renaming keeps the content, so the hashes move with the file
+                                        for algorithm in HashAlgorithm::ALL {
+                                                if let Some(cached) = self.hash_cache.remove(&(entry.old_path.clone(), algorithm)) {
+                                                        self.hash_cache.insert((new_path.to_path_buf(), algorithm), cached);
+                                                }
                                         }
-                                        if self.live_preview{self.preview_new_filename()};
+                                        entry.canonical = moved_canonical(entry, new_path);
+                                        entry.old_path = new_path.to_path_buf();
+                                }
+                                self.hashes_changed();
+                                self.group_companions();
+                                self.find_duplicate_groups();
+                                if self.live_preview {self.preview_new_filename()};
+                                Task::batch([Task::perform(report_dialog(summary), Message::ReportClosed), self.ensure_hashes()])
+                        },
+                        Message::CancelApply => {
+                                if let Some(progress) = &self.applying {
+                                        progress.cancel.store(true, Ordering::Relaxed);
+                                }
+                                Task::none()
+                        },
+                        Message::ReportClosed(save) => {
+                                if !save {
+                                        return Task::none();
+                                }
+                                let rows: Vec<(&Path, &Path, String)> = self.report_rows.iter()
+                                        .map(|(old, new, status)| (old.as_path(), new.as_path(), status.clone()))
+                                        .collect();
+                                match mapping::export_plan(&rows) {
+                                        Ok(content) => Task::perform(save_csv(content, "rename-report.csv"), Message::Exported),
+                                        Err(error) => Task::future(error_dialog(error)).discard(),
+                                }
+                        },
+                        Message::PatternChange(i,pattern) => {
+                                self.texts_state[i].pattern = pattern;
+                                self.step_changed(i);
+                                if self.live_preview{self.preview_new_filename()};
 
                                 Task::none()
                         },
                         Message::TextChange(i,value) => {
-                                self.texts_state[i].value = self.filter_invalid(value);
-                                let filtered_value = self.texts_state[i].value.clone();
-                                let pattern = self.texts_state[i].pattern.clone();
-
-                                match self.texts_state[i].action_option {
-                                        Some(ActionOptions::MatchAndReplace) | Some(ActionOptions::RegexReplace) => {
-                                                self.actions[i].update_values(
-                                                        StateValue::ReplaceValue(pattern,filtered_value));
-                                                },
-                                        Some(ActionOptions::Prefix) | Some(ActionOptions::Suffix) => {
-                                                self.actions[i].update_values(StateValue::AffixValue(filtered_value));
-                                                },
-                                                _ =>{}
-                                        }
-                                        if self.live_preview{self.preview_new_filename()};
+                                let allow_folders = matches!(self.texts_state[i].action_option,
+                                        Some(ActionOptions::Prefix) | Some(ActionOptions::Template));
+                                // Only templates have capture groups to fall back from
+                                let pattern = match self.texts_state[i].action_option {
+                                        Some(ActionOptions::Template) => self.texts_state[i].pattern.clone(),
+                                        _ => String::new(),
+                                };
+                                self.texts_state[i].value = self.filter_invalid(value, allow_folders, &pattern);
+                                self.step_changed(i);
+                                if self.live_preview{self.preview_new_filename()};
 
+                                self.ensure_hashes()
+                        },
+                        Message::ScriptEdit(i,action) => {
+                                let is_edit = action.is_edit();
+                                self.texts_state[i].script.perform(action);
+                                if is_edit {
+                                        self.texts_state[i].value = self.texts_state[i].script.text();
+                                        self.step_changed(i);
+                                        if self.live_preview {self.preview_new_filename()};
+                                }
+                                Task::none()
+                        },
+                        Message::PluginFieldChange(i,field,value) => {
+                                let fields = &mut self.texts_state[i].fields;
+                                if fields.len() <= field {
+                                        fields.resize(field + 1, String::new());
+                                }
+                                fields[field] = value;
+                                self.step_changed(i);
+                                if self.live_preview {self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::CommandInput(i,command) => {
+                                self.texts_state[i].value = command;
+                                Task::none()
+                        },
+                        Message::CommandSubmit(i) => {
+                                self.texts_state[i].submitted_command = self.texts_state[i].value.clone();
+                                self.step_changed(i);
+                                if self.live_preview {self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::CommandChange(i,options) => {
+                                self.texts_state[i].command = options;
+                                // The step keeps the last submitted command, a half typed one never runs
+                                self.step_changed(i);
+                                if self.live_preview {self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::UnicodeChange(i,options) => {
+                                self.texts_state[i].unicode = options;
+                                self.step_changed(i);
+                                if self.live_preview{self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::ProfileChange(i,profile) => {
+                                self.texts_state[i].profile = profile;
+                                self.step_changed(i);
+                                if self.live_preview{self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::CleanupChange(i,options) => {
+                                self.texts_state[i].cleanup = options;
+                                self.step_changed(i);
+                                if self.live_preview{self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::TruncateChange(i,options) => {
+                                self.texts_state[i].truncate = options;
+                                self.step_changed(i);
+                                if self.live_preview{self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::RenumberChange(i,options) => {
+                                self.texts_state[i].renumber = options;
+                                self.step_changed(i);
+                                if self.live_preview{self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::OpenMappingPicker(i) => {
+                                let id = self.texts_state[i].id;
+                                Task::perform(load_mapping(), move |mapping| Message::MappingLoaded(id, mapping))
+                        },
+                        Message::MappingLoaded(id, Some(result)) => {
+                                let Some(i) = self.texts_state.iter().position(|text_state| text_state.id == id) else {
+                                        return Task::none();
+                                };
+                                match result {
+                                        Ok(mapping) => {
+                                                self.texts_state[i].mapping_error = None;
+                                                self.texts_state[i].mapping = mapping;
+                                                self.step_changed(i);
+                                        },
+                                        Err(error) => self.texts_state[i].mapping_error = Some(error),
+                                }
+                                if self.live_preview{self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::ExportPreview => self.finish_preview(AfterPreview::ExportPreview),
+                        Message::ExportScript => self.finish_preview(AfterPreview::ExportScript),
+                        Message::SavePipeline => {
+                                // Commands are saved as typed, loading asks to confirm them again
+                                let mut pipeline = self.pipeline();
+                                for (step, text_state) in pipeline.steps.iter_mut().zip(&self.texts_state) {
+                                        step.value = text_state.value.clone();
+                                }
+                                Task::perform(save_pipeline(pipeline.to_json()), Message::Exported)
+                        },
+                        Message::LoadPipeline => {
+                                Task::perform(load_pipeline(), Message::PipelineLoaded)
+                        },
+                        Message::PipelineLoaded(Some(Ok(pipeline))) => {
+                                self.texts_state = pipeline.steps.iter().map(TextState::from_step).collect();
+                                for text_state in &mut self.texts_state {
+                                        self.step_id += 1;
+                                        text_state.id = self.step_id;
+                                }
+                                self.time_zone = TimeZoneChoice::parse(&pipeline.time_zone);
+                                self.time_zone_input = pipeline.time_zone;
+                                self.target_profile = pipeline.target_profile;
+                                if self.live_preview {self.preview_new_filename()};
+                                self.ensure_hashes()
+                        },
+                        Message::PipelineLoaded(Some(Err(error))) => {
+                                Task::future(error_dialog(error)).discard()
+                        },
+                        Message::ToggleWatch(true) => {
+                                Task::perform(pick_folder(), Message::WatchFolderSelected)
+                        },
+                        Message::ToggleWatch(false) => {
+                                if let Some((_, stop)) = self.watching.take() {
+                                        stop.store(true, Ordering::Relaxed);
+                                }
+                                Task::none()
+                        },
+                        Message::WatchFolderSelected(Some(folder)) => {
+                                // The pipeline is copied, later edits apply to the next watch
+                                let stop = Arc::new(AtomicBool::new(false));
+                                self.watching = Some((folder.clone(), stop.clone()));
+                                Task::run(watch::watch(folder, self.pipeline(), stop), Message::Watch)
+                        },
+                        Message::Watch(event) => {
+                                let line = format!("{} {}", chrono::Local::now().format("%H:%M:%S"), event);
+                                self.watch_log.push(line);
+                                if self.watch_log.len() > WATCH_LOG_LINES {
+                                        self.watch_log.remove(0);
+                                }
+                                Task::none()
+                        },
+                        Message::Exported(Some(Err(error))) => {
+                                Task::future(error_dialog(error)).discard()
+                        },
+                        Message::ConditionChange(i,kind,input,negate) => {
+                                self.texts_state[i].condition = Condition::new(kind, input, negate);
+                                self.step_changed(i);
+                                if self.live_preview{self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::TargetProfileChange(profile) => {
+                                self.target_profile = profile;
+                                self.validate_names();
                                 Task::none()
                         },
                         Message::OpenFolderPicker => {
@@ -228,12 +846,7 @@ impl FileRenamerApp {
                                 Task::perform(pick_files(), Message::FilesSelected)
                         },
                         Message::FilesSelected(Some(files)) => {
-                                self.file_names = files.into_iter().map(|path_buf| {
-
-                                        (path_buf.clone(),path_buf)
-                                }).collect();
-
-                                Task::none()
+                                self.load_files(files)
                         },
                         Message::FolderSelected(path) => {
                                 if let Some(dir_path) = path {
@@ -242,19 +855,226 @@ impl FileRenamerApp {
                                         Task::none()
                                 }
                         },
-                        Message::PopulateTable(file_names)=> {                      
-                                self.file_names = file_names.into_iter().map(|path_buf| {                                       
-                                        (path_buf.clone(), path_buf)
-                                }).collect();
-                                
+                        Message::PopulateTable(file_names)=> {
+                                self.load_files(file_names)
+                        },
+                        Message::DropFinished => {
+                                self.drop_hover = false;
+                                let (folders, files): (Vec<PathBuf>, Vec<PathBuf>) = std::mem::take(&mut self.dropped)
+                                        .into_iter()
+                                        .partition(|path| path.is_dir());
+                                if folders.is_empty() {
+                                        return self.add_files(files);
+                                }
+                                // A folder loads like Choose Folder, loose files and late paths of the same drop are added to it
+                                let mut cleared = Task::none();
+                                if self.replaced_by_drop != Some(self.drop_gesture) {
+                                        self.replaced_by_drop = Some(self.drop_gesture);
+                                        cleared = self.load_files(Vec::new());
+                                }
+                                let generation = self.list_generation;
+                                let read = async move {
+                                        let mut paths = read_folders(folders).await;
+                                        paths.extend(files);
+                                        read_entries(paths).await
+                                };
+                                Task::batch([cleared, Task::perform(read, move |entries| Message::EntriesRead(generation, entries))])
+                        },
+                        Message::OpenAddFiles => {
+                                Task::perform(pick_files(), Message::FilesAdded)
+                        },
+                        Message::OpenAddFolder => {
+                                Task::perform(pick_folder(), Message::FolderAdded)
+                        },
+                        Message::FilesAdded(files) => {
+                                self.add_files(files.unwrap_or_default())
+                        },
+                        Message::FolderAdded(Some(folder)) => {
+                                Task::perform(read_files_from_folder(folder), |files| Message::FilesAdded(Some(files)))
+                        },
+                        Message::SelectRow(i, selected) => {
+                                if let Some(entry) = self.file_names.get_mut(i) {
+                                        entry.selected = selected;
+                                }
+                                Task::none()
+                        },
+                        Message::SelectAll(selected) => {
+                                for entry in &mut self.file_names {
+                                        entry.selected = selected;
+                                }
                                 Task::none()
                         },
+                        Message::RemoveSelected => {
+                                self.file_names.retain(|entry| !entry.selected);
+                                self.list_changed()
+                        },
+                        Message::ClearList => {
+                                self.load_files(Vec::new())
+                        },
+                        Message::EntriesRead(generation, entries) => {
+                                if generation != self.list_generation || self.push_entries(entries) == 0 {
+                                        return Task::none();
+                                }
+                                self.list_changed()
+                        },
                         Message::Preview => {
                                 self.preview_new_filename(); Task::none()
                         },
+                        Message::PreviewDue(generation) => {
+                                if generation != self.preview_generation {
+                                        return Task::none();
+                                }
+                                self.stop_preview_run();
+                                let job = self.preview_job();
+                                let cancel = Arc::new(AtomicBool::new(false));
+                                let (task, handle) = Task::perform(run_preview(job, cancel.clone()),
+                                        move |output| Message::PreviewDone(generation, output))
+                                        .abortable();
+                                self.preview_run = Some(PreviewRun { cancel, handle });
+                                task
+                        },
+                        Message::PreviewFinished(output, after) => {
+                                // The list changed while the names were computed, they are not applied
+                                if !output.is_some_and(|output| self.show_preview(output)) {
+                                        return Task::none();
+                                }
+                                match after {
+                                        AfterPreview::Apply => self.review_apply(),
+                                        AfterPreview::DryRun => self.start_apply(true),
+                                        AfterPreview::ExportPreview => self.export_preview(),
+                                        AfterPreview::ExportScript => self.export_script(),
+                                }
+                        },
+                        Message::PreviewDone(generation, output) => {
+                                if generation == self.preview_generation {
+                                        self.preview_run = None;
+                                        if let Some(output) = output {
+                                                self.show_preview(output);
+                                        }
+                                }
+                                Task::none()
+                        },
+                        Message::ShowPage(page) => {
+                                self.page = page;
+                                Task::none()
+                        },
+                        Message::Hash(HashEvent::Hashed { path, algorithm, modified, digest }) => {
+                                match digest {
+                                        Ok(digest) => {
+                                                // The preview picks up new digests once the job is done
+                                                self.hash_cache.insert((path, algorithm), CachedHash { modified, digest });
+                                        },
+                                        Err(error) => {
+                                                self.hash_failures.insert((path, algorithm), (modified, error));
+                                                self.add_hash_errors();
+                                        },
+                                }
+                                if let Some(progress) = &mut self.hashing {
+                                        progress.done += 1;
+                                }
+                                Task::none()
+                        },
+                        Message::Hash(HashEvent::Finished) => {
+                                self.hashing = None;
+                                self.hashes_changed();
+                                self.find_duplicate_groups();
+                                if self.live_preview {self.preview_new_filename()};
+                                // Tokens may have changed while hashing
+                                self.ensure_hashes()
+                        },
                         Message::TogglePreview(is_on) => {
                                 self.live_preview = is_on; Task::none()
                         },
+                        Message::ToggleRemoveEmptyFolders(is_on) => {
+                                self.remove_empty_folders = is_on; Task::none()
+                        },
+                        Message::ToggleVerifyMoves(is_on) => {
+                                self.verify_moves = is_on; Task::none()
+                        },
+                        Message::ToggleCompanions(is_on) => {
+                                self.group_companions = is_on;
+                                self.group_companions();
+                                self.find_duplicate_groups();
+                                if self.live_preview {self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::ToggleDuplicates(is_on) => {
+                                self.find_duplicates = is_on;
+                                self.find_duplicate_groups();
+                                if self.live_preview {self.preview_new_filename()};
+                                self.ensure_hashes()
+                        },
+                        Message::DuplicateHandlingChange(handling) => {
+                                self.duplicate_handling = handling;
+                                if self.live_preview {self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::CompanionExtensionsChange(input) => {
+                                self.companion_extensions = input;
+                                self.group_companions();
+                                self.find_duplicate_groups();
+                                if self.live_preview {self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::CompanionRulesChange(input) => {
+                                self.companion_rules = input;
+                                self.group_companions();
+                                self.find_duplicate_groups();
+                                if self.live_preview {self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::ToggleColumn(column, is_on) => {
+                                self.meta_columns.retain(|c| *c != column);
+                                if is_on {self.meta_columns.push(column);}
+                                Task::none()
+                        },
+                        Message::TimeZoneChange(input) => {
+                                self.time_zone = TimeZoneChoice::parse(&input);
+                                self.time_zone_input = input;
+                                if self.live_preview && self.time_zone.is_some() {self.preview_new_filename()};
+                                Task::none()
+                        },
+                        Message::SortBy(key, descending) => {
+                                self.sort_key = key;
+                                self.sort_descending = descending;
+                                self.sort_files();
+                                self.find_duplicate_groups();
+                                if self.live_preview {self.preview_new_filename()};
+                                self.read_exif_dates()
+                        },
+                        Message::ExifRead(dates) => {
+                                let dates: HashMap<PathBuf, Option<chrono::NaiveDateTime>> = dates.into_iter().collect();
+                                for entry in &mut self.file_names {
+                                        if let Some(date) = dates.get(&entry.old_path) {
+                                                entry.exif_date = *date;
+                                        }
+                                }
+                                if self.sort_key == SortKey::ExifDate {
+                                        self.sort_files();
+                                        self.find_duplicate_groups();
+                                        if self.live_preview {self.preview_new_filename()};
+                                }
+                                Task::none()
+                        },
+                        Message::DragStart(i) => {
+                                if self.sort_key == SortKey::Manual {
+                                        self.dragging = Some(i);
+                                }
+                                Task::none()
+                        },
+                        Message::DragOver(i) => {
+                                if let Some(from) = self.dragging {
+                                        self.dragging = Some(self.move_row(from, i));
+                                }
+                                Task::none()
+                        },
+                        Message::DragEnd => {
+                                if self.dragging.take().is_some() {
+                                        self.find_duplicate_groups();
+                                        if self.live_preview {self.preview_new_filename()};
+                                }
+                                Task::none()
+                        },
                         Message::Event(event) => match event {
                                 Event::Keyboard(keyboard::Event::KeyPressed {
                                         key: keyboard::Key::Named(key::Named::Tab),
@@ -266,77 +1086,808 @@ impl FileRenamerApp {
                                         } else {
                                                 operation::focus_next()
                                         }}
+                                Event::Mouse(iced::mouse::Event::ButtonReleased(iced::mouse::Button::Left)) if self.dragging.is_some() => {
+                                        Task::done(Message::DragEnd)
+                                }
+                                Event::Window(window::Event::FileHovered(_)) => {
+                                        if !self.drop_hover {
+                                                self.drop_gesture += 1;
+                                        }
+                                        self.drop_hover = true;
+                                        Task::none()
+                                }
+                                Event::Window(window::Event::FilesHoveredLeft) => {
+                                        self.drop_hover = false;
+                                        Task::none()
+                                }
+                                Event::Window(window::Event::FileDropped(path)) => {
+                                        // Every file of a drop comes as its own event, they are added together
+                                        self.dropped.push(path);
+                                        if self.dropped.len() > 1 {
+                                                return Task::none();
+                                        }
+                                        Task::perform(tokio::time::sleep(DROP_SETTLE), |_| Message::DropFinished)
+                                }
                                         _ => Task::none()
                         },
                         _ => Task::none()
                 }
         }
 
-        fn preview_new_filename(&mut self) {
-                for (old_path, new_path) in self.file_names.iter_mut() {
-                        *new_path = old_path.clone();
-                        for renamer in &mut self.actions {
-                                *new_path = renamer.action(new_path);
-                                }
+        /// Replaces the list with `files`, which show up once their metadata is read.
+        fn load_files(&mut self, files: Vec<PathBuf>) -> Task<Message> {
+                self.cancel_hashing();
+                self.file_names.clear();
+                self.page = 0;
+                self.list_generation += 1;
+                let task = self.list_changed();
+                Task::batch([task, self.add_files(files)])
+        }
 
+        /// Adds `files` to the list once their metadata is read, skipping files that are already in it.
+        fn add_files(&mut self, files: Vec<PathBuf>) -> Task<Message> {
+                if files.is_empty() {
+                        return Task::none();
+                }
+                let generation = self.list_generation;
+                Task::perform(read_entries(files), move |entries| Message::EntriesRead(generation, entries))
         }
-}
-        fn filter_invalid(&self, input: String) -> String{
-                let invalid = ['<','>',':','"','/','\\','|','?','*','.'];
-                let filter_str :String = input
-                .chars()
-                .filter(|c| !invalid.contains(c))
-                .collect();
-                filter_str
+
+        /// Appends the entries that are not listed yet, by canonical path, and returns how many.
+        fn push_entries(&mut self, entries: Vec<FileEntry>) -> usize {
+                let mut known: HashSet<PathBuf> = self.file_names.iter().map(|entry| entry.canonical.clone()).collect();
+                let count = self.file_names.len();
+                self.file_names.extend(entries.into_iter().filter(|entry| known.insert(entry.canonical.clone())));
+                self.file_names.len() - count
         }
-        fn test_button<'a>(&self) -> Element<'a,Message>{
-                button("Test").on_press(Message::AddAction).into()}
 
-        fn action_input_ui<'a>(&self,index: usize) -> Element<'a,Message> {
-                let mut content= row![];
+        fn list_changed(&mut self) -> Task<Message> {
+                self.group_companions();
+                self.sort_files();
+                self.find_duplicate_groups();
+                self.validate_names();
+                if self.live_preview {self.preview_new_filename()};
+                Task::batch([self.ensure_hashes(), self.read_exif_dates()])
+        }
 
-                let text_state = &self.texts_state[index];
-                
-                let (pattern_label,value_label) = match &text_state.action_option {
-                        Some(ActionOptions::MatchAndReplace) =>{
-                                ("Match".to_string(),"Replace".to_string())
-                        },
-                        Some(ActionOptions::RegexReplace) =>{
-                                ("Regex".to_string(),"Replace".to_string())
-                        },
-                        Some(ActionOptions::Prefix) =>{
-                                ("".to_string(),"Prefix".to_string())
-                        },
-                        Some(ActionOptions::Suffix) =>{("".to_string(),"Suffix".to_string())},
-                        _ =>{("_".to_string(),"_".to_string())},
-                };
+        /// Reads the EXIF dates of files that have not been read yet, only while sorting by them.
+        fn read_exif_dates(&mut self) -> Task<Message> {
+                if self.sort_key != SortKey::ExifDate {
+                        return Task::none();
+                }
+                let paths: Vec<PathBuf> = self.file_names.iter_mut()
+                        .filter(|entry| !entry.exif_read)
+                        .map(|entry| {
+                                entry.exif_read = true;
+                                entry.old_path.clone()
+                        })
+                        .collect();
+                if paths.is_empty() {
+                        return Task::none();
+                }
+                Task::perform(read_exif_dates(paths), Message::ExifRead)
+        }
 
-                
-                
-                if let Some(ActionOptions::MatchAndReplace) | Some(ActionOptions::RegexReplace) = &text_state.action_option {
-                        content = content.push(row! [
-                                text!("{}:",pattern_label).width(55),//.align_x(Center).align_y(Center),
-                                text_input("", &text_state.pattern).on_input(move |s| Message::PatternChange(index, s)),
+        /// Asks for a new preview, it runs in the background once edits pause.
+        fn preview_new_filename(&mut self) {
+                self.preview_requested = true;
+        }
+
+        /// Computes the preview right away in the background, then runs `after` with the new names.
+        fn finish_preview(&mut self, after: AfterPreview) -> Task<Message> {
+                self.stop_preview_run();
+                self.preview_requested = false;
+                // Results of runs that are still on their way are stale now
+                self.preview_generation += 1;
+                let job = self.preview_job();
+                Task::perform(run_preview(job, Arc::new(AtomicBool::new(false))), move |output| Message::PreviewFinished(output, after))
+        }
+
+        /// Saves the previewed names as a CSV plan.
+        fn export_preview(&self) -> Task<Message> {
+                let rows: Vec<(&Path, &Path, String)> = self.file_names.iter().map(|entry| {
+                        let status = if !entry.problems.is_empty() {
+                                entry.problems.join("; ")
+                        } else if entry.old_path == entry.new_path {
+                                String::from("unchanged")
+                        } else {
+                                String::from("ok")
+                        };
+                        (entry.old_path.as_path(), entry.new_path.as_path(), status)
+                }).collect();
+                match mapping::export_plan(&rows) {
+                        Ok(content) => Task::perform(save_csv(content, "rename-plan.csv"), Message::Exported),
+                        Err(error) => Task::future(error_dialog(error)).discard(),
+                }
+        }
+
+        /// Saves the previewed renames as a script.
+        fn export_script(&self) -> Task<Message> {
+                let files: Vec<(PathBuf, PathBuf)> = self.file_names.iter()
+                        .map(|entry| (entry.old_path.clone(), entry.new_path.clone()))
+                        .collect();
+                Task::perform(save_script(plan::order(&files)), Message::Exported)
+        }
+
+        /// Nothing is applying and every digest the names use is known.
+        fn can_apply(&self) -> bool {
+                self.applying.is_none() && !self.digests_pending()
+        }
+
+        /// Asks to confirm names with problems before applying.
+        fn review_apply(&self) -> Task<Message> {
+                let problem_count = self.file_names.iter().filter(|entry| !entry.problems.is_empty()).count();
+                if problem_count > 0 {
+                        Task::perform(problems_dialog(problem_count, self.target_profile),
+                                |result| Message::ConfirmApply(result == MessageDialogResult::Yes))
+                } else {
+                        Task::done(Message::ConfirmApply(true))
+                }
+        }
+
+
+        fn stop_preview_run(&mut self) {
+                if let Some(run) = self.preview_run.take() {
+                        run.cancel.store(true, Ordering::Relaxed);
+                        run.handle.abort();
+                }
+        }
+
+        /// Snapshot of the list and steps for a preview run, unchanged rows and hashes are shared with the last run.
+        fn preview_job(&mut self) -> PreviewJob {
+                let rows_changed = self.preview_rows.len() != self.file_names.len()
+                        || self.file_names.iter().zip(self.preview_rows.iter()).any(|(entry, row)| {
+                                entry.old_path != row.old_path || entry.meta != row.meta
+                                        || entry.companion != row.companion || entry.duplicate != row.duplicate
+                        });
+                if rows_changed {
+                        self.preview_rows = Arc::new(self.file_names.iter().map(|entry| PreviewRow {
+                                old_path: entry.old_path.clone(),
+                                meta: entry.meta.clone(),
+                                companion: entry.companion.clone(),
+                                duplicate: entry.duplicate,
+                        }).collect());
+                }
+                let hashes = self.hash_snapshot.get_or_insert_with(|| Arc::new(self.hash_cache.clone())).clone();
+                PreviewJob {
+                        rows: self.preview_rows.clone(),
+                        steps: self.texts_state.iter().map(TextState::step).collect(),
+                        time_zone: self.time_zone.unwrap_or_default(),
+                        hashes,
+                        hash_version: self.hash_version,
+                        duplicate_handling: self.duplicate_handling,
+                        profile: self.target_profile,
+                        previous: self.preview_cache.clone(),
+                }
+        }
+
+        /// Copies a finished run into the list, unless the list changed while it ran.
+        fn show_preview(&mut self, output: PreviewOutput) -> bool {
+                let rows = output.cache.rows();
+                if rows.len() != self.file_names.len()
+                        || self.file_names.iter().zip(rows.iter()).any(|(entry, row)| entry.old_path != row.old_path) {
+                        if self.live_preview {self.preview_new_filename()};
+                        return false;
+                }
+                let results = output.new_paths.into_iter().zip(output.errors).zip(output.problems);
+                for (entry, ((new_path, errors), problems)) in self.file_names.iter_mut().zip(results) {
+                        entry.new_path = new_path;
+                        entry.errors = errors;
+                        entry.problems = problems;
+                }
+                self.preview_cache = Some(output.cache);
+                self.add_hash_errors();
+                true
+        }
+
+        /// Runs the changed rows of the list in the background, or only simulates it for a dry run.
+        fn start_apply(&mut self, dry_run: bool) -> Task<Message> {
+                if self.applying.is_some() {
+                        return Task::none();
+                }
+                let files: Vec<(PathBuf, PathBuf)> = self.file_names.iter()
+                        .filter(|entry| entry.old_path != entry.new_path)
+                        .map(|entry| (entry.old_path.clone(), entry.new_path.clone()))
+                        .collect();
+                let cancel = Arc::new(AtomicBool::new(false));
+                self.applying = Some(ApplyProgress {
+                        done: 0,
+                        total: files.len(),
+                        current: None,
+                        started: std::time::Instant::now(),
+                        cancel: cancel.clone(),
+                        dry_run,
+                });
+                let options = ApplyOptions { remove_empty_folders: self.remove_empty_folders, verify_hash: self.verify_moves, dry_run };
+                Task::run(plan::apply_in_background(files, options, cancel), Message::Apply)
+        }
+
+        /// `(old, new, status)` of every file of an applied plan, with the planned name for files that were not renamed.
+        fn report_rows(&self, report: &ApplyReport) -> Vec<(PathBuf, PathBuf, String)> {
+                let planned: HashMap<&Path, &Path> = self.file_names.iter()
+                        .map(|entry| (entry.old_path.as_path(), entry.new_path.as_path()))
+                        .collect();
+                let planned_row = |old: &PathBuf, status: String| {
+                        let new = planned.get(old.as_path()).map_or_else(|| old.clone(), |new| new.to_path_buf());
+                        (old.clone(), new, status)
+                };
+                let (renamed, moved) = if report.dry_run { ("would rename", "would move") } else { ("renamed", "moved") };
+                report.renamed.iter().map(|(old, new)| (old.clone(), new.clone(), String::from(renamed)))
+                        .chain(report.moved.iter().map(|(old, new)| (old.clone(), new.clone(), String::from(moved))))
+                        .chain(report.failed.iter().map(|(old, error)| planned_row(old, format!("failed: {}", error))))
+                        .chain(report.skipped.iter().map(|old| planned_row(old, String::from("cancelled"))))
+                        .collect()
+        }
+
+        /// Rebuilds the step's validation errors after its settings changed.
+        fn step_changed(&mut self, i: usize) {
+                let errors = self.texts_state[i].step().build().validation_errors();
+                self.texts_state[i].validation_errors = errors;
+        }
+
+        fn hashes_changed(&mut self) {
+                self.hash_version += 1;
+                self.hash_snapshot = None;
+        }
+
+        /// Starts hashing the files that miss a digest used by a hash token or the duplicate finder.
+        /// Only one job runs at a time, the next one starts when it finishes.
+        fn ensure_hashes(&mut self) -> Task<Message> {
+                if self.hashing.is_some() {
+                        return Task::none();
+                }
+                let files = self.missing_hashes();
+                if files.is_empty() {
+                        return Task::none();
+                }
+
+                let total = files.iter().map(|(_, _, needed)| needed.len()).sum();
+                let (task, handle) = Task::run(hash::hash_files(files), Message::Hash).abortable();
+                self.hashing = Some(HashProgress { done: 0, total, handle });
+                task
+        }
+
+        /// True while digests that the steps or the duplicate finder use are still being computed,
+        /// the new names are not final until then.
+        fn digests_pending(&self) -> bool {
+                self.hashing.is_some() && !self.missing_hashes().is_empty()
+        }
+
+        /// Files with the algorithms they have no digest for yet.
+        fn missing_hashes(&self) -> Vec<(PathBuf, Option<SystemTime>, Vec<HashAlgorithm>)> {
+                let mut algorithms: Vec<HashAlgorithm> = Vec::new();
+                for text_state in &self.texts_state {
+                        if let Some(ActionOptions::Prefix | ActionOptions::Suffix | ActionOptions::Template) = text_state.action_option {
+                                for algorithm in hash::algorithms_in(&text_state.value) {
+                                        if !algorithms.contains(&algorithm) {
+                                                algorithms.push(algorithm);
+                                        }
+                                }
+                        }
+                }
+                let candidates = self.duplicate_candidates();
+
+                let mut files = Vec::new();
+                for (entry, is_candidate) in self.file_names.iter().zip(candidates) {
+                        if entry.companion.is_some() {
+                                continue;
+                        }
+                        let modified = entry.meta.as_ref().and_then(|meta| meta.modified);
+                        let mut needed = algorithms.clone();
+                        if is_candidate && !needed.contains(&DUPLICATE_HASH) {
+                                needed.push(DUPLICATE_HASH);
+                        }
+                        needed.retain(|algorithm| hash::lookup(&self.hash_cache, &entry.old_path, modified, *algorithm).is_none()
+                                && self.hash_failure(&entry.old_path, modified, *algorithm).is_none());
+                        if !needed.is_empty() {
+                                files.push((entry.old_path.clone(), modified, needed));
+                        }
+                }
+                files
+        }
+
+        /// Files that share their size with another file, all false when the finder is off.
+        fn duplicate_candidates(&self) -> Vec<bool> {
+                if !self.find_duplicates {
+                        return vec![false; self.file_names.len()];
+                }
+                let sizes: Vec<Option<u64>> = self.file_names.iter()
+                        .map(|entry| entry.companion.is_none().then_some(entry.meta.as_ref().map(|meta| meta.size)).flatten())
+                        .collect();
+                duplicate::size_candidates(&sizes)
+        }
+
+        /// Groups the loaded files by size and then by content hash.
+        fn find_duplicate_groups(&mut self) {
+                let candidates = self.duplicate_candidates();
+                let files: Vec<(Option<u64>, Option<&str>)> = self.file_names.iter().zip(candidates).map(|(entry, is_candidate)| {
+                        if !is_candidate {
+                                return (None, None);
+                        }
+                        let modified = entry.meta.as_ref().and_then(|meta| meta.modified);
+                        (entry.meta.as_ref().map(|meta| meta.size),
+                                hash::lookup(&self.hash_cache, &entry.old_path, modified, DUPLICATE_HASH))
+                }).collect();
+                let duplicates = duplicate::find_duplicates(&files);
+                for (entry, duplicate) in self.file_names.iter_mut().zip(duplicates) {
+                        entry.duplicate = duplicate;
+                }
+        }
+
+        /// Error of the last try to hash the file, unless it changed since.
+        fn hash_failure(&self, path: &Path, modified: Option<SystemTime>, algorithm: HashAlgorithm) -> Option<&str> {
+                self.hash_failures.get(&(path.to_path_buf(), algorithm))
+                        .filter(|(failed_modified, _)| *failed_modified == modified)
+                        .map(|(_, error)| error.as_str())
+        }
+
+        /// Shows files that could not be hashed on their row, their hash tokens stay empty.
+        fn add_hash_errors(&mut self) {
+                if self.hash_failures.is_empty() {
+                        return;
+                }
+                for i in 0..self.file_names.len() {
+                        let entry = &self.file_names[i];
+                        let modified = entry.meta.as_ref().and_then(|meta| meta.modified);
+                        let errors: Vec<String> = HashAlgorithm::ALL.into_iter()
+                                .filter_map(|algorithm| self.hash_failure(&entry.old_path, modified, algorithm)
+                                        .map(|error| format!("Unable to hash ({}): {}", algorithm.token(), error)))
+                                .collect();
+                        for error in errors {
+                                if !self.file_names[i].problems.contains(&error) {
+                                        self.file_names[i].problems.push(error);
+                                }
+                        }
+                }
+        }
+
+        fn cancel_hashing(&mut self) {
+                if let Some(progress) = self.hashing.take() {
+                        progress.handle.abort();
+                        self.hashes_changed();
+                }
+        }
+
+        /// The current steps and settings as they run, commands not confirmed with Enter are left out.
+        fn pipeline(&self) -> Pipeline {
+                Pipeline {
+                        steps: self.texts_state.iter().map(TextState::step).collect(),
+                        time_zone: self.time_zone_input.clone(),
+                        target_profile: self.target_profile,
+                }
+        }
+
+        /// Splits the list into a primary file followed by its companions, so groups move as one.
+        fn take_groups(&mut self) -> Vec<Vec<FileEntry>> {
+                let mut groups: Vec<Vec<FileEntry>> = Vec::new();
+                for entry in std::mem::take(&mut self.file_names) {
+                        match groups.last_mut() {
+                                Some(group) if entry.companion.is_some() => group.push(entry),
+                                _ => groups.push(vec![entry]),
+                        }
+                }
+                groups
+        }
+
+        /// Orders the list by the chosen column, companions stay below their primary file.
+        fn sort_files(&mut self) {
+                if self.sort_key == SortKey::Manual {
+                        return;
+                }
+                let mut groups = self.take_groups();
+                let name = |group: &Vec<FileEntry>| group[0].old_path.file_name()
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                let modified = |group: &Vec<FileEntry>| group[0].meta.as_ref().and_then(|meta| meta.modified);
+                match self.sort_key {
+                        SortKey::Manual => {},
+                        SortKey::Name => groups.sort_by(|a, b| sort::natural_cmp(&name(a), &name(b))),
+                        SortKey::Modified => groups.sort_by_key(modified),
+                        SortKey::Size => groups.sort_by_key(|group| group[0].meta.as_ref().map(|meta| meta.size)),
+                        // Files without EXIF data, or not read yet, use their modification time
+                        SortKey::ExifDate => groups.sort_by_cached_key(|group| group[0].exif_date
+                                .or_else(|| modified(group).map(|time| chrono::DateTime::<chrono::Local>::from(time).naive_local()))),
+                        SortKey::Extension => groups.sort_by(|a, b| {
+                                let ext = |group: &Vec<FileEntry>| group[0].old_path.extension()
+                                        .map(|ext| ext.to_string_lossy().to_lowercase());
+                                ext(a).cmp(&ext(b)).then_with(|| sort::natural_cmp(&name(a), &name(b)))
+                        }),
+                }
+                if self.sort_descending {
+                        groups.reverse();
+                }
+                self.file_names = groups.into_iter().flatten().collect();
+        }
+
+        /// Moves the group of row `from` to the place of the group of row `to`, returns its new row.
+        fn move_row(&mut self, from: usize, to: usize) -> usize {
+                let group_of = |entries: &[FileEntry], row: usize| {
+                        entries[..=row.min(entries.len().saturating_sub(1))].iter().filter(|entry| entry.companion.is_none()).count().saturating_sub(1)
+                };
+                if self.file_names.is_empty() {
+                        return from;
+                }
+                let (from_group, to_group) = (group_of(&self.file_names, from), group_of(&self.file_names, to));
+                let mut groups = self.take_groups();
+                sort::move_item(&mut groups, from_group, to_group);
+                let row = groups[..to_group.min(groups.len())].iter().map(Vec::len).sum();
+                self.file_names = groups.into_iter().flatten().collect();
+                row
+        }
+
+        /// Checks the previewed names against the rules of the target file system.
+        fn validate_names(&mut self) {
+                let files: Vec<(&Path, &Path)> = self.file_names.iter()
+                        .map(|entry| (entry.old_path.as_path(), entry.new_path.as_path()))
+                        .collect();
+                let problems = validate::validate_batch(self.target_profile, &files);
+                for (entry, problems) in self.file_names.iter_mut().zip(problems) {
+                        entry.problems = entry.errors.iter().cloned().chain(problems).collect();
+                }
+                self.add_hash_errors();
+        }
+
+        /// Links companion files to their primary file and keeps each group together in the list.
+        fn group_companions(&mut self) {
+                self.companion_error = None;
+                for entry in self.file_names.iter_mut() {
+                        entry.companion = None;
+                }
+                if !self.group_companions {
+                        return;
+                }
+                let rules = match CompanionRules::new(&self.companion_extensions, &self.companion_rules) {
+                        Ok(rules) => rules,
+                        Err(error) => {
+                                self.companion_error = Some(error);
+                                return;
+                        }
+                };
+
+                let paths: Vec<&Path> = self.file_names.iter().map(|entry| entry.old_path.as_path()).collect();
+                let links = rules.group(&paths);
+                let mut companions: HashMap<PathBuf, Vec<FileEntry>> = HashMap::new();
+                let mut primaries = Vec::new();
+                for (mut entry, link) in std::mem::take(&mut self.file_names).into_iter().zip(links) {
+                        match link {
+                                Some(link) => {
+                                        let primary = link.primary.clone();
+                                        entry.companion = Some(link);
+                                        companions.entry(primary).or_default().push(entry);
+                                },
+                                None => primaries.push(entry),
+                        }
+                }
+                for entry in primaries {
+                        let group = companions.remove(&entry.old_path).unwrap_or_default();
+                        self.file_names.push(entry);
+                        self.file_names.extend(group);
+                }
+        }
+
+        /// Strips characters that are invalid in file names. Token names and arguments inside `{}`
+        /// are kept as is, fallback text is cleaned like the rest of the name.
+        /// With `allow_folders`, `/` is kept so the new name can include sub folders.
+        fn filter_invalid(&self, input: String, allow_folders: bool, pattern: &str) -> String{
+                let regex = if pattern.is_empty() { None } else { Regex::new(pattern).ok() };
+                let clean = |text: &str| -> String {
+                        text.chars().filter(|c| (allow_folders && *c == '/') || !INVALID_CHARS.contains(c)).collect()
+                };
+                let mut filter_str = String::new();
+                let mut rest = input.as_str();
+                while let Some(start) = rest.find('{') {
+                        filter_str.push_str(&clean(&rest[..start]));
+                        // A token that is still being typed is kept until it is closed
+                        let Some(len) = rest[start..].find('}') else {
+                                filter_str.push_str(&rest[start..]);
+                                rest = "";
+                                break;
+                        };
+                        filter_str.push('{');
+                        filter_str.push_str(&token::clean_fallback(&rest[start + 1..start + len], regex.as_ref(), &clean));
+                        filter_str.push('}');
+                        rest = &rest[start + len + 1..];
+                }
+                filter_str.push_str(&clean(rest));
+                filter_str
+        }
+        fn test_button<'a>(&self) -> Element<'a,Message>{
+                button("Test").on_press(Message::AddAction).into()}
+
+        fn action_input_ui<'a>(&'a self,index: usize) -> Element<'a,Message> {
+                let mut content= row![];
+
+                let text_state = &self.texts_state[index];
+                
+                let (pattern_label,value_label) = match &text_state.action_option {
+                        Some(ActionOptions::MatchAndReplace) =>{
+                                ("Match".to_string(),"Replace".to_string())
+                        },
+                        Some(ActionOptions::RegexReplace) =>{
+                                ("Regex".to_string(),"Replace".to_string())
+                        },
+                        Some(ActionOptions::Prefix) =>{
+                                ("".to_string(),"Prefix".to_string())
+                        },
+                        Some(ActionOptions::Suffix) =>{("".to_string(),"Suffix".to_string())},
+                        Some(ActionOptions::Template) =>{("Regex".to_string(),"Name".to_string())},
+                        Some(ActionOptions::Unicode) =>{("".to_string(),"Form".to_string())},
+                        Some(ActionOptions::MakeSafe) =>{("".to_string(),"Target".to_string())},
+                        Some(ActionOptions::Cleanup) =>{("".to_string(),"Clean".to_string())},
+                        Some(ActionOptions::Truncate) =>{("".to_string(),"Length".to_string())},
+                        Some(ActionOptions::Renumber) =>{("".to_string(),"Numbers".to_string())},
+                        Some(ActionOptions::Import) =>{("".to_string(),"File".to_string())},
+                        Some(ActionOptions::Script) =>{("".to_string(),"Script".to_string())},
+                        Some(ActionOptions::Command) =>{("".to_string(),"Command".to_string())},
+                        Some(ActionOptions::Plugin) =>{("".to_string(),"".to_string())},
+                        _ =>{("_".to_string(),"_".to_string())},
+                };
+
+                
+                
+                if let Some(ActionOptions::MatchAndReplace) | Some(ActionOptions::RegexReplace) | Some(ActionOptions::Template) = &text_state.action_option {
+                        content = content.push(row! [
+                                text!("{}:",pattern_label).width(55),//.align_x(Center).align_y(Center),
+                                text_input("", &text_state.pattern).on_input(move |s| Message::PatternChange(index, s)),
                         ]
                         .spacing(10)
                         .align_y(Center));
                 }
 
-           content.push(
+                let value_input: Element<'a,Message> = match &text_state.action_option {
+                        Some(ActionOptions::Unicode) => self.unicode_options_ui(index, &value_label),
+                        Some(ActionOptions::Cleanup) => self.cleanup_options_ui(index, &value_label),
+                        Some(ActionOptions::Truncate) => self.truncate_options_ui(index, &value_label),
+                        Some(ActionOptions::Renumber) => self.renumber_options_ui(index, &value_label),
+                        Some(ActionOptions::Import) => self.import_ui(index, &value_label),
+                        Some(ActionOptions::Script) => self.script_ui(index, &value_label),
+                        Some(ActionOptions::Command) => self.command_ui(index, &value_label),
+                        Some(ActionOptions::Plugin) => self.plugin_ui(index),
+                        Some(ActionOptions::MakeSafe) => row![
+                                text!("{}:",value_label).width(55),
+                                pick_list(TargetProfile::ALL, Some(text_state.profile),
+                                        move |profile| Message::ProfileChange(index, profile)),
+                        ].spacing(10).align_y(Center).width(Fill).into(),
+                        _ => row![
+                                text!("{}:",value_label).width(55).align_y(Center),
+                                text_input("", &text_state.value).on_input(move |s| Message::TextChange(index, s)),
+                        ].spacing(10).align_y(Center).width(Fill).into(),
+                };
+
+           let inputs = content.push(
                 row![
                      //text!("{}:",pattern_label).width(70).align_y(Center),
                      //text_input("", &text_state.pattern).on_input(move |s| Message::PatternChange(index, s)),
-                     text!("{}:",value_label).width(55).align_y(Center),
-                     text_input("", &text_state.value).on_input(move |s| Message::TextChange(index, s)),
+                     value_input,
                      self.action_picker_ui(index),
                      button("Delete").on_press(Message::RemoveAction(index)).style(button::danger),
 
                 ].spacing(10)
                 .align_y(Center)
         ).spacing(10)
-        .wrap()
-        .into()
+        .wrap();
+
+                let inputs = column![inputs, self.condition_ui(index)].spacing(10);
+                let mut errors = text_state.validation_errors.clone();
+                if let Some(ActionOptions::Import) = text_state.action_option {
+                        errors.extend(self.import_errors(index));
+                }
+                if let Some(error) = self.texts_state[index].condition.error() {
+                        errors.push(format!("Condition: {}", error));
+                }
+                if errors.is_empty() {
+                        return inputs.into();
+                }
+                let mut error_list = column![].spacing(5);
+                for error in errors {
+                        error_list = error_list.push(text(error).size(13).style(text::danger));
+                }
+                column![inputs, error_list].spacing(10).into()
+        }
+
+        fn condition_ui<'a>(&self, index: usize) -> Element<'a,Message> {
+                let condition = &self.texts_state[index].condition;
+                let (kind, input, negate) = (condition.kind, condition.input.clone(), condition.negate);
+                let mut content = row![
+                        text("When:").width(55),
+                        pick_list(ConditionKind::ALL, Some(kind), {
+                                let input = input.clone();
+                                move |kind| Message::ConditionChange(index, kind, input.clone(), negate)
+                        }),
+                ].spacing(10).align_y(Center);
+                if kind != ConditionKind::Always {
+                        content = content.push(text_input(kind.placeholder(), &input)
+                                .on_input(move |input| Message::ConditionChange(index, kind, input, negate)));
+                        content = content.push(checkbox(negate).label("Not")
+                                .on_toggle(move |negate| Message::ConditionChange(index, kind, input.clone(), negate)));
+                }
+                content.into()
+        }
+
+        fn unicode_options_ui<'a>(&self, index: usize, label: &str) -> Element<'a,Message> {
+                let options = self.texts_state[index].unicode;
+                let forms = [NormalForm::Nfc, NormalForm::Nfd, NormalForm::Nfkc, NormalForm::Nfkd];
+                row![
+                        text!("{}:",label).width(55),
+                        pick_list(forms, Some(options.form),
+                                move |form| Message::UnicodeChange(index, UnicodeOptions { form, ..options })),
+                        checkbox(options.transliterate).label("ASCII")
+                                .on_toggle(move |transliterate| Message::UnicodeChange(index, UnicodeOptions { transliterate, ..options })),
+                        checkbox(options.slugify).label("Slugify")
+                                .on_toggle(move |slugify| Message::UnicodeChange(index, UnicodeOptions { slugify, ..options })),
+                ].spacing(10).align_y(Center).width(Fill).into()
+        }
+
+        fn cleanup_options_ui<'a>(&self, index: usize, label: &str) -> Element<'a,Message> {
+                let options = self.texts_state[index].cleanup;
+                let conversions = [SpaceConversion::Keep, SpaceConversion::ToSpaces, SpaceConversion::ToUnderscores,
+                        SpaceConversion::ToDots, SpaceConversion::ToPercent20];
+                let option = move |is_checked: bool, name: &'static str, update: fn(&mut CleanupOptions, bool)| {
+                        checkbox(is_checked).label(name).on_toggle(move |is_on| {
+                                let mut options = options;
+                                update(&mut options, is_on);
+                                Message::CleanupChange(index, options)
+                        })
+                };
+                row![
+                        text!("{}:",label).width(55),
+                        option(options.collapse_whitespace, "Collapse spaces", |o, v| o.collapse_whitespace = v),
+                        option(options.trim, "Trim", |o, v| o.trim = v),
+                        option(options.strip_copy_markers, "Strip \" - Copy\" / (n)", |o, v| o.strip_copy_markers = v),
+                        option(options.remove_empty_brackets, "Empty brackets", |o, v| o.remove_empty_brackets = v),
+                        option(options.collapse_separators, "Repeated separators", |o, v| o.collapse_separators = v),
+                        pick_list(conversions, Some(options.spaces),
+                                move |spaces| Message::CleanupChange(index, CleanupOptions { spaces, ..options })),
+                ].spacing(10).align_y(Center).width(Fill).wrap().into()
+        }
+
+        fn truncate_options_ui<'a>(&self, index: usize, label: &str) -> Element<'a,Message> {
+                let options = self.texts_state[index].truncate;
+                let length = if options.max_length == 0 { String::new() } else { options.max_length.to_string() };
+                row![
+                        text!("{}:",label).width(55),
+                        text_input("64", &length).width(70).on_input(move |input| {
+                                let digits: String = input.chars().filter(char::is_ascii_digit).collect();
+                                Message::TruncateChange(index, TruncateOptions { max_length: digits.parse().unwrap_or(0), ..options })
+                        }),
+                        pick_list([LengthUnit::Characters, LengthUnit::Bytes], Some(options.unit),
+                                move |unit| Message::TruncateChange(index, TruncateOptions { unit, ..options })),
+                        checkbox(options.word_boundary).label("Whole words")
+                                .on_toggle(move |word_boundary| Message::TruncateChange(index, TruncateOptions { word_boundary, ..options })),
+                        checkbox(options.ellipsis).label("Add …")
+                                .on_toggle(move |ellipsis| Message::TruncateChange(index, TruncateOptions { ellipsis, ..options })),
+                ].spacing(10).align_y(Center).width(Fill).wrap().into()
+        }
+
+        fn renumber_options_ui<'a>(&self, index: usize, label: &str) -> Element<'a,Message> {
+                let options = self.texts_state[index].renumber;
+                let targets = [NumberTarget::First, NumberTarget::Last, NumberTarget::All];
+                let paddings = [NumberPadding::Keep, NumberPadding::Fixed, NumberPadding::Widest, NumberPadding::Strip];
+                let width = if options.width == 0 { String::new() } else { options.width.to_string() };
+                let offset = if options.offset == 0 { String::new() } else { options.offset.to_string() };
+                let mut content = row![
+                        text!("{}:",label).width(55),
+                        pick_list(targets, Some(options.target),
+                                move |target| Message::RenumberChange(index, RenumberOptions { target, ..options })),
+                        pick_list(paddings, Some(options.padding),
+                                move |padding| Message::RenumberChange(index, RenumberOptions { padding, ..options })),
+                ].spacing(10).align_y(Center).width(Fill);
+                if options.padding == NumberPadding::Fixed {
+                        content = content.push(text_input("3", &width).width(50).on_input(move |input| {
+                                let digits: String = input.chars().filter(char::is_ascii_digit).collect();
+                                Message::RenumberChange(index, RenumberOptions { width: digits.parse().unwrap_or(0), ..options })
+                        }));
+                }
+                content.push(text("Offset:"))
+                        .push(text_input("0", &offset).width(70).on_input(move |input| {
+                                let number: String = input.chars().enumerate()
+                                        .filter(|(i, c)| c.is_ascii_digit() || (*i == 0 && *c == '-'))
+                                        .map(|(_, c)| c)
+                                        .collect();
+                                Message::RenumberChange(index, RenumberOptions { offset: number.parse().unwrap_or(0), ..options })
+                        }))
+                        .wrap()
+                        .into()
+        }
+
+        fn import_ui<'a>(&self, index: usize, label: &str) -> Element<'a,Message> {
+                let mapping = &self.texts_state[index].mapping;
+                let summary = if mapping.rows.is_empty() {
+                        String::from("CSV or TSV with old and new names")
+                } else {
+                        format!("{} rows", mapping.rows.len())
+                };
+                row![
+                        text!("{}:",label).width(55),
+                        button("Import CSV…").on_press(Message::OpenMappingPicker(index)),
+                        text(summary),
+                ].spacing(10).align_y(Center).width(Fill).into()
+        }
+
+        fn script_ui<'a>(&'a self, index: usize, label: &str) -> Element<'a,Message> {
+                row![
+                        text!("{}:",label).width(55),
+                        text_editor(&self.texts_state[index].script)
+                                .placeholder("if index < 9 { stem + \"_0\" + (index + 1) } else { stem + \"_\" + (index + 1) }")
+                                .font(Font::MONOSPACE)
+                                .height(120)
+                                .on_action(move |action| Message::ScriptEdit(index, action)),
+                ].spacing(10).width(Fill).into()
+        }
+
+        fn command_ui<'a>(&self, index: usize, label: &str) -> Element<'a,Message> {
+                let text_state = &self.texts_state[index];
+                let options = text_state.command;
+                let timeout = if options.timeout_secs == 0 { String::new() } else { options.timeout_secs.to_string() };
+                let mut content = column![
+                        row![
+                                text!("{}:",label).width(55),
+                                text_input("tr '[:upper:]' '[:lower:]'", &text_state.value)
+                                        .font(Font::MONOSPACE)
+                                        .on_input(move |command| Message::CommandInput(index, command))
+                                        .on_submit(Message::CommandSubmit(index)),
+                        ].spacing(10).align_y(Center),
+                        row![
+                                space::horizontal().width(55),
+                                tooltip(
+                                        checkbox(options.batch).label("One process for all names")
+                                                .on_toggle(move |batch| Message::CommandChange(index, CommandOptions { batch, ..options })),
+                                        "Names are sent line by line, the command prints one new name per line",
+                                        tooltip::Position::Bottom
+                                ),
+                                checkbox(options.full_paths).label("Send full paths")
+                                        .on_toggle(move |full_paths| Message::CommandChange(index, CommandOptions { full_paths, ..options })),
+                                text("Timeout (s):"),
+                                text_input("10", &timeout).width(50).on_input(move |input| {
+                                        let digits: String = input.chars().filter(char::is_ascii_digit).collect();
+                                        Message::CommandChange(index, CommandOptions { timeout_secs: digits.parse().unwrap_or(0), ..options })
+                                }),
+                        ].spacing(10).align_y(Center).wrap(),
+                ].spacing(10).width(Fill);
+                if text_state.command_edited() {
+                        content = content.push(text("Press Enter to run the command").size(13));
+                }
+                content.into()
+        }
+
+        /// Inputs declared by the step's plugin.
+        fn plugin_ui<'a>(&self, index: usize) -> Element<'a,Message> {
+                let text_state = &self.texts_state[index];
+                let fields = plugin::find(&text_state.plugin).map(|plugin| plugin.fields()).unwrap_or_default();
+                let mut content = row![].spacing(10).align_y(Center).width(Fill);
+                for (i, field) in fields.into_iter().enumerate() {
+                        let value = text_state.fields.get(i).cloned().unwrap_or_default();
+                        content = content.push(text!("{}:", field.label));
+                        let input: Element<'a,Message> = if field.choices.is_empty() {
+                                text_input(&field.placeholder, &value)
+                                        .on_input(move |value| Message::PluginFieldChange(index, i, value))
+                                        .into()
+                        } else {
+                                pick_list(field.choices, Some(value),
+                                        move |value| Message::PluginFieldChange(index, i, value))
+                                        .into()
+                        };
+                        content = content.push(input);
+                }
+                content.wrap().into()
+        }
 
+        /// Read errors and mapping rows that match no loaded file.
+        fn import_errors(&self, index: usize) -> Vec<String> {
+                let text_state = &self.texts_state[index];
+                if let Some(error) = &text_state.mapping_error {
+                        return vec![error.clone()];
+                }
+                let files: Vec<&Path> = self.file_names.iter().map(|entry| entry.old_path.as_path()).collect();
+                let unmatched = text_state.mapping.unmatched(&files);
+                if unmatched.is_empty() {
+                        return Vec::new();
+                }
+                let mut shown = unmatched.iter().take(5).map(|old| format!("`{}`", old)).collect::<Vec<_>>().join(", ");
+                if unmatched.len() > 5 {
+                        shown.push_str(&format!(" and {} more", unmatched.len() - 5));
+                }
+                vec![format!("{} row(s) match no file: {}", unmatched.len(), shown)]
         }
 
         fn action_picker_ui<'a>(&self, index: usize) -> Element<'a,Message> {
@@ -345,14 +1896,193 @@ impl FileRenamerApp {
                         ActionOptions::RegexReplace,
                         ActionOptions::Prefix,
                         ActionOptions::Suffix,
+                        ActionOptions::Template,
+                        ActionOptions::Unicode,
+                        ActionOptions::MakeSafe,
+                        ActionOptions::Cleanup,
+                        ActionOptions::Truncate,
+                        ActionOptions::Renumber,
+                        ActionOptions::Import,
+                        ActionOptions::Script,
+                        ActionOptions::Command,
                 ];
-                pick_list(pick_options,
-                        self.texts_state[index].action_option,
+                let text_state = &self.texts_state[index];
+                let mut choices: Vec<ActionChoice> = pick_options.into_iter().map(ActionChoice::Builtin).collect();
+                choices.extend(plugin::plugins().iter().map(|plugin| ActionChoice::Plugin {
+                        id: plugin.id().to_owned(),
+                        name: plugin.name().to_owned(),
+                }));
+                // Broken plugins stay pickable so their steps show why they failed to load
+                choices.extend(plugin::load_errors().into_iter()
+                        .filter(|(id, _)| plugin::find(id).is_none())
+                        .map(|(id, _)| ActionChoice::Plugin { name: format!("{} (failed to load)", id), id }));
+                let selected = match text_state.action_option {
+                        Some(ActionOptions::Plugin) => choices.iter()
+                                .find(|choice| matches!(choice, ActionChoice::Plugin { id, .. } if *id == text_state.plugin))
+                                .cloned()
+                                .or_else(|| Some(ActionChoice::Plugin { id: text_state.plugin.clone(), name: text_state.plugin.clone() })),
+                        action => action.map(ActionChoice::Builtin),
+                };
+                pick_list(choices,
+                        selected,
                         move |selected| Message::UpdateAction(index, selected),
 
                 ).into()
         }
 
+        fn companion_ui<'a>(&self) -> Element<'a,Message> {
+                let toggle = checkbox(self.group_companions)
+                        .label("Rename companion files together")
+                        .on_toggle(Message::ToggleCompanions);
+                if !self.group_companions {
+                        return toggle.into();
+                }
+                let mut content = column![
+                        toggle,
+                        row![text("Primary:").width(55),
+                        text_input("cr2, mkv, jpg", &self.companion_extensions)
+                                .on_input(Message::CompanionExtensionsChange)].spacing(10).align_y(Center),
+                        row![text("Rules:").width(55),
+                        text_input("<stem>.<lang>.srt", &self.companion_rules)
+                                .on_input(Message::CompanionRulesChange)].spacing(10).align_y(Center),
+                ].spacing(10);
+                if let Some(error) = &self.companion_error {
+                        content = content.push(text(error.clone()).size(13).style(text::danger));
+                }
+                content.into()
+        }
+
+        fn duplicate_ui<'a>(&self) -> Element<'a,Message> {
+                let toggle = checkbox(self.find_duplicates)
+                        .label("Find duplicate files")
+                        .on_toggle(Message::ToggleDuplicates);
+                if !self.find_duplicates {
+                        return toggle.into();
+                }
+                let groups = self.file_names.iter().filter(|entry| matches!(entry.duplicate, Some(Duplicate { copy: 0, .. }))).count();
+                let copies = self.file_names.iter().filter(|entry| matches!(entry.duplicate, Some(Duplicate { copy: 1.., .. }))).count();
+                row![
+                        toggle,
+                        text!("{} group(s), {} copies", groups, copies),
+                        space::horizontal(),
+                        text("Copies:"),
+                        pick_list(DuplicateHandling::ALL, Some(self.duplicate_handling), Message::DuplicateHandlingChange),
+                ].spacing(10).align_y(Center).into()
+        }
+
+        fn watch_ui<'a>(&self) -> Element<'a,Message> {
+                let toggle = tooltip(
+                        checkbox(self.watching.is_some())
+                                .label("Watch a folder and rename new files")
+                                .on_toggle(Message::ToggleWatch),
+                        "New files are renamed with the current steps once they stop growing",
+                        tooltip::Position::Bottom
+                );
+                let mut content = column![toggle].spacing(5);
+                if let Some((folder, _)) = &self.watching {
+                        content = content.push(text!("Watching {}", folder.display()).size(13));
+                }
+                for line in &self.watch_log {
+                        content = content.push(text(line.clone()).size(12));
+                }
+                content.into()
+        }
+
+        fn column_options_ui<'a>(&self) -> Element<'a,Message> {
+                let mut options = row![].spacing(15).align_y(Center);
+                for meta_column in MetaColumn::ALL {
+                        options = options.push(
+                                checkbox(self.meta_columns.contains(&meta_column))
+                                .label(meta_column.to_string())
+                                .on_toggle(move |is_on| Message::ToggleColumn(meta_column, is_on)));
+                }
+                let time_zone_input = text_input("Local, UTC or Europe/Paris", &self.time_zone_input)
+                        .on_input(Message::TimeZoneChange)
+                        .width(200);
+                options = options.push(space::horizontal());
+                options = options.push(text("Sort:"));
+                let descending = self.sort_descending;
+                options = options.push(pick_list(SortKey::ALL, Some(self.sort_key), move |key| Message::SortBy(key, descending)));
+                options = options.push(button(text(if descending { "▼" } else { "▲" }))
+                        .on_press_maybe((self.sort_key != SortKey::Manual).then_some(Message::SortBy(self.sort_key, !descending))));
+                options = options.push(text("Target:"));
+                options = options.push(pick_list(TargetProfile::ALL, Some(self.target_profile), Message::TargetProfileChange));
+                options = options.push(text("Time zone:"));
+                options = options.push(if self.time_zone.is_some() {
+                        time_zone_input
+                } else {
+                        time_zone_input.style(|theme: &Theme, status| text_input::Style {
+                                border: iced::Border { color: theme.palette().danger, width: 1.0, radius: 2.0.into() },
+                                ..text_input::default(theme, status)
+                        })
+                });
+                container(options).padding(Padding::new(0.0).top(10.0).left(10.0).right(10.0)).into()
+        }
+
+        /// Progress bar, rate and current file of a running apply, with a cancel button.
+        fn apply_progress_ui<'a>(&self, progress: &ApplyProgress) -> Element<'a, Message> {
+                let seconds = progress.started.elapsed().as_secs_f32();
+                let rate = if seconds > 0.0 { progress.done as f32 / seconds } else { 0.0 };
+                let current = progress.current.as_ref()
+                        .and_then(|path| path.file_name())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .unwrap_or_default();
+                let cancelling = progress.cancel.load(Ordering::Relaxed);
+                column![
+                        progress_bar(0.0..=progress.total.max(1) as f32, progress.done as f32).girth(8),
+                        row![
+                                text!("{} {}/{} · {:.0} files/s · {}", if progress.dry_run { "Checking" } else { "Renaming" },
+                                        progress.done, progress.total, rate, current),
+                                space::horizontal(),
+                                button(if cancelling { "Cancelling…" } else { "Cancel" })
+                                        .on_press_maybe((!cancelling).then_some(Message::CancelApply)),
+                        ].spacing(10).align_y(Center),
+                ].spacing(5).into()
+        }
+
+        /// Buttons that change the list without replacing it.
+        fn list_ui<'a>(&self) -> Element<'a, Message> {
+                let any_selected = self.file_names.iter().any(|entry| entry.selected);
+                row![
+                        button("Add Files").on_press(Message::OpenAddFiles),
+                        button("Add Folder").on_press(Message::OpenAddFolder),
+                        button("Remove Selected").on_press_maybe(any_selected.then_some(Message::RemoveSelected)),
+                        button("Clear List").on_press_maybe((!self.file_names.is_empty()).then_some(Message::ClearList)),
+                ].spacing(10).into()
+        }
+
+        /// Shown over the file table while files are dragged over the window.
+        fn drop_target_ui<'a>(&self) -> Element<'a, Message> {
+                container(text("Drop files to add them, or a folder to load it").size(20))
+                        .center(Fill)
+                        .style(|theme: &Theme| {
+                                let primary = theme.palette().primary;
+                                container::Style {
+                                        background: Some(primary.scale_alpha(0.15).into()),
+                                        border: iced::Border { color: primary, width: 2.0, radius: 8.0.into() },
+                                        ..container::Style::default()
+                                }
+                        })
+                        .into()
+        }
+
+        /// Page buttons for lists longer than one page.
+        fn pager_ui<'a>(&self) -> Element<'a, Message> {
+                let total = self.file_names.len();
+                if total <= PAGE_SIZE {
+                        return column![].into();
+                }
+                let last = (total - 1) / PAGE_SIZE;
+                let page = self.page.min(last);
+                let first_row = page * PAGE_SIZE + 1;
+                let last_row = ((page + 1) * PAGE_SIZE).min(total);
+                row![
+                        button("Previous").on_press_maybe((page > 0).then(|| Message::ShowPage(page - 1))),
+                        text!("Rows {}–{} of {}", first_row, last_row, total),
+                        button("Next").on_press_maybe((page < last).then(|| Message::ShowPage(page + 1))),
+                ].spacing(10).align_y(Center).padding(Padding::new(0.0).left(10.0)).into()
+        }
+
         fn display_ui<'a>(&self) -> Element<'_,Message> {
                 //let row_data: &Vec<(&String,&String)>= &self.current_file_names.iter().zip(&self.modified_file_names).collect();
                 
@@ -367,28 +2097,117 @@ impl FileRenamerApp {
                 //let st:Vec<String> = (1..100).map(|i| format!("Hi {}", i)).collect();
                 //let rows:Vec<(i32,String)> = arr.into_iter().zip(st).collect();
 
-                let columns = [
-                        table::column(   bold("Original File Name"), 
-                        |row: &(PathBuf, PathBuf)| {
-                                if let Some(file_name) = &row.0.file_name() {
-                                        let filename_str = file_name.to_string_lossy();
-                                        text(filename_str)
-                                } else {text("Unable to load file")}
+                // Clicking a sortable header sorts by it, a second click flips the order
+                let sort_header = |label: &'static str, key: SortKey| {
+                        let arrow = match (self.sort_key == key, self.sort_descending) {
+                                (false, _) => "",
+                                (true, false) => " ▲",
+                                (true, true) => " ▼",
+                        };
+                        let descending = self.sort_key == key && !self.sort_descending;
+                        let header = text!("{}{}", label, arrow).font(Font { weight: font::Weight::Bold, ..Font::DEFAULT });
+                        button(header).padding(0).style(button::text)
+                                .on_press(Message::SortBy(key, descending))
+                };
+                let manual = self.sort_key == SortKey::Manual;
+                let dragging = self.dragging;
+
+                let mut columns = vec![
+                        table::column(sort_header("Original File Name", SortKey::Name),
+                        move |(i, row): (usize, &FileEntry)| -> Element<'_, Message> {
+                                let Some(file_name) = &row.old_path.file_name() else {
+                                        return text("Unable to load file").into();
+                                };
+                                let filename_str = file_name.to_string_lossy();
+                                if row.companion.is_some() {
+                                        return text!("    ↳ {}", filename_str).into();
+                                }
+                                if !manual {
+                                        return text(filename_str).into();
+                                }
+                                // Rows are dragged by their name
+                                let name = text!("≡ {}", filename_str);
+                                let name = if dragging == Some(i) { name.style(text::primary) } else { name };
+                                mouse_area(name)
+                                        .interaction(iced::mouse::Interaction::Grab)
+                                        .on_press(Message::DragStart(i))
+                                        .on_enter(Message::DragOver(i))
+                                        .into()
                           })
                         .width(FillPortion(1)),
                         table::column(bold("Modified File Name"), 
-                        |row: &(PathBuf, PathBuf)| {
-                                if let Some(file_name) = &row.1.file_name() {
+                        |(_, row): (usize, &FileEntry)| {
+                                if let Some(file_name) = &row.new_path.file_name() {
                                         let filename_str = file_name.to_string_lossy();
                                         text(filename_str)
                                 } else {text("Unable to load file")}
                           })
                         .width(FillPortion(1))
                         ];
-                table(columns, &self.file_names).padding(10)
+
+                if self.file_names.iter().any(|entry| entry.new_path.parent() != entry.old_path.parent()) {
+                        columns.insert(1, table::column(bold("Target Folder"),
+                        |(_, row): (usize, &FileEntry)| {
+                                text(target_folder(&row.old_path, &row.new_path))
+                        })
+                        .width(FillPortion(1)));
+                }
+
+                if self.file_names.iter().any(|entry| !entry.problems.is_empty()) {
+                        columns.push(table::column(bold("Problems"),
+                        |(_, row): (usize, &FileEntry)| {
+                                text(row.problems.join(", ")).style(text::danger)
+                        })
+                        .width(FillPortion(1)));
+                }
+
+                if self.file_names.iter().any(|entry| entry.duplicate.is_some()) {
+                        columns.push(table::column(bold("Duplicate"),
+                        |(_, row): (usize, &FileEntry)| match row.duplicate {
+                                Some(Duplicate { group, copy: 0 }) => text!("#{}", group),
+                                Some(Duplicate { group, copy }) => text!("#{} copy {}", group, copy),
+                                None => text(""),
+                        })
+                        .width(FillPortion(1)));
+                }
+
+                let time_zone = self.time_zone.unwrap_or_default();
+                for meta_column in &self.meta_columns {
+                        let meta_column = *meta_column;
+                        let header: Element<'_, Message> = match meta_column {
+                                MetaColumn::Modified => sort_header(meta_column.label(), SortKey::Modified).into(),
+                                MetaColumn::Size => sort_header(meta_column.label(), SortKey::Size).into(),
+                                _ => bold(meta_column.label()).into(),
+                        };
+                        columns.push(table::column(header,
+                        move |(_, row): (usize, &FileEntry)| {
+                                text(meta_column.value(row.meta.as_ref(), time_zone))
+                        })
+                        .width(FillPortion(1)));
+                }
+                // The source folder only tells rows apart when the list spans several folders
+                let first_folder = self.file_names.first().and_then(|entry| entry.old_path.parent());
+                if self.file_names.iter().any(|entry| entry.old_path.parent() != first_folder) {
+                        columns.insert(0, table::column(bold("Folder"),
+                        |(_, row): (usize, &FileEntry)| {
+                                text(row.old_path.parent().map(|folder| folder.display().to_string()).unwrap_or_default())
+                        })
+                        .width(FillPortion(1)));
+                }
+
+                let all_selected = !self.file_names.is_empty() && self.file_names.iter().all(|entry| entry.selected);
+                columns.insert(0, table::column(checkbox(all_selected).on_toggle(Message::SelectAll),
+                |(i, row): (usize, &FileEntry)| {
+                        checkbox(row.selected).on_toggle(move |selected| Message::SelectRow(i, selected))
+                }));
+
+                // Only one page of rows is laid out, so large lists stay responsive
+                let page = self.page.min(self.file_names.len().saturating_sub(1) / PAGE_SIZE);
+                table(columns, self.file_names.iter().enumerate().skip(page * PAGE_SIZE).take(PAGE_SIZE)).padding(10)
                 };
 
-                column![row![rule::vertical(1.0),table_content],rule::horizontal(1.0)].into()
+                column![self.column_options_ui(), self.pager_ui(),
+                row![rule::vertical(1.0),table_content],rule::horizontal(1.0)].spacing(10).into()
  
 
                 
@@ -421,67 +2240,57 @@ async fn pick_files() -> Option<Vec<PathBuf>> {
 }
 
 async fn read_files_from_folder(path: PathBuf) -> Vec<PathBuf> {
-        let mut file_names = Vec::new();
-    
-        // Handle the Result here. If it fails, return an empty Vec.
-        if let Ok(mut read_dir) = fs::read_dir(&path).await {
-                while let Ok(Some(entry)) = read_dir.next_entry().await {
-                if entry.path().is_file() {
-                        file_names.push(entry.path());//.file_name().to_string_lossy().into_owned());
-                        }
-                }
-        }
-        file_names
+        tokio::task::spawn_blocking(move || filesystem::files_in(&RealFs, &path)).await.unwrap_or_default()
 }
 
-async fn rename_files(files: Vec<(PathBuf, PathBuf)>) -> Vec<RenameError> {
-        let mut set = JoinSet::new();
-        let mut failures = Vec::new();
+/// List entries for `paths`, read off the UI thread.
+async fn read_entries(paths: Vec<PathBuf>) -> Vec<FileEntry> {
+        tokio::task::spawn_blocking(move || paths.into_iter().map(FileEntry::new).collect()).await.unwrap_or_default()
+}
 
-    for (old_path, new_path) in &files {
-        let old = old_path.clone();
-        let new = new_path.clone();
+async fn read_exif_dates(paths: Vec<PathBuf>) -> Vec<(PathBuf, Option<chrono::NaiveDateTime>)> {
+        tokio::task::spawn_blocking(move || paths.into_iter()
+                .map(|path| { let date = metadata::exif_date(&path); (path, date) })
+                .collect())
+                .await
+                .unwrap_or_default()
+}
 
-        set.spawn(async move {
-            // Check if the file still existing in case its moved before rename
-            if !old.exists() {
-                return Err(RenameError {
-                    old_path: old,
-                    error: "Source no longer exists".into(),
-                });
-            }
-            /*// Check if the destination parent directory still exists
-            if let Some(parent) = new.parent() {
-                if !parent.exists() {
-                    return Err(RenameError {
-                        old_path: old,
-                        error: "Destination folder is missing".into(),
-                    });
-                }
-            }*/
-
-            fs::rename(&old, &new).await.map_err(|e| RenameError {
-                old_path: old,
-                error: e.to_string(),
-            })
-        });
-    }
+async fn read_folders(folders: Vec<PathBuf>) -> Vec<PathBuf> {
+        let mut files = Vec::new();
+        for folder in folders {
+                files.extend(read_files_from_folder(folder).await);
+        }
+        files
+}
 
-    //let mut final_result = Ok(());
+/// Canonical path of `entry` once renamed to `new`, worked out from the old one without touching the disk.
+fn moved_canonical(entry: &FileEntry, new: &Path) -> PathBuf {
+        match (entry.old_path.parent(), entry.canonical.parent()) {
+                (Some(old_folder), Some(canonical_folder)) => match new.strip_prefix(old_folder) {
+                        Ok(relative) => canonical_folder.join(relative),
+                        Err(_) => new.to_path_buf(),
+                },
+                _ => new.to_path_buf(),
+        }
+}
 
-    // Wait for all tasks to complete
-    while let Some(res) = set.join_next().await {
-        match res {
-            Ok(Err(rename_err)) => failures.push(rename_err),
-            Err(join_err) => {
-                // This happens if a task panics
-                eprintln!("Worker thread failed: {:?}", join_err);
-            },
-            _ => {}
+/// Folder of `new` shown relative to the folder of `old`, empty when the file stays in place.
+fn target_folder(old: &Path, new: &Path) -> String {
+        match (old.parent(), new.parent()) {
+                (Some(old_parent), Some(new_parent)) if old_parent == new_parent => String::new(),
+                (Some(old_parent), Some(new_parent)) => match new_parent.strip_prefix(old_parent) {
+                        Ok(relative) => format!("{}/", relative.to_string_lossy()),
+                        Err(_) => new_parent.to_string_lossy().into_owned(),
+                },
+                _ => String::new(),
         }
-    }
+}
 
-    failures
+
+/// Runs a preview off the async runtime, `None` when it was cancelled.
+async fn run_preview(job: PreviewJob, cancel: Arc<AtomicBool>) -> Option<PreviewOutput> {
+        tokio::task::spawn_blocking(move || preview::compute(&job, &cancel)).await.ok().flatten()
 }
 
 async fn confirmation_dialog() -> MessageDialogResult{
@@ -492,20 +2301,268 @@ async fn confirmation_dialog() -> MessageDialogResult{
                 .show()
                 .await
         }
-async fn ok_dialog() {
-        let dialog = rfd::AsyncMessageDialog::new()
+async fn problems_dialog(problem_count: usize, profile: TargetProfile) -> MessageDialogResult {
+        rfd::AsyncMessageDialog::new()
+                .set_title("File Renamer")
+                .set_description(format!("{} file(s) break the {} naming rules or collide with another name. Rename anyway?", problem_count, profile))
+                .set_buttons(rfd::MessageButtons::YesNo)
+                .show()
+                .await
+}
+
+async fn load_mapping() -> Option<Result<Mapping, String>> {
+        let handle = AsyncFileDialog::new()
+                .set_title("Import New Names")
+                .add_filter("CSV / TSV", &["csv", "tsv", "txt"])
+                .pick_file()
+                .await?;
+        let content = match fs::read_to_string(handle.path()).await {
+                Ok(content) => content,
+                Err(e) => return Some(Err(format!("Unable to read {}: {}", handle.path().display(), e))),
+        };
+        Some(Mapping::parse(&content))
+}
+
+async fn save_csv(content: String, file_name: &str) -> Option<Result<(), String>> {
+        let handle = AsyncFileDialog::new()
+                .set_title("Export CSV")
+                .set_file_name(file_name)
+                .save_file()
+                .await?;
+        Some(fs::write(handle.path(), content).await.map_err(|e| format!("Unable to write {}: {}", handle.path().display(), e)))
+}
+
+/// Writes the plan as a shell or PowerShell script, picked from the extension, and its undo script.
+async fn save_script(steps: Vec<(PathBuf, PathBuf)>) -> Option<Result<(), String>> {
+        let handle = AsyncFileDialog::new()
+                .set_title("Export Script")
+                .add_filter("Shell script", &["sh"])
+                .add_filter("PowerShell script", &["ps1"])
+                .set_file_name("rename.sh")
+                .save_file()
+                .await?;
+        let path = handle.path().to_path_buf();
+        let kind = ScriptKind::from_path(&path);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let extension = path.extension().map(|ext| ext.to_string_lossy().into_owned()).unwrap_or_else(|| String::from("sh"));
+        let undo_path = path.with_file_name(format!("{}-undo.{}", stem, extension));
+
+        for (path, steps) in [(&path, steps.clone()), (&undo_path, plan::undo(&steps))] {
+                if let Err(e) = fs::write(path, plan::script(kind, &steps)).await {
+                        return Some(Err(format!("Unable to write {}: {}", path.display(), e)));
+                }
+                #[cfg(unix)]
+                if kind == ScriptKind::Shell {
+                        use std::os::unix::fs::PermissionsExt;
+                        let _ = fs::set_permissions(path, std::fs::Permissions::from_mode(0o755)).await;
+                }
+        }
+        Some(Ok(()))
+}
+
+async fn save_pipeline(content: String) -> Option<Result<(), String>> {
+        let handle = AsyncFileDialog::new()
+                .set_title("Save Pipeline")
+                .add_filter("Pipeline", &["json"])
+                .set_file_name("pipeline.json")
+                .save_file()
+                .await?;
+        Some(fs::write(handle.path(), content).await.map_err(|e| format!("Unable to write {}: {}", handle.path().display(), e)))
+}
+
+async fn load_pipeline() -> Option<Result<Pipeline, String>> {
+        let handle = AsyncFileDialog::new()
+                .set_title("Load Pipeline")
+                .add_filter("Pipeline", &["json"])
+                .pick_file()
+                .await?;
+        let path = handle.path().to_path_buf();
+        Some(tokio::task::spawn_blocking(move || Pipeline::load(&path)).await.map_err(|e| e.to_string()).and_then(|result| result))
+}
+
+async fn error_dialog(message: String) {
+        rfd::AsyncMessageDialog::new()
+                .set_title("File Renamer")
+                .set_description(message)
+                .set_level(rfd::MessageLevel::Error)
+                .set_buttons(rfd::MessageButtons::Ok)
+                .show()
+                .await;
+}
+
+/// Summary of an applied plan, returns whether to save the full report.
+async fn report_dialog(summary: String) -> bool {
+        let result = rfd::AsyncMessageDialog::new()
         .set_title("File Renamer")
-        .set_description("Files has been renamed.")
-        .set_buttons(rfd::MessageButtons::Ok)
+        .set_description(summary)
+        .set_buttons(rfd::MessageButtons::OkCancelCustom(String::from("Save Report"), String::from("Close")))
         .show()
         .await;
+        result == MessageDialogResult::Custom(String::from("Save Report"))
 }
 
-#[derive(Debug, Clone)]//currently not used
-pub struct RenameError {
-    pub old_path: PathBuf,
-    pub error: String,
+fn report_summary(report: &ApplyReport) -> String {
+        let would = if report.dry_run { "would be " } else { "" };
+        let mut summary = if report.dry_run { String::from("Dry run, nothing was changed.\n") } else { String::new() };
+        summary.push_str(&format!("{} file(s) {}renamed.", report.renamed.len(), would));
+        if !report.moved.is_empty() {
+                summary.push_str(&format!("\n{} file(s) {}moved to another drive.", report.moved.len(), would));
+        }
+        if report.cancelled {
+                summary.push_str(&format!("\nCancelled, {} file(s) were left as they were.", report.skipped.len()));
+        }
+        if !report.failed.is_empty() {
+                summary.push_str(&format!("\n{} file(s) {}:", report.failed.len(), if report.dry_run { "would fail" } else { "failed" }));
+                for (path, error) in report.failed.iter().take(10) {
+                        summary.push_str(&format!("\n{}: {}", path.file_name().unwrap_or_default().to_string_lossy(), error));
+                }
+        }
+        summary
 }
+
+impl MetaColumn {
+        const ALL: [MetaColumn; 5] = [
+                MetaColumn::Modified,
+                MetaColumn::Created,
+                MetaColumn::Accessed,
+                MetaColumn::Size,
+                MetaColumn::Owner,
+        ];
+
+        fn label(&self) -> &'static str {
+                match self {
+                        MetaColumn::Modified => "Modified",
+                        MetaColumn::Created => "Created",
+                        MetaColumn::Accessed => "Accessed",
+                        MetaColumn::Size => "Size",
+                        MetaColumn::Owner => "Owner",
+                }
+        }
+
+        fn value(&self, meta: Option<&FileMeta>, time_zone: TimeZoneChoice) -> String {
+                let Some(meta) = meta else { return String::from("-"); };
+                let time = |field| meta.time(field)
+                        .and_then(|time| metadata::format_time(time, "%Y-%m-%d %H:%M", time_zone))
+                        .unwrap_or_else(|| String::from("-"));
+                match self {
+                        MetaColumn::Modified => time(TimeField::Modified),
+                        MetaColumn::Created => time(TimeField::Created),
+                        MetaColumn::Accessed => time(TimeField::Accessed),
+                        MetaColumn::Size => metadata::human_size(meta.size),
+                        MetaColumn::Owner => format!("{}:{}",
+                                meta.owner.as_deref().unwrap_or("-"),
+                                meta.group.as_deref().unwrap_or("-")),
+                }
+        }
+}
+
+impl std::fmt::Display for MetaColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.label())
+    }
+}
+
+impl std::fmt::Display for NormalForm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            NormalForm::Nfc => "NFC",
+            NormalForm::Nfd => "NFD",
+            NormalForm::Nfkc => "NFKC",
+            NormalForm::Nfkd => "NFKD",
+        })
+    }
+}
+
+impl std::fmt::Display for TargetProfile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TargetProfile::Linux => "Linux",
+            TargetProfile::Windows => "Windows / NTFS",
+            TargetProfile::Fat => "FAT32 / exFAT",
+            TargetProfile::MacOs => "macOS",
+        })
+    }
+}
+
+impl std::fmt::Display for SpaceConversion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SpaceConversion::Keep => "Keep separators",
+            SpaceConversion::ToSpaces => "_ . %20 to spaces",
+            SpaceConversion::ToUnderscores => "Spaces to _",
+            SpaceConversion::ToDots => "Spaces to .",
+            SpaceConversion::ToPercent20 => "Spaces to %20",
+        })
+    }
+}
+
+impl std::fmt::Display for LengthUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            LengthUnit::Characters => "Characters",
+            LengthUnit::Bytes => "Bytes",
+        })
+    }
+}
+
+impl std::fmt::Display for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SortKey::Manual => "Manual (drag rows)",
+            SortKey::Name => "Name",
+            SortKey::Modified => "Modified",
+            SortKey::Size => "Size",
+            SortKey::ExifDate => "EXIF date",
+            SortKey::Extension => "Extension",
+        })
+    }
+}
+
+impl std::fmt::Display for ConditionKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ConditionKind::Always => "All files",
+            ConditionKind::NameRegex => "Name matches regex",
+            ConditionKind::NameGlob => "Name matches glob",
+            ConditionKind::Extension => "Extension is one of",
+            ConditionKind::Size => "Size between",
+            ConditionKind::Modified => "Modified between",
+            ConditionKind::HasToken => "Token has a value",
+        })
+    }
+}
+
+impl std::fmt::Display for DuplicateHandling {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DuplicateHandling::Keep => "Rename as usual",
+            DuplicateHandling::Exclude => "Exclude from batch",
+            DuplicateHandling::Suffix => "Add _dup suffix",
+        })
+    }
+}
+
+impl std::fmt::Display for NumberTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            NumberTarget::First => "First number",
+            NumberTarget::Last => "Last number",
+            NumberTarget::All => "All numbers",
+        })
+    }
+}
+
+impl std::fmt::Display for NumberPadding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            NumberPadding::Keep => "Keep width",
+            NumberPadding::Fixed => "Pad to width",
+            NumberPadding::Widest => "Pad to largest",
+            NumberPadding::Strip => "Strip zeros",
+        })
+    }
+}
+
 impl std::fmt::Display for ActionOptions {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.write_str(match self {
@@ -513,6 +2570,16 @@ impl std::fmt::Display for ActionOptions {
             Self::RegexReplace => "Regex Replace",
             Self::Prefix => "Add Prefix",
             Self::Suffix => "Add Suffix",
+            Self::Template => "Template",
+            Self::Unicode => "Unicode / ASCII",
+            Self::MakeSafe => "Make Safe",
+            Self::Cleanup => "Clean Up",
+            Self::Truncate => "Truncate",
+            Self::Renumber => "Renumber",
+            Self::Import => "Import CSV",
+            Self::Script => "Script (Rhai)",
+            Self::Command => "External Command",
+            Self::Plugin => "Plugin",
             //Self::UpperCase => "UPPER CASE",
             //Self::LowerCase => "lower case",
         })
@@ -0,0 +1,127 @@
+use std::io::{Read, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs `command` through the shell with `input` on stdin and returns its stdout.
+/// The process is killed once `timeout` has passed.
+pub fn run(command: &str, input: &str, env: &[(&str, &str)], timeout: Duration) -> Result<String, String> {
+        let mut child = shell(command)
+                .envs(env.iter().copied())
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .map_err(|e| format!("unable to start command: {}", e))?;
+
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let input = input.to_owned();
+        // Written from a thread so a command that prints before reading cannot block on a full pipe
+        thread::spawn(move || { let _ = stdin.write_all(input.as_bytes()); });
+        let stdout = read_in_background(child.stdout.take().expect("stdout is piped"));
+        let stderr = read_in_background(child.stderr.take().expect("stderr is piped"));
+
+        let deadline = Instant::now() + timeout;
+        let output = match stdout.recv_timeout(timeout) {
+                Ok(output) => output,
+                Err(_) => return Err(kill(child, timeout)),
+        };
+        let status = loop {
+                match child.try_wait() {
+                        Ok(Some(status)) => break status,
+                        Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(2)),
+                        Ok(None) => return Err(kill(child, timeout)),
+                        Err(e) => return Err(e.to_string()),
+                }
+        };
+        if !status.success() {
+                let stderr = stderr.recv_timeout(Duration::from_millis(100)).unwrap_or_default();
+                let stderr = String::from_utf8_lossy(&stderr);
+                let reason = stderr.lines().find(|line| !line.trim().is_empty()).unwrap_or_default();
+                return Err(match status.code() {
+                        Some(code) if reason.is_empty() => format!("command failed with exit code {}", code),
+                        Some(code) => format!("command failed with exit code {}: {}", code, reason.trim()),
+                        None => String::from("command was terminated"),
+                });
+        }
+        Ok(String::from_utf8_lossy(&output).into_owned())
+}
+
+/// One process per input, several at a time. Each input is `(stdin, FILE_PATH)`.
+pub fn run_each(command: &str, inputs: &[(String, String)], timeout: Duration) -> Vec<Result<String, String>> {
+        let threads = thread::available_parallelism().map_or(4, |n| n.get());
+        let chunk_size = inputs.len().div_ceil(threads).max(1);
+        thread::scope(|scope| {
+                let workers: Vec<_> = inputs.chunks(chunk_size).map(|chunk| scope.spawn(move || {
+                        chunk.iter().map(|(input, path)| {
+                                let name = input.trim_end_matches('\n');
+                                run(command, input, &[("FILE_NAME", name), ("FILE_PATH", path)], timeout)
+                                        .and_then(|output| single_line(&output))
+                        }).collect::<Vec<_>>()
+                })).collect();
+                workers.into_iter()
+                        .flat_map(|worker| worker.join().expect("command thread panicked"))
+                        .collect()
+        })
+}
+
+/// One process for every input, one line in and one line out per file.
+pub fn run_batch(command: &str, inputs: &[String], timeout: Duration) -> Result<Vec<String>, String> {
+        let mut stdin = inputs.join("\n");
+        stdin.push('\n');
+        let output = run(command, &stdin, &[], timeout)?;
+        let lines: Vec<String> = output.lines().map(|line| line.trim_end_matches('\r').to_owned()).collect();
+        if lines.len() != inputs.len() {
+                return Err(format!("command printed {} line(s) for {} name(s)", lines.len(), inputs.len()));
+        }
+        Ok(lines)
+}
+
+/// The new name from the output of a per-file command, a trailing newline is ignored.
+fn single_line(output: &str) -> Result<String, String> {
+        let name = output.trim_end_matches(['\n', '\r']);
+        if name.contains('\n') {
+                Err(String::from("command printed more than one line"))
+        } else {
+                Ok(name.to_owned())
+        }
+}
+
+fn shell(command: &str) -> Command {
+        #[cfg(windows)]
+        {
+                let mut shell = Command::new("cmd");
+                shell.arg("/C").arg(command);
+                shell
+        }
+        #[cfg(not(windows))]
+        {
+                use std::os::unix::process::CommandExt;
+                let mut shell = Command::new("sh");
+                // Its own process group, so a timeout also ends whatever the shell started
+                shell.arg("-c").arg(command).process_group(0);
+                shell
+        }
+}
+
+fn read_in_background(mut pipe: impl Read + Send + 'static) -> mpsc::Receiver<Vec<u8>> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+                let mut output = Vec::new();
+                let _ = pipe.read_to_end(&mut output);
+                let _ = sender.send(output);
+        });
+        receiver
+}
+
+fn kill(mut child: Child, timeout: Duration) -> String {
+        #[cfg(unix)]
+        // SAFETY: only sends a signal to the process group `shell` created for the child
+        unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+        }
+        let _ = child.kill();
+        let _ = child.wait();
+        format!("command timed out after {}s", timeout.as_secs_f32())
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use regex::Regex;
+
+pub const DEFAULT_PRIMARY_EXTENSIONS: &str = "cr2, cr3, nef, arw, dng, raf, orf, rw2, mkv, mp4, mov, avi, heic, jpg, jpeg, png";
+pub const DEFAULT_RULES: &str = "<stem>.<lang>.srt, <stem>.<lang>.ass, <stem>.<lang>.sub";
+
+/// Link from a companion file to the file it follows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Companion {
+        /// Original path of the primary file.
+        pub primary: PathBuf,
+        /// Everything after the shared stem, e.g. `.xmp` or `.en.srt`.
+        pub suffix: String,
+}
+
+/// Decides which files travel together. Files sharing a stem are always grouped,
+/// rules like `<stem>.<lang>.srt` attach files whose stem has extra parts.
+#[derive(Debug, Clone)]
+pub struct CompanionRules {
+        primary_extensions: Vec<String>,
+        rules: Vec<Regex>,
+}
+
+impl CompanionRules {
+        /// Both inputs are comma separated lists, the extension order sets the primary priority.
+        pub fn new(primary_extensions: &str, rules: &str) -> Result<CompanionRules, String> {
+                let primary_extensions = primary_extensions
+                        .split(',')
+                        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                        .filter(|ext| !ext.is_empty())
+                        .collect();
+                let rules = rules
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|rule| !rule.is_empty())
+                        .map(rule_regex)
+                        .collect::<Result<_, _>>()?;
+                Ok(CompanionRules { primary_extensions, rules })
+        }
+
+        /// Returns the companion link for every path, `None` for primaries and ungrouped files.
+        pub fn group(&self, paths: &[&Path]) -> Vec<Option<Companion>> {
+                let stems: Vec<Option<String>> = paths.iter()
+                        .map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+                        .collect();
+                let mut groups: HashMap<(Option<&Path>, &str), Vec<usize>> = HashMap::new();
+                for (i, stem) in stems.iter().enumerate() {
+                        if let Some(stem) = stem {
+                                groups.entry((paths[i].parent(), stem)).or_default().push(i);
+                        }
+                }
+
+                // Files matched by a rule move to the group of their shorter stem
+                let rule_stems: Vec<Option<String>> = paths.iter().map(|path| {
+                        let file_name = file_name(path);
+                        self.rules.iter().find_map(|rule| rule.captures(&file_name).map(|caps| caps["stem"].to_owned()))
+                }).collect();
+                for (i, rule_stem) in rule_stems.iter().enumerate() {
+                        let (Some(rule_stem), Some(stem)) = (rule_stem, &stems[i]) else { continue; };
+                        let key = (paths[i].parent(), rule_stem.as_str());
+                        if rule_stem != stem && groups.contains_key(&key) {
+                                if let Some(members) = groups.get_mut(&(paths[i].parent(), stem.as_str())) {
+                                        members.retain(|member| *member != i);
+                                }
+                                groups.get_mut(&key).expect("checked above").push(i);
+                        }
+                }
+
+                let mut companions = vec![None; paths.len()];
+                for ((_, stem), members) in &groups {
+                        if members.len() < 2 {
+                                continue;
+                        }
+                        // Files moved in by a rule never become the primary
+                        let Some(&primary) = members.iter()
+                                .filter(|i| stems[**i].as_deref() == Some(*stem))
+                                .min_by_key(|i| (self.priority(paths[**i]), **i)) else { continue; };
+                        for &i in members {
+                                if i == primary {
+                                        continue;
+                                }
+                                companions[i] = Some(Companion {
+                                        primary: paths[primary].to_path_buf(),
+                                        suffix: file_name(paths[i])[stem.len()..].to_owned(),
+                                });
+                        }
+                }
+                companions
+        }
+
+        fn priority(&self, path: &Path) -> usize {
+                let ext = path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).unwrap_or_default();
+                self.primary_extensions.iter().position(|primary| *primary == ext).unwrap_or(usize::MAX)
+        }
+}
+
+/// New path of a companion whose primary file is renamed to `primary_new`.
+pub fn companion_path(primary_new: &Path, companion: &Companion) -> PathBuf {
+        let stem = primary_new.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        primary_new.with_file_name(format!("{}{}", stem, companion.suffix))
+}
+
+fn file_name(path: &Path) -> String {
+        path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+/// `<stem>` captures the shared stem, any other `<name>` matches one dot free part.
+fn rule_regex(rule: &str) -> Result<Regex, String> {
+        let mut pattern = String::from("(?i)^");
+        let mut rest = rule;
+        let mut has_stem = false;
+        while let Some(start) = rest.find('<') {
+                pattern.push_str(&regex::escape(&rest[..start]));
+                let Some(len) = rest[start..].find('>') else {
+                        return Err(format!("`{}`: missing closing `>`", rule));
+                };
+                if &rest[start + 1..start + len] == "stem" {
+                        pattern.push_str("(?<stem>.+?)");
+                        has_stem = true;
+                } else {
+                        pattern.push_str("[^.]+");
+                }
+                rest = &rest[start + len + 1..];
+        }
+        pattern.push_str(&regex::escape(rest));
+        pattern.push('$');
+
+        if !has_stem {
+                return Err(format!("`{}`: rule needs a <stem>", rule));
+        }
+        Regex::new(&pattern).map_err(|e| format!("`{}`: {}", rule, e))
+}
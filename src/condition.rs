@@ -0,0 +1,187 @@
+use std::path::Path;
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::action::FileContext;
+use crate::metadata;
+use crate::token::{self, TokenValues};
+
+/// What a step checks before it runs on a file.
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub enum ConditionKind {
+        #[default]
+        Always,
+        NameRegex,
+        NameGlob,
+        Extension,
+        Size,
+        Modified,
+        HasToken,
+}
+
+impl ConditionKind {
+        pub const ALL: [ConditionKind; 7] = [
+                ConditionKind::Always,
+                ConditionKind::NameRegex,
+                ConditionKind::NameGlob,
+                ConditionKind::Extension,
+                ConditionKind::Size,
+                ConditionKind::Modified,
+                ConditionKind::HasToken,
+        ];
+
+        /// Example input shown in the empty text field.
+        pub fn placeholder(&self) -> &'static str {
+                match self {
+                        ConditionKind::Always => "",
+                        ConditionKind::NameRegex => r"^IMG_\d+",
+                        ConditionKind::NameGlob => "*.JPG",
+                        ConditionKind::Extension => "mp4, mkv, mov",
+                        ConditionKind::Size => "1MiB..500MiB",
+                        ConditionKind::Modified => "2024-01-01..2024-12-31",
+                        ConditionKind::HasToken => "owner",
+                }
+        }
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+        Always,
+        Name(Regex),
+        Extension(Vec<String>),
+        Size(Option<u64>, Option<u64>),
+        /// Inclusive `%Y-%m-%d` bounds, compared as text in the chosen time zone.
+        Modified(Option<String>, Option<String>),
+        HasToken(String),
+}
+
+/// Optional filter of a step, files that do not match pass through the step untouched.
+#[derive(Debug, Clone)]
+pub struct Condition {
+        pub kind: ConditionKind,
+        pub input: String,
+        pub negate: bool,
+        matcher: Result<Matcher, String>,
+}
+
+impl Default for Condition {
+        fn default() -> Self {
+                Condition::new(ConditionKind::Always, String::new(), false)
+        }
+}
+
+impl Condition {
+        pub fn new(kind: ConditionKind, input: String, negate: bool) -> Condition {
+                let matcher = compile(kind, input.trim());
+                Condition { kind, input, negate, matcher }
+        }
+
+        pub fn error(&self) -> Option<&str> {
+                self.matcher.as_ref().err().map(String::as_str)
+        }
+
+        /// `current` is the path as it enters the step. An invalid condition matches nothing.
+        pub fn matches(&self, current: &Path, ctx: &FileContext) -> bool {
+                let Ok(matcher) = &self.matcher else { return false; };
+                let is_match = match matcher {
+                        Matcher::Always => return true,
+                        Matcher::Name(regex) => current.file_name()
+                                .is_some_and(|name| regex.is_match(&name.to_string_lossy())),
+                        Matcher::Extension(extensions) => current.extension()
+                                .is_some_and(|ext| extensions.contains(&ext.to_string_lossy().to_lowercase())),
+                        Matcher::Size(min, max) => ctx.meta.is_some_and(|meta| {
+                                min.is_none_or(|min| meta.size >= min) && max.is_none_or(|max| meta.size <= max)
+                        }),
+                        Matcher::Modified(from, to) => ctx.meta
+                                .and_then(|meta| meta.modified)
+                                .and_then(|time| metadata::format_time(time, "%Y-%m-%d", ctx.time_zone))
+                                .is_some_and(|date| {
+                                        from.as_ref().is_none_or(|from| date >= *from) && to.as_ref().is_none_or(|to| date <= *to)
+                                }),
+                        Matcher::HasToken(name) => {
+                                let values = TokenValues { ctx, current, captures: None };
+                                token::has_value(name, &values)
+                        },
+                };
+                is_match != self.negate
+        }
+}
+
+fn compile(kind: ConditionKind, input: &str) -> Result<Matcher, String> {
+        if kind != ConditionKind::Always && input.is_empty() {
+                return Err(String::from("condition is empty"));
+        }
+        match kind {
+                ConditionKind::Always => Ok(Matcher::Always),
+                ConditionKind::NameRegex => Regex::new(input).map(Matcher::Name).map_err(|e| e.to_string()),
+                ConditionKind::NameGlob => Regex::new(&glob_regex(input)).map(Matcher::Name).map_err(|e| e.to_string()),
+                ConditionKind::Extension => Ok(Matcher::Extension(input
+                        .split(',')
+                        .map(|ext| ext.trim().trim_start_matches('.').to_lowercase())
+                        .filter(|ext| !ext.is_empty())
+                        .collect())),
+                ConditionKind::Size => {
+                        let (min, max) = range(input, parse_size)?;
+                        Ok(Matcher::Size(min, max))
+                },
+                ConditionKind::Modified => {
+                        let (from, to) = range(input, |date| NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                                .map(|date| date.format("%Y-%m-%d").to_string())
+                                .map_err(|_| format!("invalid date `{}`, expected YYYY-MM-DD", date)))?;
+                        Ok(Matcher::Modified(from, to))
+                },
+                ConditionKind::HasToken => {
+                        let name = input.trim_matches(['{', '}']);
+                        if token::is_token_name(name) {
+                                Ok(Matcher::HasToken(name.to_owned()))
+                        } else {
+                                Err(format!("unknown token `{}`", name))
+                        }
+                },
+        }
+}
+
+/// `MIN..MAX` with either side optional, a single value matches exactly.
+fn range<T: Clone>(input: &str, parse: impl Fn(&str) -> Result<T, String>) -> Result<(Option<T>, Option<T>), String> {
+        let parse_bound = |bound: &str| {
+                let bound = bound.trim();
+                if bound.is_empty() { Ok(None) } else { parse(bound).map(Some) }
+        };
+        match input.split_once("..") {
+                Some((min, max)) => Ok((parse_bound(min)?, parse_bound(max)?)),
+                None => {
+                        let value = parse_bound(input)?;
+                        Ok((value.clone(), value))
+                },
+        }
+}
+
+/// Bytes with an optional `K`, `M` or `G` unit, units are powers of 1024 like the size column.
+fn parse_size(input: &str) -> Result<u64, String> {
+        let split = input.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(input.len());
+        let (number, unit) = input.split_at(split);
+        let number: f64 = number.parse().map_err(|_| format!("invalid size `{}`", input))?;
+        let factor = match unit.trim().to_lowercase().as_str() {
+                "" | "b" => 1u64,
+                "k" | "kb" | "kib" => 1 << 10,
+                "m" | "mb" | "mib" => 1 << 20,
+                "g" | "gb" | "gib" => 1 << 30,
+                _ => return Err(format!("unknown size unit `{}`", unit.trim())),
+        };
+        Ok((number * factor as f64) as u64)
+}
+
+/// `*` matches any run of characters, `?` one character, the rest is literal.
+fn glob_regex(glob: &str) -> String {
+        let mut pattern = String::from("^");
+        for c in glob.chars() {
+                match c {
+                        '*' => pattern.push_str(".*"),
+                        '?' => pattern.push('.'),
+                        c => pattern.push_str(&regex::escape(&c.to_string())),
+                }
+        }
+        pattern.push('$');
+        pattern
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// What happens to the extra copies when the batch is applied, the first file of a group is always kept.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum DuplicateHandling {
+        #[default]
+        Keep,
+        Exclude,
+        Suffix,
+}
+
+impl DuplicateHandling {
+        pub const ALL: [DuplicateHandling; 3] = [
+                DuplicateHandling::Keep,
+                DuplicateHandling::Exclude,
+                DuplicateHandling::Suffix,
+        ];
+}
+
+/// Place of a file in a group of files with the same content.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Duplicate {
+        /// Group number, starting at 1 in list order.
+        pub group: usize,
+        /// 0 for the first file of the group, then 1, 2, ... for the copies.
+        pub copy: usize,
+}
+
+/// Files that share their size with another file, only those need to be hashed.
+pub fn size_candidates(sizes: &[Option<u64>]) -> Vec<bool> {
+        let mut counts: HashMap<u64, usize> = HashMap::new();
+        for size in sizes.iter().flatten() {
+                *counts.entry(*size).or_default() += 1;
+        }
+        sizes.iter().map(|size| size.is_some_and(|size| counts[&size] > 1)).collect()
+}
+
+/// Groups `(size, digest)` pairs with the same content, files without a digest are never grouped.
+pub fn find_duplicates(files: &[(Option<u64>, Option<&str>)]) -> Vec<Option<Duplicate>> {
+        let mut groups: HashMap<(u64, &str), Vec<usize>> = HashMap::new();
+        for (i, file) in files.iter().enumerate() {
+                if let (Some(size), Some(digest)) = file {
+                        groups.entry((*size, *digest)).or_default().push(i);
+                }
+        }
+        let mut groups: Vec<Vec<usize>> = groups.into_values().filter(|members| members.len() > 1).collect();
+        groups.sort_by_key(|members| members[0]);
+
+        let mut duplicates = vec![None; files.len()];
+        for (group, members) in groups.iter().enumerate() {
+                for (copy, &i) in members.iter().enumerate() {
+                        duplicates[i] = Some(Duplicate { group: group + 1, copy });
+                }
+        }
+        duplicates
+}
+
+/// `photo.jpg` becomes `photo_dup1.jpg` for the first copy.
+pub fn dup_path(path: &Path, copy: usize) -> PathBuf {
+        let stem = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let name = match path.extension() {
+                Some(ext) => format!("{}_dup{}.{}", stem, copy, ext.to_string_lossy()),
+                None => format!("{}_dup{}", stem, copy),
+        };
+        path.with_file_name(name)
+}
@@ -0,0 +1,488 @@
+use std::collections::BTreeMap;
+use std::fs::{File, FileTimes};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::hash::{self, HashAlgorithm};
+
+/// What the renamer needs to know about a file or folder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Entry {
+        pub is_dir: bool,
+        pub len: u64,
+}
+
+/// The file system calls used to apply a plan, so a plan can also run against memory.
+pub trait FileSystem: Send + Sync {
+        fn entry(&self, path: &Path) -> io::Result<Entry>;
+
+        fn exists(&self, path: &Path) -> bool {
+                self.entry(path).is_ok()
+        }
+
+        /// Whether both paths name the same file, e.g. differently cased names on a case insensitive system.
+        fn same_file(&self, a: &Path, b: &Path) -> bool;
+
+        /// Every entry of a folder.
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>>;
+
+        fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+        fn rename(&self, old: &Path, new: &Path) -> io::Result<()>;
+
+        /// Copies `old` to `new`, which must not exist yet, with its permissions and times,
+        /// and flushes the copy to disk. A failed copy is removed again.
+        fn copy_new(&self, old: &Path, new: &Path) -> io::Result<()>;
+
+        fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+        /// Removes a folder, only when it is empty.
+        fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+        fn hash(&self, path: &Path, algorithm: HashAlgorithm) -> io::Result<String>;
+}
+
+/// Files in `folder`, without sub folders.
+pub fn files_in(fs: &dyn FileSystem, folder: &Path) -> Vec<PathBuf> {
+        fs.read_dir(folder)
+                .map(|entries| entries.into_iter()
+                        .filter(|path| fs.entry(path).is_ok_and(|entry| !entry.is_dir))
+                        .collect())
+                .unwrap_or_default()
+}
+
+/// The disk, through `std::fs`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealFs;
+
+impl FileSystem for RealFs {
+        fn entry(&self, path: &Path) -> io::Result<Entry> {
+                let meta = std::fs::metadata(path)?;
+                Ok(Entry { is_dir: meta.is_dir(), len: meta.len() })
+        }
+
+        fn same_file(&self, a: &Path, b: &Path) -> bool {
+                matches!((std::fs::canonicalize(a), std::fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+                std::fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.path())).collect()
+        }
+
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+                std::fs::create_dir_all(path)
+        }
+
+        fn rename(&self, old: &Path, new: &Path) -> io::Result<()> {
+                std::fs::rename(old, new)
+        }
+
+        fn copy_new(&self, old: &Path, new: &Path) -> io::Result<()> {
+                let meta = std::fs::metadata(old)?;
+                let mut source = File::open(old)?;
+                // create_new never replaces a file that showed up in the meantime
+                let mut target = std::fs::OpenOptions::new().write(true).create_new(true).open(new)?;
+                let result = (|| {
+                        io::copy(&mut source, &mut target)?;
+                        // Times are set after writing, which would change them again
+                        let mut times = FileTimes::new();
+                        if let Ok(modified) = meta.modified() {
+                                times = times.set_modified(modified);
+                        }
+                        if let Ok(accessed) = meta.accessed() {
+                                times = times.set_accessed(accessed);
+                        }
+                        target.set_times(times)?;
+                        target.set_permissions(meta.permissions())?;
+                        target.sync_all()
+                })();
+                if result.is_err() {
+                        drop(target);
+                        let _ = std::fs::remove_file(new);
+                }
+                result
+        }
+
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+                std::fs::remove_file(path)
+        }
+
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+                std::fs::remove_dir(path)
+        }
+
+        fn hash(&self, path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+                hash::hash_file(path, algorithm)
+        }
+}
+
+/// Calls of `MemoryFs` that can be made to fail.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operation {
+        Rename,
+        Copy,
+        RemoveFile,
+        CreateDir,
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+        /// `len` is kept apart so mirrored files need no content.
+        File { path: PathBuf, content: Vec<u8>, len: u64 },
+        Dir { path: PathBuf },
+}
+
+impl Node {
+        fn path(&self) -> &Path {
+                match self {
+                        Node::File { path, .. } | Node::Dir { path } => path,
+                }
+        }
+}
+
+/// File system kept in memory, for tests and dry runs. Folders of added files exist implicitly,
+/// and as on disk `rename` replaces an existing file.
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+        case_insensitive: bool,
+        /// Keys of folders whose entries follow other case rules, e.g. a mounted drive.
+        case_folders: Mutex<Vec<(String, bool)>>,
+        nodes: Mutex<BTreeMap<String, Node>>,
+        failures: Mutex<Vec<(Operation, String, io::ErrorKind)>>,
+}
+
+impl MemoryFs {
+        pub fn new(case_insensitive: bool) -> MemoryFs {
+                MemoryFs { case_insensitive, ..Default::default() }
+        }
+
+        /// Copy of the parts of the disk a plan touches: its files, whatever already sits at
+        /// the new names and the existing folders above them. Each folder keeps the case rules of
+        /// its drive, files fail to move out of or into folders the user cannot write to, and
+        /// moves to another drive fail the rename like on disk.
+        pub fn mirror(files: &[(PathBuf, PathBuf)]) -> MemoryFs {
+                let fs = MemoryFs::new(cfg!(any(windows, target_os = "macos")));
+                let mut probed: BTreeMap<PathBuf, Option<Probe>> = BTreeMap::new();
+                let mut probe = |folder: &Path| probed.entry(folder.to_path_buf()).or_insert_with(|| Probe::new(folder)).clone();
+                for (old, new) in files {
+                        let target = new.ancestors().skip(1).find(|folder| folder.is_dir());
+                        let target_probe = target.and_then(&mut probe);
+                        if let (Some(target), Some(probe)) = (target, &target_probe) {
+                                fs.set_case_insensitive(target, probe.case_insensitive);
+                        }
+                        if let Ok(meta) = std::fs::metadata(old) {
+                                let source_probe = old.parent().and_then(&mut probe);
+                                if let Some(probe) = &source_probe {
+                                        fs.set_case_insensitive(old.parent().expect("probed folder exists"), probe.case_insensitive);
+                                }
+                                fs.add_sized(old, meta.len());
+                                if let (Some(source), Some(target)) = (&source_probe, &target_probe) {
+                                        if source.device != target.device {
+                                                fs.fail(Operation::Rename, old, io::ErrorKind::CrossesDevices);
+                                                if !target.writable {
+                                                        fs.fail(Operation::Copy, old, io::ErrorKind::PermissionDenied);
+                                                }
+                                                if !source.writable {
+                                                        fs.fail(Operation::RemoveFile, old, io::ErrorKind::PermissionDenied);
+                                                }
+                                        } else if !source.writable || !target.writable {
+                                                fs.fail(Operation::Rename, old, io::ErrorKind::PermissionDenied);
+                                        }
+                                }
+                        }
+                        match std::fs::metadata(new) {
+                                Ok(meta) if meta.is_dir() => fs.add_dir(new),
+                                Ok(meta) => fs.add_sized(new, meta.len()),
+                                Err(_) => {
+                                        if let Some(target) = target {
+                                                fs.add_dir(target);
+                                        }
+                                },
+                        }
+                }
+                fs
+        }
+
+        /// Makes names inside `folder` and its sub folders ignore case, or not, whatever the default.
+        pub fn set_case_insensitive(&self, folder: &Path, case_insensitive: bool) {
+                let key = self.key(folder);
+                let mut folders = self.case_folders.lock().unwrap();
+                folders.retain(|(folder, _)| *folder != key);
+                folders.push((key, case_insensitive));
+        }
+
+        /// Adds a file and the folders above it.
+        pub fn add_file(&self, path: &Path, content: &[u8]) {
+                self.add_parents(path);
+                let node = Node::File { path: path.to_path_buf(), content: content.to_vec(), len: content.len() as u64 };
+                self.nodes.lock().unwrap().insert(self.key(path), node);
+        }
+
+        /// Adds a file of `len` bytes without content, sizes are all the copy check compares.
+        fn add_sized(&self, path: &Path, len: u64) {
+                self.add_parents(path);
+                let node = Node::File { path: path.to_path_buf(), content: Vec::new(), len };
+                self.nodes.lock().unwrap().insert(self.key(path), node);
+        }
+
+        pub fn add_dir(&self, path: &Path) {
+                self.add_parents(path);
+                self.nodes.lock().unwrap().entry(self.key(path)).or_insert_with(|| Node::Dir { path: path.to_path_buf() });
+        }
+
+        /// Makes `operation` on `path` fail with `kind`, e.g. `PermissionDenied` or `CrossesDevices`.
+        pub fn fail(&self, operation: Operation, path: &Path, kind: io::ErrorKind) {
+                self.failures.lock().unwrap().push((operation, self.key(path), kind));
+        }
+
+        /// Paths of every file, as cased when they were created.
+        pub fn files(&self) -> Vec<PathBuf> {
+                self.nodes.lock().unwrap().values()
+                        .filter(|node| matches!(node, Node::File { .. }))
+                        .map(|node| node.path().to_path_buf())
+                        .collect()
+        }
+
+        pub fn read(&self, path: &Path) -> Option<Vec<u8>> {
+                match self.nodes.lock().unwrap().get(&self.key(path)) {
+                        Some(Node::File { content, .. }) => Some(content.clone()),
+                        _ => None,
+                }
+        }
+
+        fn key(&self, path: &Path) -> String {
+                let folders = self.case_folders.lock().unwrap();
+                if folders.is_empty() {
+                        let path = path.to_string_lossy();
+                        return if self.case_insensitive { path.to_lowercase() } else { path.into_owned() };
+                }
+                // Each name follows the rules of the folder it is in
+                let mut key = PathBuf::new();
+                let mut case_insensitive = self.case_insensitive;
+                for component in path.components() {
+                        let name = component.as_os_str().to_string_lossy();
+                        key.push(if case_insensitive { name.to_lowercase() } else { name.into_owned() });
+                        let folder = key.to_string_lossy();
+                        if let Some((_, folder_case)) = folders.iter().find(|(key, _)| *key == folder) {
+                                case_insensitive = *folder_case;
+                        }
+                }
+                key.to_string_lossy().into_owned()
+        }
+
+        fn add_parents(&self, path: &Path) {
+                let mut nodes = self.nodes.lock().unwrap();
+                for folder in path.ancestors().skip(1).filter(|folder| !folder.as_os_str().is_empty()) {
+                        nodes.entry(self.key(folder)).or_insert_with(|| Node::Dir { path: folder.to_path_buf() });
+                }
+        }
+
+        fn check(&self, operation: Operation, path: &Path) -> io::Result<()> {
+                let key = self.key(path);
+                match self.failures.lock().unwrap().iter().find(|(op, failing, _)| *op == operation && *failing == key) {
+                        Some((_, _, kind)) => Err(io::Error::from(*kind)),
+                        None => Ok(()),
+                }
+        }
+
+        /// A missing parent is an error, except for the root.
+        fn check_parent(&self, nodes: &BTreeMap<String, Node>, path: &Path) -> io::Result<()> {
+                match path.parent().filter(|parent| !parent.as_os_str().is_empty() && parent.parent().is_some()) {
+                        Some(parent) => match nodes.get(&self.key(parent)) {
+                                Some(Node::Dir { .. }) => Ok(()),
+                                Some(Node::File { .. }) => Err(io::Error::from(io::ErrorKind::NotADirectory)),
+                                None => Err(io::Error::from(io::ErrorKind::NotFound)),
+                        },
+                        None => Ok(()),
+                }
+        }
+
+        /// Keys of the entries inside the folder with `key`.
+        fn children(nodes: &BTreeMap<String, Node>, key: &str) -> Vec<String> {
+                let prefix = format!("{}{}", key.trim_end_matches(std::path::MAIN_SEPARATOR), std::path::MAIN_SEPARATOR);
+                nodes.range(prefix.clone()..).map(|(key, _)| key).take_while(|key| key.starts_with(&prefix)).cloned().collect()
+        }
+}
+
+/// What `MemoryFs::mirror` needs to know about a folder on disk.
+#[derive(Debug, Clone)]
+struct Probe {
+        device: u64,
+        writable: bool,
+        case_insensitive: bool,
+}
+
+impl Probe {
+        fn new(folder: &Path) -> Option<Probe> {
+                let meta = std::fs::metadata(folder).ok()?;
+                Some(Probe {
+                        device: device(&meta, folder),
+                        writable: writable(folder, &meta),
+                        case_insensitive: case_insensitive(folder)
+                                .unwrap_or(cfg!(any(windows, target_os = "macos"))),
+                })
+        }
+}
+
+#[cfg(unix)]
+fn device(meta: &std::fs::Metadata, _folder: &Path) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        meta.dev()
+}
+
+/// Drives are told apart by their root, e.g. `C:\`.
+#[cfg(not(unix))]
+fn device(_meta: &std::fs::Metadata, folder: &Path) -> u64 {
+        use std::hash::{Hash, Hasher};
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        folder.components().next().hash(&mut hasher);
+        hasher.finish()
+}
+
+/// Asks the system, so owners, groups and the super user are taken into account.
+#[cfg(unix)]
+fn writable(folder: &Path, _meta: &std::fs::Metadata) -> bool {
+        use std::os::unix::ffi::OsStrExt;
+        let Ok(path) = std::ffi::CString::new(folder.as_os_str().as_bytes()) else { return false; };
+        // SAFETY: `path` is a valid NUL terminated string for the duration of the call
+        unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn writable(_folder: &Path, meta: &std::fs::Metadata) -> bool {
+        !meta.permissions().readonly()
+}
+
+/// Whether names in `folder` ignore case: an entry is looked up again under swapped case.
+/// `None` when the folder has no name with letters to try.
+fn case_insensitive(folder: &Path) -> Option<bool> {
+        let entries = std::fs::read_dir(folder).ok()?.filter_map(Result::ok).map(|entry| entry.path()).take(64);
+        for path in entries {
+                let name = path.file_name()?.to_string_lossy().into_owned();
+                let swapped: String = name.chars()
+                        .map(|c| if c.is_ascii_lowercase() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+                        .collect();
+                if swapped != name {
+                        return Some(same_entry(&path, &path.with_file_name(swapped)));
+                }
+        }
+        None
+}
+
+#[cfg(unix)]
+fn same_entry(a: &Path, b: &Path) -> bool {
+        use std::os::unix::fs::MetadataExt;
+        match (std::fs::symlink_metadata(a), std::fs::symlink_metadata(b)) {
+                (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+                _ => false,
+        }
+}
+
+#[cfg(not(unix))]
+fn same_entry(a: &Path, b: &Path) -> bool {
+        matches!((std::fs::canonicalize(a), std::fs::canonicalize(b)), (Ok(a), Ok(b)) if a == b)
+}
+
+impl FileSystem for MemoryFs {
+        fn entry(&self, path: &Path) -> io::Result<Entry> {
+                match self.nodes.lock().unwrap().get(&self.key(path)) {
+                        Some(Node::File { len, .. }) => Ok(Entry { is_dir: false, len: *len }),
+                        Some(Node::Dir { .. }) => Ok(Entry { is_dir: true, len: 0 }),
+                        None => Err(io::Error::from(io::ErrorKind::NotFound)),
+                }
+        }
+
+        fn same_file(&self, a: &Path, b: &Path) -> bool {
+                self.key(a) == self.key(b) && self.exists(a)
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+                let nodes = self.nodes.lock().unwrap();
+                let key = self.key(path);
+                if !matches!(nodes.get(&key), Some(Node::Dir { .. })) {
+                        return Err(io::Error::from(io::ErrorKind::NotFound));
+                }
+                Ok(MemoryFs::children(&nodes, &key).iter()
+                        .map(|child| nodes[child].path())
+                        .filter(|child| child.parent().map(|parent| self.key(parent)).as_ref() == Some(&key))
+                        .map(Path::to_path_buf)
+                        .collect())
+        }
+
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+                for folder in path.ancestors() {
+                        self.check(Operation::CreateDir, folder)?;
+                }
+                if let Some(Node::File { .. }) = self.nodes.lock().unwrap().get(&self.key(path)) {
+                        return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+                }
+                self.add_dir(path);
+                Ok(())
+        }
+
+        fn rename(&self, old: &Path, new: &Path) -> io::Result<()> {
+                self.check(Operation::Rename, old)?;
+                let mut nodes = self.nodes.lock().unwrap();
+                self.check_parent(&nodes, new)?;
+                let (old_key, new_key) = (self.key(old), self.key(new));
+                let Some(node) = nodes.remove(&old_key) else { return Err(io::Error::from(io::ErrorKind::NotFound)); };
+                let node = match node {
+                        Node::File { content, len, .. } => Node::File { path: new.to_path_buf(), content, len },
+                        Node::Dir { .. } => {
+                                // Everything inside a folder moves with it
+                                for child in MemoryFs::children(&nodes, &old_key) {
+                                        let mut moved = nodes.remove(&child).expect("child was listed");
+                                        let path = new.join(moved.path().strip_prefix(old).unwrap_or(moved.path()));
+                                        match &mut moved {
+                                                Node::File { path: child_path, .. } | Node::Dir { path: child_path } => *child_path = path.clone(),
+                                        }
+                                        nodes.insert(self.key(&path), moved);
+                                }
+                                Node::Dir { path: new.to_path_buf() }
+                        },
+                };
+                nodes.insert(new_key, node);
+                Ok(())
+        }
+
+        fn copy_new(&self, old: &Path, new: &Path) -> io::Result<()> {
+                self.check(Operation::Copy, old)?;
+                let mut nodes = self.nodes.lock().unwrap();
+                self.check_parent(&nodes, new)?;
+                if nodes.contains_key(&self.key(new)) {
+                        return Err(io::Error::from(io::ErrorKind::AlreadyExists));
+                }
+                let Some(Node::File { content, len, .. }) = nodes.get(&self.key(old)).cloned() else {
+                        return Err(io::Error::from(io::ErrorKind::NotFound));
+                };
+                nodes.insert(self.key(new), Node::File { path: new.to_path_buf(), content, len });
+                Ok(())
+        }
+
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+                self.check(Operation::RemoveFile, path)?;
+                let mut nodes = self.nodes.lock().unwrap();
+                match nodes.get(&self.key(path)) {
+                        Some(Node::File { .. }) => { nodes.remove(&self.key(path)); Ok(()) },
+                        Some(Node::Dir { .. }) => Err(io::Error::from(io::ErrorKind::IsADirectory)),
+                        None => Err(io::Error::from(io::ErrorKind::NotFound)),
+                }
+        }
+
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+                let mut nodes = self.nodes.lock().unwrap();
+                let key = self.key(path);
+                match nodes.get(&key) {
+                        Some(Node::Dir { .. }) if MemoryFs::children(&nodes, &key).is_empty() => { nodes.remove(&key); Ok(()) },
+                        Some(Node::Dir { .. }) => Err(io::Error::from(io::ErrorKind::DirectoryNotEmpty)),
+                        Some(Node::File { .. }) => Err(io::Error::from(io::ErrorKind::NotADirectory)),
+                        None => Err(io::Error::from(io::ErrorKind::NotFound)),
+                }
+        }
+
+        fn hash(&self, path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+                let content = self.read(path).ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))?;
+                hash::hash_reader(content.as_slice(), algorithm)
+        }
+}
@@ -0,0 +1,196 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use iced::futures::{SinkExt, Stream};
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HashAlgorithm {
+        Crc32,
+        Md5,
+        Sha1,
+        Sha256,
+        Blake3,
+}
+
+/// Digest of a file, valid as long as the file keeps the same modification time.
+#[derive(Debug, Clone)]
+pub struct CachedHash {
+        pub modified: Option<SystemTime>,
+        pub digest: String,
+}
+
+pub type HashCache = HashMap<(PathBuf, HashAlgorithm), CachedHash>;
+
+/// Files that could not be hashed, with the modification time they had and the error.
+/// They are not hashed again until they change.
+pub type HashFailures = HashMap<(PathBuf, HashAlgorithm), (Option<SystemTime>, String)>;
+
+#[derive(Debug, Clone)]
+pub enum HashEvent {
+        Hashed {
+                path: PathBuf,
+                algorithm: HashAlgorithm,
+                modified: Option<SystemTime>,
+                digest: Result<String, String>,
+        },
+        Finished,
+}
+
+impl HashAlgorithm {
+        pub const ALL: [HashAlgorithm; 5] = [
+                HashAlgorithm::Crc32,
+                HashAlgorithm::Md5,
+                HashAlgorithm::Sha1,
+                HashAlgorithm::Sha256,
+                HashAlgorithm::Blake3,
+        ];
+
+        /// Name used in tokens, e.g. `{sha256:8}`.
+        pub fn token(&self) -> &'static str {
+                match self {
+                        HashAlgorithm::Crc32 => "crc32",
+                        HashAlgorithm::Md5 => "md5",
+                        HashAlgorithm::Sha1 => "sha1",
+                        HashAlgorithm::Sha256 => "sha256",
+                        HashAlgorithm::Blake3 => "blake3",
+                }
+        }
+
+        pub fn from_token(name: &str) -> Option<HashAlgorithm> {
+                HashAlgorithm::ALL.into_iter().find(|algorithm| algorithm.token() == name)
+        }
+
+        /// Length of the digest in hex characters.
+        pub fn hex_len(&self) -> usize {
+                match self {
+                        HashAlgorithm::Crc32 => 8,
+                        HashAlgorithm::Md5 => 32,
+                        HashAlgorithm::Sha1 => 40,
+                        HashAlgorithm::Sha256 | HashAlgorithm::Blake3 => 64,
+                }
+        }
+}
+
+/// Hash algorithms referenced by the tokens of `input`.
+pub fn algorithms_in(input: &str) -> Vec<HashAlgorithm> {
+        let mut algorithms = Vec::new();
+        for token in input.split('{').skip(1).filter_map(|rest| rest.split_once('}')).map(|(token, _)| token) {
+                for alternative in token.split('|') {
+                        let name = alternative.split(':').next().unwrap_or_default().trim();
+                        if let Some(algorithm) = HashAlgorithm::from_token(name) {
+                                if !algorithms.contains(&algorithm) {
+                                        algorithms.push(algorithm);
+                                }
+                        }
+                }
+        }
+        algorithms
+}
+
+/// Cached digest of `path`, ignored when the file changed since it was hashed.
+pub fn lookup<'a>(cache: &'a HashCache, path: &Path, modified: Option<SystemTime>, algorithm: HashAlgorithm) -> Option<&'a str> {
+        cache.get(&(path.to_path_buf(), algorithm))
+                .filter(|cached| cached.modified == modified)
+                .map(|cached| cached.digest.as_str())
+}
+
+/// Reads the whole file and returns its lowercase hex digest, blocking.
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<String> {
+        hash_reader(File::open(path)?, algorithm)
+}
+
+/// Lowercase hex digest of everything `reader` returns.
+pub fn hash_reader(reader: impl Read, algorithm: HashAlgorithm) -> io::Result<String> {
+        Ok(hash_reader_all(reader, &[algorithm])?.remove(0))
+}
+
+/// Digests of the file for every algorithm, in the same order, reading it once.
+pub fn hash_file_all(path: &Path, algorithms: &[HashAlgorithm]) -> io::Result<Vec<String>> {
+        hash_reader_all(File::open(path)?, algorithms)
+}
+
+/// Digests of everything `reader` returns for every algorithm, in the same order.
+pub fn hash_reader_all(mut reader: impl Read, algorithms: &[HashAlgorithm]) -> io::Result<Vec<String>> {
+        let mut buffer = vec![0; 64 * 1024];
+        let mut hashers: Vec<Hasher> = algorithms.iter().map(|algorithm| Hasher::new(*algorithm)).collect();
+        loop {
+                let read = reader.read(&mut buffer)?;
+                if read == 0 {
+                        break;
+                }
+                for hasher in &mut hashers {
+                        hasher.update(&buffer[..read]);
+                }
+        }
+        Ok(hashers.into_iter().map(Hasher::finish).collect())
+}
+
+/// Hashes every `(path, modified, algorithms)` entry off the UI thread, one read per file and one event per digest.
+pub fn hash_files(files: Vec<(PathBuf, Option<SystemTime>, Vec<HashAlgorithm>)>) -> impl Stream<Item = HashEvent> {
+        iced::stream::channel(100, async move |mut output| {
+                for (path, modified, algorithms) in files {
+                        let (file, needed) = (path.clone(), algorithms.clone());
+                        let digests = tokio::task::spawn_blocking(move || hash_file_all(&file, &needed))
+                                .await
+                                .map_err(|e| e.to_string())
+                                .and_then(|result| result.map_err(|e| e.to_string()));
+                        for (i, algorithm) in algorithms.into_iter().enumerate() {
+                                let digest = digests.as_ref().map(|digests| digests[i].clone()).map_err(String::clone);
+                                let event = HashEvent::Hashed { path: path.clone(), algorithm, modified, digest };
+                                if output.send(event).await.is_err() {
+                                        return;
+                                }
+                        }
+                }
+                let _ = output.send(HashEvent::Finished).await;
+        })
+}
+
+enum Hasher {
+        Crc32(crc32fast::Hasher),
+        Md5(Md5),
+        Sha1(Sha1),
+        Sha256(Sha256),
+        Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+        fn new(algorithm: HashAlgorithm) -> Hasher {
+                match algorithm {
+                        HashAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+                        HashAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+                        HashAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+                        HashAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+                        HashAlgorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+                }
+        }
+
+        fn update(&mut self, data: &[u8]) {
+                match self {
+                        Hasher::Crc32(hasher) => hasher.update(data),
+                        Hasher::Md5(hasher) => hasher.update(data),
+                        Hasher::Sha1(hasher) => hasher.update(data),
+                        Hasher::Sha256(hasher) => hasher.update(data),
+                        Hasher::Blake3(hasher) => { hasher.update(data); },
+                }
+        }
+
+        fn finish(self) -> String {
+                match self {
+                        Hasher::Crc32(hasher) => format!("{:08x}", hasher.finalize()),
+                        Hasher::Md5(hasher) => to_hex(&hasher.finalize()),
+                        Hasher::Sha1(hasher) => to_hex(&hasher.finalize()),
+                        Hasher::Sha256(hasher) => to_hex(&hasher.finalize()),
+                        Hasher::Blake3(hasher) => hasher.finalize().to_hex().to_string(),
+                }
+        }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
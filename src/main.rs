@@ -3,14 +3,62 @@
 #![allow(unused)] // Disables all 'unused' related warnings (variables, code, imports)
 mod action;
 mod app;
+mod command;
+mod companion;
+mod condition;
+mod duplicate;
+mod filesystem;
+mod hash;
+mod mapping;
+mod metadata;
+mod pipeline;
+mod plan;
+mod plugin;
+mod preview;
+mod script;
+mod sort;
+mod token;
+mod validate;
+mod watch;
 
-use action::{Modify,Operation,InsertMode};
+use action::{CleanupOptions,LengthUnit,Modify,Operation,InsertMode,SpaceConversion,TruncateOptions,UnicodeOptions};
+use companion::CompanionRules;
+use validate::TargetProfile;
 use app::FileRenamerApp;
 use iced::{Size, Settings};
 use regex::Regex;
 
 fn main() -> iced::Result {
-    
+    // Headless watch mode: file-renamer --watch DIR --pipeline FILE [--allow-commands]
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(folder) = arg_value(&args, "--watch") {
+        let Some(pipeline) = arg_value(&args, "--pipeline") else {
+            eprintln!("--watch needs --pipeline FILE");
+            std::process::exit(2);
+        };
+        let allow_commands = args.iter().any(|arg| arg == "--allow-commands");
+        let result = pipeline::Pipeline::load(std::path::Path::new(pipeline)).and_then(|pipeline| {
+            if pipeline.has_commands() && !allow_commands {
+                return Err(String::from("the pipeline runs external commands, pass --allow-commands to run them"));
+            }
+            let stop = std::sync::atomic::AtomicBool::new(false);
+            watch::run(std::path::Path::new(folder), &pipeline, &stop, |event| {
+                // Failures go to stderr so scripts can tell them from renames
+                let line = format!("{} {}", chrono::Local::now().format("%H:%M:%S"), event);
+                if event.is_failure() {
+                    eprintln!("{}", line);
+                } else {
+                    println!("{}", line);
+                }
+            })
+        });
+        if let Err(error) = result {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
     iced::application(FileRenamerApp::default, FileRenamerApp::update, FileRenamerApp::view)
     .subscription(FileRenamerApp::subscription)
     .title("File Renamer")
@@ -26,11 +74,19 @@ fn main() -> iced::Result {
     
 }
 
+fn arg_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(String::as_str)
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::PathBuf;
 
-    use crate::action::Action;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::action::{Action, FileContext};
+    use crate::metadata::{FileMeta, TimeZoneChoice};
+    use crate::hash::{self, HashAlgorithm, HashCache};
 
     use super::*;
     #[test]
@@ -40,12 +96,12 @@ mod tests {
     modify.prefix_mode(&pattern);
     let mut test_path = PathBuf::from("/A/B/FooBar.txt");
 
-    modify.action(&mut test_path);
+    test_path = modify.action(&test_path);
     assert_eq!(test_path,PathBuf::from("/A/B/modifiedFooBar.txt"));
 
     modify.suffix_mode(&pattern);
 
-    modify.action(&mut test_path);
+    test_path = modify.action(&test_path);
     assert_eq!(test_path,PathBuf::from("/A/B/modifiedFooBarmodified.txt"));
 
     }
@@ -58,13 +114,13 @@ mod tests {
         .find_and_replace_op(&"Modified".to_string());
         let mut test_path = PathBuf::from("/A/B/FooBar.txt");
 
-        modify.action(&mut test_path);
+        test_path = modify.action(&test_path);
         assert_eq!(test_path,PathBuf::from("/A/B/ModifiedBar.txt"));
 
         modify
         .set_pattern("Bar")
         .find_and_replace_op(&"".to_string());
-        modify.action(&mut test_path);
+        test_path = modify.action(&test_path);
         assert_eq!(test_path,PathBuf::from("/A/B/Modified.txt"));
         
     }
@@ -77,7 +133,7 @@ mod tests {
     .regex_op(&"Modified".to_string());
 
     let mut test_path = PathBuf::from("/A/B/FooBar.txt");
-    modify.action(&mut test_path);
+    test_path = modify.action(&test_path);
 
     assert_eq!(test_path,PathBuf::from("/A/B/ModifiedBar.txt"));
     }
@@ -92,10 +148,1109 @@ mod tests {
     .regex_op(&"test".to_string());
 
     let mut test_path = PathBuf::from("/A/B/FooBar.txt");
-    modify.action(&mut test_path);
+    test_path = modify.action(&test_path);
+
+    assert_eq!(test_path,PathBuf::from("/A/B/FooBar.txt"));
+    }
+
+    #[test]
+    fn test_metadata_tokens() {
+    let meta = FileMeta {
+        modified: Some(UNIX_EPOCH + Duration::from_secs(1_714_521_600)),
+        size: 1536,
+        owner: Some(String::from("alice")),
+        ..Default::default()
+    };
+    let ctx = FileContext { meta: Some(&meta), time_zone: TimeZoneChoice::Utc, ..Default::default() };
+    let mut modify = Modify::new_affix();
+    modify.prefix_mode("{modified:%Y-%m-%d}_{size:human}_{owner}_");
 
+    let test_path = modify.action_with(&PathBuf::from("/A/B/FooBar.txt"), &ctx);
+    assert_eq!(test_path,PathBuf::from("/A/B/2024-05-01_1.5KiB_alice_FooBar.txt"));
+
+    // Tokens without a value are kept as typed
+    let test_path = Action::action(&modify, &PathBuf::from("/A/B/FooBar.txt"));
+    assert_eq!(test_path,PathBuf::from("/A/B/{modified:%Y-%m-%d}_{size:human}_{owner}_FooBar.txt"));
+    }
+
+    #[test]
+    fn test_template() {
+    let meta = FileMeta {
+        modified: Some(UNIX_EPOCH + Duration::from_secs(1_714_521_600)),
+        ..Default::default()
+    };
+    let original = PathBuf::from("/Photos/Paris/IMG_0042.jpg");
+    let ctx = FileContext {
+        original: Some(&original),
+        index: 2,
+        meta: Some(&meta),
+        time_zone: TimeZoneChoice::Utc,
+        hashes: None,
+    };
+    let mut modify = Modify::new_template();
+    modify.set_template(r"IMG_(?<num>\d+)", "{date:%Y-%m-%d}_{parent}_{counter:03}_{num}_{artist|Unknown}");
+    assert!(modify.validation_errors().is_empty());
+
+    let test_path = modify.action_with(&original, &ctx);
+    assert_eq!(test_path,PathBuf::from("/Photos/Paris/2024-05-01_Paris_003_0042_Unknown.jpg"));
+    }
+
+    #[test]
+    fn test_template_errors() {
+    use crate::action::INVALID_CHARS;
+    use crate::token;
+
+    let mut modify = Modify::new_template();
+    modify.set_template("", "{stem}_{artist}_{counter:x}_{1}");
+
+    assert_eq!(modify.validation_errors(), vec![
+        "{artist}: unknown token",
+        "{counter:x}: invalid width `x`",
+        "{1}: capture groups need a regex",
+    ]);
+    // An invalid template leaves the name unchanged
+    let test_path = modify.action(&PathBuf::from("/A/B/FooBar.txt"));
     assert_eq!(test_path,PathBuf::from("/A/B/FooBar.txt"));
+
+    // Templates that give no name are a per-file error, an empty template keeps names
+    modify.set_template("", "{size|}");
+    assert_eq!(modify.try_action(&PathBuf::from("/A/a.txt"), &FileContext::default()), Err(String::from("the template gives an empty name")));
+    modify.set_template("", "");
+    assert_eq!(modify.try_action(&PathBuf::from("/A/a.txt"), &FileContext::default()), Ok(PathBuf::from("/A/a.txt")));
+
+    // Fallback text is cleaned like the rest of the name, token arguments are kept
+    let clean = |text: &str| text.chars().filter(|c| !INVALID_CHARS.contains(c)).collect::<String>();
+    assert_eq!(token::clean_fallback("artist|a:b*?", None, clean), "artist|ab");
+    assert_eq!(token::clean_fallback("date:%H:%M", None, clean), "date:%H:%M");
+    assert_eq!(token::clean_fallback("artist|date:%H:%M", None, clean), "artist|date:%H:%M");
+    let regex = Regex::new(r"(?<take>\d+)").unwrap();
+    assert_eq!(token::clean_fallback("artist|take", Some(&regex), clean), "artist|take");
+
+    modify.set_template("", "{counter:999999999}");
+    assert_eq!(modify.validation_errors(), vec!["{counter:999999999}: width must be at most 32"]);
+
+    // Counters past i64::MAX are a per-file error, a fallback still applies
+    let ctx = FileContext { index: 2, ..Default::default() };
+    modify.set_template("", "{counter:,9223372036854775806}");
+    assert!(modify.validation_errors().is_empty());
+    assert_eq!(modify.try_action(&PathBuf::from("/A/a.txt"), &FileContext::default()), Ok(PathBuf::from("/A/9223372036854775806.txt")));
+    assert_eq!(modify.try_action(&PathBuf::from("/A/a.txt"), &ctx), Err(String::from("{counter:,9223372036854775806}: counter is out of range")));
+    assert_eq!(modify.action_with(&PathBuf::from("/A/a.txt"), &ctx), PathBuf::from("/A/a.txt"));
+    modify.set_template("", "{counter:,0,9223372036854775807|last}");
+    assert_eq!(modify.try_action(&PathBuf::from("/A/a.txt"), &ctx), Ok(PathBuf::from("/A/last.txt")));
+    }
+
+    #[test]
+    fn test_template_folders() {
+    let meta = FileMeta {
+        modified: Some(UNIX_EPOCH + Duration::from_secs(1_714_521_600)),
+        ..Default::default()
+    };
+    let ctx = FileContext { meta: Some(&meta), time_zone: TimeZoneChoice::Utc, ..Default::default() };
+    let mut modify = Modify::new_template();
+    modify.set_template("", "{year}/{month}/{stem}");
+
+    let test_path = modify.action_with(&PathBuf::from("/A/B/FooBar.txt"), &ctx);
+    assert_eq!(test_path,PathBuf::from("/A/B/2024/05/FooBar.txt"));
+
+    // Captures can never move a file out of its folder
+    modify.set_template(r"(.*)_", "{1}/{stem}");
+    let test_path = modify.action(&PathBuf::from("/A/B/.._FooBar.txt"));
+    assert_eq!(test_path,PathBuf::from("/A/B/.._FooBar.txt"));
+    }
+
+    #[test]
+    fn test_companion_grouping() {
+    let rules = CompanionRules::new(companion::DEFAULT_PRIMARY_EXTENSIONS, companion::DEFAULT_RULES).unwrap();
+    let files = [
+        PathBuf::from("/A/IMG_0001.xmp"),
+        PathBuf::from("/A/IMG_0001.JPG"),
+        PathBuf::from("/A/IMG_0001.CR2"),
+        PathBuf::from("/A/Movie.mkv"),
+        PathBuf::from("/A/Movie.en.srt"),
+        PathBuf::from("/A/Other.txt"),
+    ];
+    let paths: Vec<&std::path::Path> = files.iter().map(|p| p.as_path()).collect();
+    let links = rules.group(&paths);
+
+    let suffixes: Vec<Option<&str>> = links.iter()
+        .map(|link| link.as_ref().map(|link| link.suffix.as_str()))
+        .collect();
+    assert_eq!(suffixes, vec![Some(".xmp"), Some(".JPG"), None, None, Some(".en.srt"), None]);
+    assert_eq!(links[0].as_ref().unwrap().primary, files[2]);
+    assert_eq!(links[4].as_ref().unwrap().primary, files[3]);
+
+    let new_path = companion::companion_path(&PathBuf::from("/A/Paris_001.mkv"), links[4].as_ref().unwrap());
+    assert_eq!(new_path, PathBuf::from("/A/Paris_001.en.srt"));
+    }
+
+    #[test]
+    fn test_unicode() {
+    let nfd = PathBuf::from("/A/B/Cafe\u{301}.txt");
+    let modify = Modify::new_unicode(UnicodeOptions::default());
+    assert_eq!(modify.action(&nfd),PathBuf::from("/A/B/Caf\u{e9}.txt"));
+
+    let modify = Modify::new_unicode(UnicodeOptions { transliterate: true, ..Default::default() });
+    let test_path = modify.action(&PathBuf::from("/A/B/é ß Ж 東京.v2.txt"));
+    assert_eq!(test_path,PathBuf::from("/A/B/e ss Zh Dongjing.v2.txt"));
+
+    let modify = Modify::new_unicode(UnicodeOptions { slugify: true, ..Default::default() });
+    let test_path = modify.action(&PathBuf::from("/A/B/  Crème Brûlée (Final)_v2.JPG"));
+    assert_eq!(test_path,PathBuf::from("/A/B/creme-brulee-final-v2.JPG"));
+    }
+
+    #[test]
+    fn test_target_profiles() {
+    assert!(validate::check_name(TargetProfile::Linux, "CON.txt").is_empty());
+    assert_eq!(validate::check_name(TargetProfile::Windows, "CON.txt"), vec!["reserved device name"]);
+    assert_eq!(validate::check_name(TargetProfile::Fat, "a:b. "),
+        vec!["forbidden characters `:`", "ends with a space or dot"]);
+    assert_eq!(validate::check_name(TargetProfile::Linux, &"é".repeat(128)), vec!["too long (256 of 255 bytes)"]);
+
+    let modify = Modify::new_make_safe(TargetProfile::Windows);
+    let test_path = modify.action(&PathBuf::from("/A/B/CON.txt"));
+    assert_eq!(test_path,PathBuf::from("/A/B/_CON.txt"));
+    let test_path = modify.action(&PathBuf::from(format!("/A/B/{}?.txt", "x".repeat(300))));
+    assert_eq!(test_path,PathBuf::from(format!("/A/B/{}.txt", "x".repeat(251))));
+    // Shortening never leaves a trailing space or dot, or an empty stem
+    let test_path = modify.action(&PathBuf::from(format!("/A/B/{} {}.txt", "x".repeat(250), "y".repeat(20))));
+    assert_eq!(test_path,PathBuf::from(format!("/A/B/{}.txt", "x".repeat(250))));
+    assert_eq!(validate::make_safe(TargetProfile::Linux, &format!("a.{}", "e".repeat(300))).len(), 255);
+    assert_eq!(validate::make_safe(TargetProfile::Windows, ". . .txt"), "_.txt");
+    assert_eq!(validate::check_name(TargetProfile::Windows, "a:b|c:d|"), vec!["forbidden characters `:|`"]);
+
+    let files = [
+        (PathBuf::from("/A/one.txt"), PathBuf::from("/A/Same.txt")),
+        (PathBuf::from("/A/two.txt"), PathBuf::from("/A/same.txt")),
+    ];
+    let pairs: Vec<(&std::path::Path, &std::path::Path)> = files.iter().map(|(o, n)| (o.as_path(), n.as_path())).collect();
+    assert!(validate::validate_batch(TargetProfile::Linux, &pairs).iter().all(Vec::is_empty));
+    let problems = validate::validate_batch(TargetProfile::Windows, &pairs);
+    assert_eq!(problems[1], vec!["same new name as `one.txt`"]);
+
+    // Files on disk that are not renamed are never overwritten
+    let dir = std::env::temp_dir().join(format!("renamer_validate_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(dir.join("Taken.txt"), "").unwrap();
+    let files = [(dir.join("a.txt"), dir.join("Taken.txt")), (dir.join("b.txt"), dir.join("taken.txt")), (dir.join("c.txt"), dir.join("new/c.txt"))];
+    let pairs: Vec<(&std::path::Path, &std::path::Path)> = files.iter().map(|(o, n)| (o.as_path(), n.as_path())).collect();
+    let exists = vec![String::from("a file with this name already exists")];
+    assert_eq!(validate::validate_batch(TargetProfile::Linux, &pairs), vec![exists.clone(), vec![], vec![]]);
+    assert_eq!(validate::validate_batch(TargetProfile::Windows, &pairs)[0][0], exists[0]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_cleanup() {
+    let modify = Modify::new_cleanup(CleanupOptions {
+        spaces: SpaceConversion::ToSpaces,
+        strip_copy_markers: true,
+        remove_empty_brackets: true,
+        ..Default::default()
+    });
+    let test_path = modify.action(&PathBuf::from("/A/B/My__Holiday.Photo%20[ ]  () - Copy (2).jpg"));
+    assert_eq!(test_path,PathBuf::from("/A/B/My Holiday Photo.jpg"));
+
+    let modify = Modify::new_cleanup(CleanupOptions {
+        spaces: SpaceConversion::ToUnderscores,
+        collapse_separators: true,
+        ..Default::default()
+    });
+    let test_path = modify.action(&PathBuf::from("/A/B/ report -- final  v2 (1).pdf"));
+    assert_eq!(test_path,PathBuf::from("/A/B/report_-_final_v2_(1).pdf"));
+
+    let modify = Modify::new_cleanup(CleanupOptions { spaces: SpaceConversion::ToDots, ..Default::default() });
+    assert_eq!(modify.action(&PathBuf::from("/A/B/ my  photo .jpg")), PathBuf::from("/A/B/my.photo.jpg"));
+    let modify = Modify::new_cleanup(CleanupOptions { spaces: SpaceConversion::ToPercent20, ..Default::default() });
+    assert_eq!(modify.action(&PathBuf::from("/A/B/my photo.jpg")), PathBuf::from("/A/B/my%20photo.jpg"));
+    }
+
+    #[test]
+    fn test_truncate() {
+    let mut options = TruncateOptions { max_length: 12, ..Default::default() };
+    let modify = Modify::new_truncate(options);
+    let test_path = modify.action(&PathBuf::from("/A/B/Holiday in Paris 2024.jpg"));
+    assert_eq!(test_path,PathBuf::from("/A/B/Holiday in P.jpg"));
+
+    options.word_boundary = true;
+    options.ellipsis = true;
+    let modify = Modify::new_truncate(options);
+    let test_path = modify.action(&PathBuf::from("/A/B/Holiday in Paris 2024.jpg"));
+    assert_eq!(test_path,PathBuf::from("/A/B/Holiday in….jpg"));
+
+    // 5 bytes never splits the 4 byte emoji or the combining accent
+    let modify = Modify::new_truncate(TruncateOptions { max_length: 5, unit: LengthUnit::Bytes, ..Default::default() });
+    let test_path = modify.action(&PathBuf::from("/A/B/a\u{1F600}e\u{301}z.txt"));
+    assert_eq!(test_path,PathBuf::from("/A/B/a\u{1F600}.txt"));
+
+    // Names that become equal after truncation are reported as collisions
+    let modify = Modify::new_truncate(TruncateOptions { max_length: 6, ..Default::default() });
+    let files: Vec<(PathBuf, PathBuf)> = ["/A/Report_v1.pdf", "/A/Report_v2.pdf"].iter()
+        .map(|p| (PathBuf::from(p), modify.action(&PathBuf::from(p))))
+        .collect();
+    let pairs: Vec<(&std::path::Path, &std::path::Path)> = files.iter().map(|(o, n)| (o.as_path(), n.as_path())).collect();
+    let problems = validate::validate_batch(TargetProfile::Linux, &pairs);
+    assert_eq!(problems[1], vec!["same new name as `Report_v1.pdf`"]);
+    }
+
+    #[test]
+    fn test_hash_tokens() {
+    let dir = std::env::temp_dir().join(format!("renamer_hash_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let file = dir.join("abc.txt");
+    std::fs::write(&file, "abc").unwrap();
+    assert_eq!(hash::hash_file(&file, HashAlgorithm::Crc32).unwrap(), "352441c2");
+    assert_eq!(hash::hash_file(&file, HashAlgorithm::Md5).unwrap(), "900150983cd24fb0d6963f7d28e17f72");
+    assert_eq!(hash::hash_file(&file, HashAlgorithm::Sha256).unwrap(),
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_eq!(hash::hash_file_all(&file, &[HashAlgorithm::Md5, HashAlgorithm::Crc32]).unwrap(),
+        vec!["900150983cd24fb0d6963f7d28e17f72", "352441c2"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(hash::hash_file_all(&file, &[HashAlgorithm::Md5]).is_err());
+
+    assert_eq!(hash::algorithms_in("{sha256:8}_{stem}_{md5|crc32}"),
+        vec![HashAlgorithm::Sha256, HashAlgorithm::Md5, HashAlgorithm::Crc32]);
+
+    let original = PathBuf::from("/A/abc.txt");
+    let mut cache = HashCache::new();
+    cache.insert((original.clone(), HashAlgorithm::Sha256), hash::CachedHash {
+        modified: None,
+        digest: String::from("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+    });
+    let ctx = FileContext { original: Some(&original), hashes: Some(&cache), ..Default::default() };
+    let mut modify = Modify::new_template();
+    modify.set_template("", "{stem}_{sha256:8}");
+    assert!(modify.validation_errors().is_empty());
+    assert_eq!(modify.action_with(&original, &ctx), PathBuf::from("/A/abc_ba7816bf.txt"));
+
+    // Missing digests fall back like any other token
+    modify.set_template("", "{stem}_{md5|nohash}");
+    assert_eq!(modify.action_with(&original, &ctx), PathBuf::from("/A/abc_nohash.txt"));
+
+    modify.set_template("", "{sha256:99}");
+    assert_eq!(modify.validation_errors().len(), 1);
+    }
+
+    #[test]
+    fn test_duplicates() {
+    use crate::duplicate::{self, Duplicate};
+    let sizes = [Some(3), Some(5), Some(3), None, Some(3)];
+    assert_eq!(duplicate::size_candidates(&sizes), vec![true, false, true, false, true]);
+
+    // Same size alone is not enough, the content hash decides
+    let files = [(Some(3), Some("aaa")), (Some(5), None), (Some(3), Some("bbb")), (None, None), (Some(3), Some("aaa"))];
+    assert_eq!(duplicate::find_duplicates(&files), vec![
+        Some(Duplicate { group: 1, copy: 0 }), None, None, None, Some(Duplicate { group: 1, copy: 1 }),
+    ]);
+
+    assert_eq!(duplicate::dup_path(&PathBuf::from("/A/photo.final.jpg"), 2), PathBuf::from("/A/photo.final_dup2.jpg"));
+    assert_eq!(duplicate::dup_path(&PathBuf::from("/A/README"), 1), PathBuf::from("/A/README_dup1"));
+    }
+
+    #[test]
+    fn test_conditions() {
+    use crate::condition::{Condition, ConditionKind};
+    let meta = FileMeta {
+        modified: Some(UNIX_EPOCH + Duration::from_secs(1_714_521_600)),
+        size: 3 * 1024 * 1024,
+        ..Default::default()
+    };
+    let ctx = FileContext { meta: Some(&meta), time_zone: TimeZoneChoice::Utc, ..Default::default() };
+    let jpg = PathBuf::from("/A/IMG_0001.JPG");
+    let mp4 = PathBuf::from("/A/clip.mp4");
+
+    let glob = Condition::new(ConditionKind::NameGlob, String::from("*.JPG"), false);
+    assert!(glob.matches(&jpg, &ctx));
+    assert!(!glob.matches(&PathBuf::from("/A/IMG_0001.jpg"), &ctx));
+
+    let videos = Condition::new(ConditionKind::Extension, String::from("mkv, .MP4"), false);
+    assert!(videos.matches(&mp4, &ctx));
+    assert!(!videos.matches(&jpg, &ctx));
+    let not_videos = Condition::new(ConditionKind::Extension, String::from("mkv, mp4"), true);
+    assert!(not_videos.matches(&jpg, &ctx));
+
+    assert!(Condition::new(ConditionKind::NameRegex, String::from(r"^IMG_\d+"), false).matches(&jpg, &ctx));
+    assert!(Condition::new(ConditionKind::Size, String::from("1MiB..5M"), false).matches(&jpg, &ctx));
+    assert!(!Condition::new(ConditionKind::Size, String::from("..1MiB"), false).matches(&jpg, &ctx));
+    assert!(Condition::new(ConditionKind::Modified, String::from("2024-05-01.."), false).matches(&jpg, &ctx));
+    assert!(!Condition::new(ConditionKind::Modified, String::from("2024-01-01..2024-04-30"), false).matches(&jpg, &ctx));
+    assert!(!Condition::new(ConditionKind::HasToken, String::from("owner"), false).matches(&jpg, &ctx));
+
+    // Invalid conditions report an error and match nothing
+    let invalid = Condition::new(ConditionKind::Size, String::from("10 parsecs"), false);
+    assert!(invalid.error().is_some());
+    assert!(!invalid.matches(&jpg, &ctx));
+    assert!(Condition::new(ConditionKind::HasToken, String::from("artist"), false).error().is_some());
+    }
+
+    #[test]
+    fn test_sort() {
+    use crate::sort;
+    let mut names = vec!["img10.jpg", "IMG2.jpg", "img1.jpg", "img02.jpg", "a.jpg"];
+    names.sort_by(|a, b| sort::natural_cmp(a, b));
+    assert_eq!(names, vec!["a.jpg", "img1.jpg", "IMG2.jpg", "img02.jpg", "img10.jpg"]);
+
+    let mut rows = vec!['a', 'b', 'c', 'd'];
+    sort::move_item(&mut rows, 0, 2);
+    assert_eq!(rows, vec!['b', 'c', 'a', 'd']);
+    sort::move_item(&mut rows, 3, 0);
+    assert_eq!(rows, vec!['d', 'b', 'c', 'a']);
+
+    assert_eq!(crate::metadata::exif_date(std::path::Path::new("Cargo.toml")), None);
+    }
+
+    #[test]
+    fn test_renumber() {
+    use crate::action::{NumberPadding, NumberTarget, RenumberOptions};
+    let files: Vec<PathBuf> = ["/A/scan1.png", "/A/scan12.png", "/A/scan120.png"].iter().map(PathBuf::from).collect();
+    let inputs: Vec<&std::path::Path> = files.iter().map(PathBuf::as_path).collect();
+
+    let mut modify = Modify::new_renumber(RenumberOptions { padding: NumberPadding::Widest, ..Default::default() });
+    modify.prepare(&inputs);
+    assert_eq!(modify.action(&files[0]), PathBuf::from("/A/scan001.png"));
+    assert_eq!(modify.action(&files[2]), PathBuf::from("/A/scan120.png"));
+
+    let modify = Modify::new_renumber(RenumberOptions { padding: NumberPadding::Strip, ..Default::default() });
+    assert_eq!(modify.action(&PathBuf::from("/A/IMG_0042.jpg")), PathBuf::from("/A/IMG_42.jpg"));
+
+    // Numbers are shifted as integers, the original width is kept
+    let modify = Modify::new_renumber(RenumberOptions { target: NumberTarget::Last, offset: 12, ..Default::default() });
+    assert_eq!(modify.action(&PathBuf::from("/A/Show S01E09.mkv")), PathBuf::from("/A/Show S01E21.mkv"));
+
+    let modify = Modify::new_renumber(RenumberOptions {
+        target: NumberTarget::All, padding: NumberPadding::Fixed, width: 3, ..Default::default()
+    });
+    assert_eq!(modify.action(&PathBuf::from("/A/v2 part7.txt")), PathBuf::from("/A/v002 part007.txt"));
+
+    // Results below zero leave the number untouched
+    let modify = Modify::new_renumber(RenumberOptions { offset: -5, ..Default::default() });
+    assert_eq!(modify.action(&PathBuf::from("/A/track3.mp3")), PathBuf::from("/A/track3.mp3"));
+    }
+
+    #[test]
+    fn test_mapping() {
+    use crate::mapping::{self, Mapping};
+    use std::path::Path;
+    let csv = "old,new\nIMG_0001.jpg,Beach.jpg\n/B/scan.png,\"Scan, page 1.png\"\nmissing.txt,gone.txt\n";
+    let mapping = Mapping::parse(csv).unwrap();
+    assert_eq!(mapping.rows.len(), 3);
+    assert_eq!(mapping.lookup(Path::new("/A/IMG_0001.jpg")), Some("Beach.jpg"));
+    assert_eq!(mapping.lookup(Path::new("/B/scan.png")), Some("Scan, page 1.png"));
+    assert_eq!(mapping.lookup(Path::new("/C/scan.png")), None);
+    assert_eq!(mapping.unmatched(&[Path::new("/A/IMG_0001.jpg"), Path::new("/B/scan.png")]), vec!["missing.txt"]);
+
+    let tsv = Mapping::parse("a.txt\tb/c.txt\n").unwrap();
+    let modify = Modify::new_import(tsv);
+    assert_eq!(modify.action(&PathBuf::from("/A/a.txt")), PathBuf::from("/A/b/c.txt"));
+    assert!(Mapping::parse("a.txt\n").is_err());
+
+    // Imported names lose invalid characters and cannot climb out of the folder
+    let mapping = Mapping::parse("a.txt,\"what?: <new>.txt\"\nb.txt,../../etc/b.txt\n").unwrap();
+    assert_eq!(mapping.lookup(Path::new("/A/a.txt")), Some("what new.txt"));
+    assert_eq!(mapping.lookup(Path::new("/A/b.txt")), Some("etc/b.txt"));
+    assert!(Mapping::parse("a.txt,\"???\"\n").is_err());
+
+    let plan = mapping::export_plan(&[(Path::new("/A/a b.txt"), Path::new("/A/c,d.txt"), String::from("ok"))]).unwrap();
+    assert_eq!(plan, "old_path,new_path,status\n/A/a b.txt,\"/A/c,d.txt\",ok\n");
+    }
+
+    #[test]
+    fn test_plan_scripts() {
+    use crate::plan::{self, ScriptKind};
+    let p = |name: &str| PathBuf::from(format!("/A/{}", name));
+
+    // Chains run back to front, unchanged files are dropped
+    let steps = plan::order(&[(p("a"), p("b")), (p("b"), p("c")), (p("d"), p("d"))]);
+    assert_eq!(steps, vec![(p("b"), p("c")), (p("a"), p("b"))]);
+
+    // Swaps go through a temporary name
+    let steps = plan::order(&[(p("a"), p("b")), (p("b"), p("a"))]);
+    assert_eq!(steps, vec![(p("a"), p(".a.rename-1")), (p("b"), p("a")), (p(".a.rename-1"), p("b"))]);
+
+    let dir = std::env::temp_dir().join(format!("renamer_plan_{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (first, second) = (dir.join("it's one.txt"), dir.join("$two `x`.txt"));
+    std::fs::write(&first, "1").unwrap();
+    std::fs::write(&second, "2").unwrap();
+    let steps = plan::order(&[(first.clone(), second.clone()), (second.clone(), first.clone()),
+        (dir.join("missing"), dir.join("sub/new"))]);
+    let script = dir.join("rename.sh");
+    std::fs::write(&script, plan::script(ScriptKind::Shell, &steps)).unwrap();
+    let output = std::process::Command::new("sh").arg(&script).output().unwrap();
+    // The missing file fails, the swap still happens
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("missing"), "{}", stderr);
+    assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+    assert_eq!(std::fs::read_to_string(&first).unwrap(), "2");
+    assert_eq!(std::fs::read_to_string(&second).unwrap(), "1");
+
+    let undo = dir.join("rename-undo.sh");
+    let done: Vec<(PathBuf, PathBuf)> = steps.into_iter().filter(|(old, _)| *old != dir.join("missing")).collect();
+    std::fs::write(&undo, plan::script(ScriptKind::Shell, &plan::undo(&done))).unwrap();
+    let output = std::process::Command::new("sh").arg(&undo).output().unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    assert_eq!(std::fs::read_to_string(&first).unwrap(), "1");
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    let script = String::from_utf8(plan::script(ScriptKind::PowerShell, &[(p("it's"), p("b"))])).unwrap();
+    assert!(script.contains("Move-Item -LiteralPath '/A/it''s' -Destination '/A/b'"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_move_across() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        use crate::filesystem::RealFs;
+        use crate::plan::{self, Transfer};
+
+        let dir = std::env::temp_dir().join(format!("renamer_move_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let (old, new) = (dir.join("a.txt"), dir.join("b.txt"));
+        std::fs::write(&old, "content").unwrap();
+        let modified = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        std::fs::File::options().write(true).open(&old).unwrap()
+            .set_times(std::fs::FileTimes::new().set_modified(modified)).unwrap();
+        std::fs::set_permissions(&old, std::fs::Permissions::from_mode(0o640)).unwrap();
+
+        plan::move_across(&RealFs, &old, &new, true).unwrap();
+        assert!(!old.exists());
+        assert_eq!(std::fs::read_to_string(&new).unwrap(), "content");
+        let meta = std::fs::metadata(&new).unwrap();
+        assert_eq!(meta.modified().unwrap(), modified);
+        assert_eq!(meta.permissions().mode() & 0o777, 0o640);
+
+        // An existing target is never replaced and the original stays
+        std::fs::write(&old, "other").unwrap();
+        assert!(plan::move_across(&RealFs, &old, &new, false).is_err());
+        assert_eq!(std::fs::read_to_string(&new).unwrap(), "content");
+        assert!(old.exists());
+
+        // A real move to another file system when the machine has one
+        let other = PathBuf::from("/dev/shm");
+        if other.is_dir() && std::fs::metadata(&other).unwrap().dev() != std::fs::metadata(&dir).unwrap().dev() {
+            let target = other.join(format!("renamer_move_{}.txt", std::process::id()));
+            assert_eq!(plan::rename_checked(&RealFs, &old, &target, true), Ok(Transfer::Moved));
+            assert_eq!(std::fs::read_to_string(&target).unwrap(), "other");
+            std::fs::remove_file(&target).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
+    #[test]
+    fn test_memory_fs() {
+        use std::io::ErrorKind;
+        use std::path::Path;
+        use std::sync::atomic::AtomicBool;
+        use crate::filesystem::{self, FileSystem, MemoryFs, Operation};
+        use crate::plan::{self, ApplyOptions};
+
+        let p = |name: &str| PathBuf::from(format!("/A/{}", name));
+        let fs = MemoryFs::new(true);
+        for name in ["a.txt", "b.txt", "c.txt", "d.txt", "e.txt"] {
+            fs.add_file(&p(name), name.as_bytes());
+        }
+        fs.add_file(&p("taken.txt"), b"taken");
+        fs.fail(Operation::Rename, &p("c.txt"), ErrorKind::PermissionDenied);
+        fs.fail(Operation::Rename, &p("d.txt"), ErrorKind::CrossesDevices);
+        fs.fail(Operation::RemoveFile, &p("e.txt"), ErrorKind::PermissionDenied);
+        fs.fail(Operation::Rename, &p("e.txt"), ErrorKind::CrossesDevices);
+
+        let files = [
+            (p("a.txt"), p("A.TXT")),
+            (p("b.txt"), p("TAKEN.txt")),
+            (p("c.txt"), p("c2.txt")),
+            (p("d.txt"), p("sub/d.txt")),
+            (p("e.txt"), p("e2.txt")),
+        ];
+        let options = ApplyOptions { verify_hash: true, ..Default::default() };
+        let report = plan::apply(&fs, &files, options, &AtomicBool::new(false), |_, _, _| {});
+        // Case only renames work on a case insensitive system, other names are never replaced
+        assert_eq!(report.renamed, vec![(p("a.txt"), p("A.TXT"))]);
+        assert_eq!(report.moved, vec![(p("d.txt"), p("sub/d.txt"))]);
+        assert_eq!(fs.read(&p("sub/d.txt")).unwrap(), b"d.txt");
+        let failed: Vec<&Path> = report.failed.iter().map(|(old, _)| old.as_path()).collect();
+        assert_eq!(failed.len(), 3);
+        assert!(report.failed.iter().any(|(old, error)| *old == p("b.txt") && error.contains("already exists")));
+        assert!(report.failed.iter().any(|(old, error)| *old == p("c.txt") && error.contains("permission denied")));
+        // A move that cannot delete its original leaves no copy behind
+        assert!(fs.exists(&p("e.txt")) && !fs.exists(&p("e2.txt")));
+        assert!(filesystem::files_in(&fs, Path::new("/A")).contains(&p("A.TXT")));
+
+        // A dry run reports the collision with a file on disk that is not in the list
+        let dir = std::env::temp_dir().join(format!("renamer_dry_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("x"), "x").unwrap();
+        std::fs::write(dir.join("y"), "y").unwrap();
+        std::fs::write(dir.join("other"), "other").unwrap();
+        let files = [(dir.join("x"), dir.join("other")), (dir.join("y"), dir.join("new/y"))];
+        let mirror = MemoryFs::mirror(&files);
+        let options = ApplyOptions { dry_run: true, ..Default::default() };
+        let report = plan::apply(&mirror, &files, options, &AtomicBool::new(false), |_, _, _| {});
+        assert!(report.dry_run);
+        assert_eq!(report.renamed, vec![(dir.join("y"), dir.join("new/y"))]);
+        assert_eq!(report.failed.len(), 1);
+        assert!(dir.join("x").exists() && dir.join("y").exists() && !dir.join("new").exists());
+
+        // The mirror keeps the case rules of each folder and sees moves to another drive
+        let mirror = MemoryFs::mirror(&[(dir.join("x"), dir.join("z"))]);
+        let on_disk = dir.join("X").exists();
+        assert_eq!(mirror.same_file(&dir.join("x"), &dir.join("X")), on_disk);
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let other_drive = Path::new("/dev/shm");
+            let device = |path: &Path| std::fs::metadata(path).map(|meta| meta.dev()).ok();
+            if device(other_drive).is_some_and(|other| Some(other) != device(&dir)) {
+                let files = [(dir.join("x"), other_drive.join(format!("renamer_dry_{}", std::process::id())))];
+                let report = plan::apply(&MemoryFs::mirror(&files), &files, options, &AtomicBool::new(false), |_, _, _| {});
+                assert_eq!(report.moved, files.to_vec());
+                assert!(!files[0].1.exists());
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        // Folders can follow other case rules than the rest, e.g. a mounted drive
+        let fs = MemoryFs::new(false);
+        fs.set_case_insensitive(Path::new("/B"), true);
+        fs.add_file(Path::new("/A/x"), b"x");
+        fs.add_file(Path::new("/B/x"), b"x");
+        assert!(!fs.exists(Path::new("/A/X")));
+        assert!(fs.same_file(Path::new("/B/x"), Path::new("/B/X")));
+        assert_eq!(filesystem::files_in(&fs, Path::new("/b")), Vec::<PathBuf>::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_list_editing() {
+        use crate::app::{FileEntry, Message};
+
+        // `update` starts the preview timer, which needs a runtime
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let _guard = runtime.enter();
+        let dir = std::env::temp_dir().join(format!("renamer_list_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+        std::fs::write(dir.join("b.txt"), "b").unwrap();
+        std::os::unix::fs::symlink(dir.join("a.txt"), dir.join("link.txt")).unwrap();
+
+        // The same file through a link or `..` is only listed once
+        let mut app = FileRenamerApp::default();
+        let entries = [dir.join("a.txt"), dir.join("sub/../b.txt"), dir.join("link.txt"), dir.join("b.txt")]
+            .into_iter().map(FileEntry::new).collect();
+        let _ = app.update(Message::EntriesRead(0, entries));
+        let listed: Vec<&PathBuf> = app.files().iter().map(|entry| &entry.old_path).collect();
+        assert_eq!(listed, vec![&dir.join("a.txt"), &dir.join("sub/../b.txt")]);
+        let _ = app.update(Message::EntriesRead(0, vec![FileEntry::new(dir.join("sub/../a.txt"))]));
+        assert_eq!(app.files().len(), 2);
+
+        let _ = app.update(Message::SelectRow(0, true));
+        let _ = app.update(Message::RemoveSelected);
+        assert_eq!(app.files().len(), 1);
+        assert!(!app.files()[0].selected);
+
+        // Files read for the list before it was cleared are dropped
+        let _ = app.update(Message::ClearList);
+        assert!(app.files().is_empty());
+        let _ = app.update(Message::EntriesRead(0, vec![FileEntry::new(dir.join("a.txt"))]));
+        assert!(app.files().is_empty());
+
+        // A dropped folder replaces the list once per drop, paths that arrive late are added
+        let drop = |app: &mut FileRenamerApp, path: PathBuf, hover: bool| {
+            if hover {
+                let _ = app.update(Message::Event(iced::Event::Window(iced::window::Event::FileHovered(path.clone()))));
+            }
+            let _ = app.update(Message::Event(iced::Event::Window(iced::window::Event::FileDropped(path))));
+            let _ = app.update(Message::DropFinished);
+        };
+        let _ = app.update(Message::EntriesRead(1, vec![FileEntry::new(dir.join("b.txt"))]));
+        drop(&mut app, dir.join("sub"), true);
+        assert!(app.files().is_empty());
+        let _ = app.update(Message::EntriesRead(2, vec![FileEntry::new(dir.join("a.txt"))]));
+        drop(&mut app, dir.join("sub"), false);
+        assert_eq!(app.files().len(), 1);
+        drop(&mut app, dir.join("sub"), true);
+        assert!(app.files().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_collisions() {
+        use std::io::ErrorKind;
+        use std::sync::atomic::AtomicBool;
+        use std::path::Path;
+        use crate::filesystem::{Entry, FileSystem, MemoryFs, Operation};
+        use crate::plan;
+
+        // Renames that take a while, so parallel moves to one name would both pass the exists check
+        struct SlowFs(MemoryFs);
+        impl FileSystem for SlowFs {
+            fn entry(&self, path: &Path) -> std::io::Result<Entry> { self.0.entry(path) }
+            fn same_file(&self, a: &Path, b: &Path) -> bool { self.0.same_file(a, b) }
+            fn read_dir(&self, path: &Path) -> std::io::Result<Vec<PathBuf>> { self.0.read_dir(path) }
+            fn create_dir_all(&self, path: &Path) -> std::io::Result<()> { self.0.create_dir_all(path) }
+            fn rename(&self, old: &Path, new: &Path) -> std::io::Result<()> {
+                std::thread::sleep(Duration::from_millis(20));
+                self.0.rename(old, new)
+            }
+            fn copy_new(&self, old: &Path, new: &Path) -> std::io::Result<()> { self.0.copy_new(old, new) }
+            fn remove_file(&self, path: &Path) -> std::io::Result<()> { self.0.remove_file(path) }
+            fn remove_dir(&self, path: &Path) -> std::io::Result<()> { self.0.remove_dir(path) }
+            fn hash(&self, path: &Path, algorithm: HashAlgorithm) -> std::io::Result<String> { self.0.hash(path, algorithm) }
+        }
+
+        // Rows with the same new name, all but one fail instead of replacing each other
+        let p = |name: &str| PathBuf::from(format!("/A/{}", name));
+        let fs = SlowFs(MemoryFs::new(false));
+        let files: Vec<(PathBuf, PathBuf)> = (0..8).map(|i| (p(&format!("f{}", i)), p("same"))).collect();
+        for (old, _) in &files {
+            fs.0.add_file(old, b"f");
+        }
+        let report = plan::apply(&fs, &files, Default::default(), &AtomicBool::new(false), |_, _, _| {});
+        assert_eq!(report.renamed.len(), 1);
+        assert_eq!(report.failed.len(), 7);
+        assert!(report.failed.iter().all(|(_, error)| error.contains("already exists")));
+        assert_eq!(fs.0.files().len(), 8);
+
+        // Names that only differ in case collide on a case insensitive system
+        let fs = MemoryFs::new(true);
+        fs.add_file(&p("x"), b"x");
+        fs.add_file(&p("y"), b"y");
+        let report = plan::apply(&fs, &[(p("x"), p("A.txt")), (p("y"), p("a.txt"))], Default::default(), &AtomicBool::new(false), |_, _, _| {});
+        assert_eq!(report.renamed.len(), 1);
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(fs.files().len(), 2);
+
+        // A file that cannot leave its temporary name is reported where it is
+        let fs = MemoryFs::new(false);
+        fs.add_file(&p("a"), b"a");
+        fs.add_file(&p("b"), b"b");
+        let temp = p(".a.rename-1");
+        fs.fail(Operation::Rename, &temp, ErrorKind::PermissionDenied);
+        let report = plan::apply(&fs, &[(p("a"), p("b")), (p("b"), p("a"))], Default::default(), &AtomicBool::new(false), |_, _, _| {});
+        assert_eq!(report.renamed, vec![(p("b"), p("a"))]);
+        assert_eq!(report.stranded, vec![(p("a"), temp.clone())]);
+        assert!(report.failed[0].1.contains(".a.rename-1"));
+        assert_eq!(fs.read(&temp).unwrap(), b"a");
+
+        // A file that cannot move to its temporary name is a plain failure, it never left its name
+        let fs = MemoryFs::new(false);
+        fs.add_file(&p("a"), b"a");
+        fs.add_file(&p("b"), b"b");
+        fs.fail(Operation::Rename, &p("a"), ErrorKind::PermissionDenied);
+        let report = plan::apply(&fs, &[(p("a"), p("b")), (p("b"), p("a"))], Default::default(), &AtomicBool::new(false), |_, _, _| {});
+        assert!(report.renamed.is_empty());
+        assert!(report.stranded.is_empty());
+        let failed: Vec<&PathBuf> = report.failed.iter().map(|(old, _)| old).collect();
+        assert_eq!(failed, vec![&p("a"), &p("b")]);
+        assert!(report.failed.iter().all(|(_, error)| !error.contains(".rename-")));
+        assert_eq!(fs.read(&p("a")).unwrap(), b"a");
+        assert_eq!(fs.read(&p("b")).unwrap(), b"b");
+    }
+
+    #[test]
+    fn test_apply_cancel() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use crate::filesystem::RealFs;
+        use crate::plan;
+
+        let dir = std::env::temp_dir().join(format!("renamer_apply_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let p = |name: &str| dir.join(name);
+        for name in ["a", "b", "c"] {
+            std::fs::write(p(name), name).unwrap();
+        }
+
+        // Cancelled after the first file, the swap is never started
+        let cancel = AtomicBool::new(false);
+        let files = [(p("a"), p("b")), (p("b"), p("a")), (p("c"), p("d"))];
+        let report = plan::apply(&RealFs, &files, Default::default(), &cancel, |_, _, _| cancel.store(true, Ordering::Relaxed));
+        assert!(report.cancelled);
+        assert_eq!(report.renamed, vec![(p("c"), p("d"))]);
+        assert_eq!(report.skipped.len(), 2);
+        assert_eq!(std::fs::read_to_string(p("a")).unwrap(), "a");
+
+        // Cancelled halfway through the swap, it still finishes
+        let cancel = AtomicBool::new(false);
+        let report = plan::apply(&RealFs, &files[..2], Default::default(), &cancel, |done, total, _| {
+            cancel.store(true, Ordering::Relaxed);
+            assert_eq!(total, 2);
+            assert!(done <= total);
+        });
+        assert_eq!(report.renamed.len(), 2);
+        assert!(report.skipped.is_empty());
+        assert_eq!(std::fs::read_to_string(p("a")).unwrap(), "b");
+        assert_eq!(std::fs::read_to_string(p("b")).unwrap(), "a");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 
-}
\ No newline at end of file
+
+    #[test]
+    fn test_watch_pipeline() {
+        use crate::app::ActionOptions;
+        use crate::condition::ConditionKind;
+        use crate::pipeline::{Pipeline, Step};
+        use crate::watch::{Renamer, WatchEvent};
+
+        let pipeline = Pipeline {
+            steps: vec![
+                Step { action: ActionOptions::Prefix, value: String::from("new_"), ..Default::default() },
+                Step {
+                    action: ActionOptions::Suffix,
+                    value: String::from("_{counter}"),
+                    condition: ConditionKind::Extension,
+                    condition_input: String::from("jpg"),
+                    ..Default::default()
+                },
+            ],
+            time_zone: String::from("UTC"),
+            ..Default::default()
+        };
+        let pipeline = Pipeline::from_json(&pipeline.to_json()).unwrap();
+        assert_eq!(pipeline.steps.len(), 2);
+        assert_eq!(pipeline.steps[1].condition, ConditionKind::Extension);
+        assert!(Pipeline::from_json("{\"steps\": 3}").is_err());
+        assert!(!pipeline.has_commands());
+        let commands = Pipeline { steps: vec![Step { action: ActionOptions::Command, value: String::from("tr a-z A-Z"), ..Default::default() }], ..Default::default() };
+        assert!(commands.has_commands());
+
+        let dir = std::env::temp_dir().join(format!("file-renamer-watch-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a.jpg"), "a").unwrap();
+        std::fs::write(dir.join("b.txt"), "b").unwrap();
+        std::fs::write(dir.join("new_b.txt"), "taken").unwrap();
+
+        let mut renamer = Renamer::new(&pipeline);
+        let renamed = renamer.rename(&dir.join("a.jpg")).unwrap();
+        assert!(matches!(renamed, WatchEvent::Renamed { .. }) && !renamed.is_failure());
+        assert!(dir.join("new_a_1.jpg").exists());
+        // Never overwrites an existing file
+        let skipped = renamer.rename(&dir.join("b.txt")).unwrap();
+        assert!(matches!(skipped, WatchEvent::Skipped { .. }) && skipped.is_failure());
+        assert_eq!(std::fs::read_to_string(dir.join("new_b.txt")).unwrap(), "taken");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_watch_run() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::time::{Duration, SystemTime};
+        use crate::app::ActionOptions;
+        use crate::pipeline::{Pipeline, Step};
+        use crate::watch::{self, WatchEvent};
+
+        let pipeline = Pipeline {
+            steps: vec![Step { action: ActionOptions::Prefix, value: String::from("new_"), ..Default::default() }],
+            ..Default::default()
+        };
+        let dir = std::env::temp_dir().join(format!("file-renamer-watch-run-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // The same folder as a relative path from the working directory
+        let cwd = std::env::current_dir().unwrap();
+        let relative: PathBuf = cwd.components().skip(1).map(|_| std::path::Component::ParentDir)
+            .chain(dir.components().skip(1))
+            .collect();
+        assert!(relative.is_relative());
+
+        let stop = AtomicBool::new(false);
+        let events = std::sync::Mutex::new(Vec::new());
+        let wait_for = |count: usize| {
+            for _ in 0..100 {
+                if events.lock().unwrap().iter().filter(|event| matches!(event, WatchEvent::Renamed { .. })).count() >= count {
+                    return;
+                }
+                std::thread::sleep(Duration::from_millis(100));
+            }
+        };
+        std::thread::scope(|scope| {
+            scope.spawn(|| watch::run(&relative, &pipeline, &stop, |event| events.lock().unwrap().push(event)).unwrap());
+            std::thread::sleep(Duration::from_millis(300));
+            std::fs::write(dir.join("a.txt"), "a").unwrap();
+            wait_for(1);
+
+            // Another file that arrives under the name the watcher gave is renamed too
+            let _ = std::fs::remove_file(dir.join("new_a.txt"));
+            let file = std::fs::File::create(dir.join("new_a.txt")).unwrap();
+            file.set_modified(SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000)).unwrap();
+            drop(file);
+            wait_for(2);
+            stop.store(true, Ordering::Relaxed);
+        });
+        assert!(dir.join("new_new_a.txt").exists());
+        assert!(!dir.join("new_new_new_a.txt").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_script() {
+        let meta = FileMeta { modified: None, created: None, accessed: None, size: 2048, owner: None, group: None };
+        let ctx = FileContext { index: 4, meta: Some(&meta), ..Default::default() };
+        let path = PathBuf::from("/A/IMG_7.jpeg");
+
+        let script = Modify::new_script(r#"
+            let n = parse_int(stem.sub_string(4)) * 10;
+            if meta.size > 1024 { ext = "jpg"; }
+            `photo_${n}_${index + 1}`
+        "#);
+        assert!(script.validation_errors().is_empty());
+        assert_eq!(script.try_action(&path, &ctx).unwrap(), PathBuf::from("/A/photo_70_5.jpg"));
+
+        // Runtime errors are reported per file and keep the name
+        let script = Modify::new_script("if stem == \"IMG_7\" { throw \"no\" } stem");
+        assert!(script.try_action(&path, &ctx).unwrap_err().contains("no"));
+        assert_eq!(script.action_with(&path, &ctx), path);
+        assert!(Modify::new_script("42").try_action(&path, &ctx).unwrap_err().contains("expected a string"));
+
+        // No modules, eval or endless loops
+        assert!(Modify::new_script("import \"fs\" as fs; stem").try_action(&path, &ctx).is_err());
+        assert!(Modify::new_script("eval(\"stem\")").try_action(&path, &ctx).is_err());
+        assert!(Modify::new_script("loop {} stem").try_action(&path, &ctx).is_err());
+        assert!(!Modify::new_script("stem +").validation_errors().is_empty());
+        assert_eq!(Modify::new_script("").try_action(&path, &ctx).unwrap(), path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_external_command() {
+        use crate::action::CommandOptions;
+        use std::path::Path;
+
+        let ctx = FileContext::default();
+        let files = [PathBuf::from("/A/one.txt"), PathBuf::from("/A/two.txt")];
+        let inputs: Vec<&Path> = files.iter().map(PathBuf::as_path).collect();
+
+        let mut command = Modify::new_command("tr a-z A-Z", CommandOptions::default());
+        command.prepare(&inputs);
+        assert_eq!(command.try_action(&files[0], &ctx).unwrap(), PathBuf::from("/A/ONE.TXT"));
+        // Without prepare the command runs for the one file
+        let command = Modify::new_command("echo \"$FILE_PATH\" | sed 's/one/uno/'", CommandOptions::default());
+        assert_eq!(command.try_action(&files[0], &ctx).unwrap(), PathBuf::from("/A/uno.txt"));
+
+        let batch = CommandOptions { batch: true, ..Default::default() };
+        let mut command = Modify::new_command("sed 's/^/x_/'", batch);
+        command.prepare(&inputs);
+        assert_eq!(command.try_action(&files[1], &ctx).unwrap(), PathBuf::from("/A/x_two.txt"));
+        let mut command = Modify::new_command("head -n 1", batch);
+        command.prepare(&inputs);
+        assert!(command.try_action(&files[0], &ctx).unwrap_err().contains("1 line(s) for 2 name(s)"));
+
+        // Failures are reported per file and keep the name
+        let command = Modify::new_command("echo broken >&2; exit 3", CommandOptions::default());
+        assert_eq!(command.try_action(&files[0], &ctx).unwrap_err(), "command failed with exit code 3: broken");
+        assert_eq!(command.action_with(&files[0], &ctx), files[0]);
+        let command = Modify::new_command("sleep 5", CommandOptions { timeout_secs: 1, ..Default::default() });
+        assert!(command.try_action(&files[0], &ctx).unwrap_err().contains("timed out"));
+        // A timeout also ends the processes the shell started
+        let marker = std::env::temp_dir().join(format!("renamer_command_{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let script = format!("(sleep 2; touch '{}') & wait", marker.display());
+        let command = Modify::new_command(&script, CommandOptions { timeout_secs: 1, ..Default::default() });
+        assert!(command.try_action(&files[0], &ctx).unwrap_err().contains("timed out"));
+        std::thread::sleep(Duration::from_millis(1500));
+        assert!(!marker.exists());
+        assert!(Modify::new_command("true", CommandOptions::default()).try_action(&files[0], &ctx).unwrap_err().contains("no name"));
+
+        // Printed paths stay inside the file's folder
+        let full_paths = CommandOptions { full_paths: true, ..Default::default() };
+        let command = Modify::new_command("sed 's/one/uno/'", full_paths);
+        assert_eq!(command.try_action(&files[0], &ctx).unwrap(), PathBuf::from("/A/uno.txt"));
+        let command = Modify::new_command("echo /etc/passwd", CommandOptions::default());
+        assert!(command.try_action(&files[0], &ctx).unwrap_err().contains("outside the file's folder"));
+        let command = Modify::new_command("echo ../../etc/x", CommandOptions::default());
+        assert_eq!(command.try_action(&files[0], &ctx).unwrap(), PathBuf::from("/A/etc/x"));
+
+        // Later previews reuse the output of an unchanged command step
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+        use crate::app::ActionOptions;
+        use crate::pipeline::Step;
+        use crate::preview::{self, PreviewJob, PreviewRow};
+        let runs = std::env::temp_dir().join(format!("renamer_command_runs_{}", std::process::id()));
+        let _ = std::fs::remove_file(&runs);
+        let mut job = PreviewJob {
+            rows: Arc::new(files.iter().map(|path| PreviewRow { old_path: path.clone(), meta: None, companion: None, duplicate: None }).collect()),
+            steps: vec![Step { action: ActionOptions::Command, value: format!("echo >> '{}'; tr a-z A-Z", runs.display()), ..Default::default() }],
+            time_zone: TimeZoneChoice::Utc,
+            hashes: Arc::new(HashCache::new()),
+            hash_version: 0,
+            duplicate_handling: Default::default(),
+            profile: Default::default(),
+            previous: None,
+        };
+        let output = preview::compute(&job, &AtomicBool::new(false)).unwrap();
+        assert_eq!(output.new_paths[1], PathBuf::from("/A/TWO.TXT"));
+        job.steps.push(Step { action: ActionOptions::Suffix, value: String::from("_b"), ..Default::default() });
+        job.rows = Arc::new(job.rows.iter().cloned().rev().collect());
+        job.previous = Some(output.cache);
+        let output = preview::compute(&job, &AtomicBool::new(false)).unwrap();
+        assert_eq!(output.new_paths[0], PathBuf::from("/A/TWO_b.TXT"));
+        assert_eq!(std::fs::read_to_string(&runs).unwrap().lines().count(), 2);
+        std::fs::remove_file(&runs).unwrap();
+    }
+
+    #[test]
+    fn test_plugins() {
+        use crate::app::ActionOptions;
+        use crate::pipeline::Step;
+        use crate::action::StateValue;
+        use crate::plugin::{self, ActionPlugin, Field, WasmPlugin};
+        use std::sync::Arc;
+
+        struct Reverse;
+        impl ActionPlugin for Reverse {
+            fn id(&self) -> &str { "test-reverse" }
+            fn name(&self) -> &str { "Reverse" }
+            fn fields(&self) -> Vec<Field> { vec![Field::text("Suffix", "")] }
+            fn build(&self, values: &[String]) -> Box<dyn Action> {
+                Box::new(ReverseAction { suffix: values.first().cloned().unwrap_or_default() })
+            }
+        }
+        struct ReverseAction { suffix: String }
+        impl Action for ReverseAction {
+            fn action(&self, file_name: &PathBuf) -> PathBuf {
+                let stem = file_name.file_stem().unwrap_or_default().to_string_lossy();
+                let mut new_name = file_name.to_owned();
+                action::set_stem(&mut new_name, format!("{}{}", stem.chars().rev().collect::<String>(), self.suffix), file_name.extension().map(|ext| ext.to_os_string()));
+                new_name
+            }
+            fn update_values(&mut self, _data: StateValue) {}
+        }
+        let step = |id: &str, fields: &[&str]| Step {
+            action: ActionOptions::Plugin,
+            plugin: id.to_owned(),
+            fields: fields.iter().map(|field| field.to_string()).collect(),
+            ..Default::default()
+        };
+        let path = PathBuf::from("/A/my_holiday photo.JPG");
+        plugin::register(Arc::new(Reverse));
+        let listed = plugin::plugins().iter().any(|plugin| plugin.name() == "Reverse");
+        let reversed = plugin::build("test-reverse", &[String::from("_r")]).action(&PathBuf::from("/A/abc.txt"));
+        let from_step = step("test-reverse", &["_r"]).build().action(&path);
+        plugin::unregister("test-reverse");
+        assert!(listed);
+        assert_eq!(reversed, PathBuf::from("/A/cba_r.txt"));
+        assert_eq!(from_step, PathBuf::from("/A/otohp yadiloh_ym_r.JPG"));
+        assert!(plugin::find("test-reverse").is_none());
+        assert!(plugin::build("test-reverse", &[]).try_action(&PathBuf::from("/A/abc.txt"), &FileContext::default()).is_err());
+
+        let missing = step("not-installed", &[]).build();
+        assert_eq!(missing.action(&path), path);
+        assert!(missing.try_action(&path, &FileContext::default()).is_err());
+
+        // Exports memory, alloc() returning 1024 and rename() writing the status byte and `x` in front of the input
+        let wasm: &[u8] = &[
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            0x01, 0x0c, 0x02, 0x60, 0x01, 0x7f, 0x01, 0x7f, 0x60, 0x02, 0x7f, 0x7f, 0x01, 0x7e,
+            0x03, 0x03, 0x02, 0x00, 0x01,
+            0x05, 0x03, 0x01, 0x00, 0x01,
+            0x07, 0x1b, 0x03,
+            0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00,
+            0x05, b'a', b'l', b'l', b'o', b'c', 0x00, 0x00,
+            0x06, b'r', b'e', b'n', b'a', b'm', b'e', 0x00, 0x01,
+            0x0a, 0x28, 0x02,
+            0x05, 0x00, 0x41, 0x80, 0x08, 0x0b,
+            0x20, 0x00,
+            0x41, 0xfe, 0x07, 0x41, 0x00, 0x3a, 0x00, 0x00,
+            0x41, 0xff, 0x07, 0x41, 0xf8, 0x00, 0x3a, 0x00, 0x00,
+            0x42, 0xfe, 0x07, 0x42, 0x20, 0x86, 0x20, 0x01, 0x41, 0x02, 0x6a, 0xad, 0x84, 0x0b,
+        ];
+        let wasm_plugin = WasmPlugin::new("prefix-x", wasm).unwrap();
+        assert_eq!(wasm_plugin.name(), "prefix-x");
+        assert!(wasm_plugin.fields().is_empty());
+        assert_eq!(wasm_plugin.build(&[]).action(&PathBuf::from("/A/b.txt")), PathBuf::from("/A/xb.txt"));
+        assert!(WasmPlugin::new("broken", &wasm[..20]).is_err());
+
+        let dir = std::env::temp_dir().join(format!("renamer_plugins_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("prefix-x.wasm"), wasm).unwrap();
+        std::fs::write(dir.join("broken.wasm"), &wasm[..20]).unwrap();
+        std::fs::write(dir.join("notes.txt"), "").unwrap();
+        let loaded = plugin::load_folder(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].0, "broken");
+        assert!(loaded[0].1.as_ref().is_err_and(|error| error.contains("broken.wasm")));
+        assert_eq!(loaded[1].0, "prefix-x");
+        assert!(loaded[1].1.is_ok());
+    }
+
+    #[test]
+    fn test_preview() {
+        use std::sync::atomic::AtomicBool;
+        use std::sync::Arc;
+        use crate::app::ActionOptions;
+        use crate::pipeline::Step;
+        use crate::preview::{self, PreviewJob, PreviewRow};
+
+        let rows: Vec<PreviewRow> = (0..3000)
+            .map(|i| PreviewRow { old_path: PathBuf::from(format!("/A/IMG_{}.jpg", i)), meta: None, companion: None, duplicate: None })
+            .collect();
+        let mut job = PreviewJob {
+            rows: Arc::new(rows),
+            steps: vec![
+                Step { action: ActionOptions::RegexReplace, pattern: String::from("^IMG_"), value: String::from("photo_"), ..Default::default() },
+                Step { action: ActionOptions::Suffix, value: String::from("_a"), ..Default::default() },
+            ],
+            time_zone: TimeZoneChoice::Utc,
+            hashes: Arc::new(HashCache::new()),
+            hash_version: 0,
+            duplicate_handling: Default::default(),
+            profile: Default::default(),
+            previous: None,
+        };
+        let output = preview::compute(&job, &AtomicBool::new(false)).unwrap();
+        assert_eq!(output.new_paths[2999], PathBuf::from("/A/photo_2999_a.jpg"));
+        assert!(output.problems.iter().all(Vec::is_empty));
+
+        // Editing the last step reuses the output of the first one
+        job.steps[1].value = String::from("_b");
+        job.previous = Some(output.cache);
+        let output = preview::compute(&job, &AtomicBool::new(false)).unwrap();
+        assert_eq!(output.new_paths[7], PathBuf::from("/A/photo_7_b.jpg"));
+
+        // Changed rows are never served from the cache
+        job.rows = Arc::new(vec![PreviewRow { old_path: PathBuf::from("/A/IMG_x.jpg"), meta: None, companion: None, duplicate: None }]);
+        job.previous = Some(output.cache);
+        let output = preview::compute(&job, &AtomicBool::new(false)).unwrap();
+        assert_eq!(output.new_paths, vec![PathBuf::from("/A/photo_x_b.jpg")]);
+
+        assert!(preview::compute(&job, &AtomicBool::new(true)).is_none());
+
+        // New digests redo the steps that read them, unchanged earlier steps are reused
+        job.steps.push(Step { action: ActionOptions::Suffix, value: String::from("_{crc32}"), ..Default::default() });
+        job.previous = None;
+        let output = preview::compute(&job, &AtomicBool::new(false)).unwrap();
+        assert_eq!(output.new_paths, vec![PathBuf::from("/A/photo_x_b_{crc32}.jpg")]);
+        let mut hashes = HashCache::new();
+        hashes.insert((PathBuf::from("/A/IMG_x.jpg"), HashAlgorithm::Crc32), hash::CachedHash { modified: None, digest: String::from("1234abcd") });
+        job.hashes = Arc::new(hashes);
+        job.hash_version += 1;
+        job.previous = Some(output.cache);
+        let output = preview::compute(&job, &AtomicBool::new(false)).unwrap();
+        assert_eq!(output.new_paths, vec![PathBuf::from("/A/photo_x_b_1234abcd.jpg")]);
+        // A step that leaves no stem is an error for that row instead of a hidden `.ext` name
+        let paths = ["/A/IMG_1.jpg", "/A/.hidden", "/A/IMG_2", "/A/__abc.jpg"];
+        job.rows = Arc::new(paths.iter()
+            .map(|path| PreviewRow { old_path: PathBuf::from(path), meta: None, companion: None, duplicate: None })
+            .collect());
+        job.previous = None;
+        job.steps = vec![Step { action: ActionOptions::RegexReplace, pattern: String::from(r"^IMG_\d+$"), value: String::new(), ..Default::default() }];
+        let output = preview::compute(&job, &AtomicBool::new(false)).unwrap();
+        assert_eq!(output.new_paths[..3], job.rows[..3].iter().map(|row| row.old_path.clone()).collect::<Vec<_>>());
+        assert_eq!(output.errors[0], vec!["Step 1: the new name `.jpg` has no stem"]);
+        assert!(output.errors[1].is_empty());
+        assert_eq!(output.errors[2], vec!["Step 1: the new name is empty"]);
+        job.steps = vec![Step { action: ActionOptions::Truncate, truncate: TruncateOptions { max_length: 2, ..Default::default() }, ..Default::default() }];
+        let output = preview::compute(&job, &AtomicBool::new(false)).unwrap();
+        assert_eq!(output.new_paths[3], PathBuf::from("/A/__abc.jpg"));
+        assert_eq!(output.problems[3], vec!["Step 1: the new name `.jpg` has no stem"]);
+    }
+}
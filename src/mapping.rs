@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::action::INVALID_CHARS;
+
+/// First cells that mark a header row instead of a file.
+const HEADERS: [&str; 7] = ["old", "old name", "old_name", "old path", "old_path", "from", "source"];
+
+/// Old names or paths mapped to new names, as prepared in a spreadsheet.
+#[derive(Debug, Clone, Default)]
+pub struct Mapping {
+        /// Rows in file order, `(old, new)`.
+        pub rows: Vec<(String, String)>,
+        by_path: HashMap<PathBuf, String>,
+        by_name: HashMap<String, String>,
+}
+
+impl Mapping {
+        /// Reads two columns of CSV, or TSV when the first line contains a tab.
+        /// An old value with a `/` is matched against the full path, otherwise against the file name.
+        pub fn parse(content: &str) -> Result<Mapping, String> {
+                let first_line = content.lines().next().unwrap_or_default();
+                let delimiter = if first_line.contains('\t') { b'\t' } else { b',' };
+                let mut reader = csv::ReaderBuilder::new()
+                        .has_headers(false)
+                        .flexible(true)
+                        .delimiter(delimiter)
+                        .from_reader(content.as_bytes());
+
+                let mut rows = Vec::new();
+                for (line, record) in reader.records().enumerate() {
+                        let record = record.map_err(|e| e.to_string())?;
+                        let old = record.get(0).unwrap_or_default().trim();
+                        if line == 0 && HEADERS.contains(&old.to_lowercase().as_str()) {
+                                continue;
+                        }
+                        if old.is_empty() && record.iter().all(|cell| cell.trim().is_empty()) {
+                                continue;
+                        }
+                        let Some(new) = record.get(1).map(str::trim).filter(|new| !new.is_empty()) else {
+                                return Err(format!("line {}: missing new name for `{}`", line + 1, old));
+                        };
+                        if clean_name(new).is_empty() {
+                                return Err(format!("line {}: `{}` is not a valid name", line + 1, new));
+                        }
+                        rows.push((old.to_owned(), new.to_owned()));
+                }
+                Ok(Mapping::new(rows))
+        }
+
+        /// New names lose the characters typed names cannot have, rows left without a name are ignored.
+        pub fn new(rows: Vec<(String, String)>) -> Mapping {
+                let mut by_path = HashMap::new();
+                let mut by_name = HashMap::new();
+                for (old, new) in &rows {
+                        let new = clean_name(new);
+                        if new.is_empty() {
+                                continue;
+                        }
+                        if old.contains('/') {
+                                by_path.insert(PathBuf::from(old), new);
+                        } else {
+                                by_name.insert(old.clone(), new);
+                        }
+                }
+                Mapping { rows, by_path, by_name }
+        }
+
+        /// New name for a file, a full path row wins over a file name row.
+        pub fn lookup(&self, path: &Path) -> Option<&str> {
+                if let Some(new) = self.by_path.get(path) {
+                        return Some(new);
+                }
+                let name = path.file_name()?.to_string_lossy();
+                self.by_name.get(name.as_ref()).map(String::as_str)
+        }
+
+        /// Old values of the rows that match none of `files`.
+        pub fn unmatched(&self, files: &[&Path]) -> Vec<&str> {
+                let names: Vec<String> = files.iter()
+                        .filter_map(|path| path.file_name())
+                        .map(|name| name.to_string_lossy().into_owned())
+                        .collect();
+                self.rows.iter()
+                        .map(|(old, _)| old.as_str())
+                        .filter(|old| if old.contains('/') {
+                                !files.contains(&Path::new(old))
+                        } else {
+                                !names.iter().any(|name| name == old)
+                        })
+                        .collect()
+        }
+}
+
+/// `new` without invalid characters, a `/` still starts a sub folder but never leaves the folder.
+fn clean_name(new: &str) -> String {
+        new.split('/')
+                .map(|part| part.chars().filter(|c| *c == '.' || !INVALID_CHARS.contains(c)).collect::<String>())
+                .filter(|part| !part.trim().is_empty() && part != "." && part != "..")
+                .collect::<Vec<_>>()
+                .join("/")
+}
+
+/// The preview as CSV with an `old_path,new_path,status` header.
+pub fn export_plan(rows: &[(&Path, &Path, String)]) -> Result<String, String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(["old_path", "new_path", "status"]).map_err(|e| e.to_string())?;
+        for (old, new, status) in rows {
+                writer.write_record([&*old.to_string_lossy(), &*new.to_string_lossy(), status.as_str()])
+                        .map_err(|e| e.to_string())?;
+        }
+        let bytes = writer.into_inner().map_err(|e| e.to_string())?;
+        String::from_utf8(bytes).map_err(|e| e.to_string())
+}
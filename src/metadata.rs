@@ -0,0 +1,125 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::time::SystemTime;
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use chrono_tz::Tz;
+
+pub const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d";
+
+/// The parts of `std::fs::Metadata` that can be used in names and table columns.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FileMeta {
+        pub modified: Option<SystemTime>,
+        pub created: Option<SystemTime>,
+        pub accessed: Option<SystemTime>,
+        pub size: u64,
+        pub owner: Option<String>,
+        pub group: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimeField {
+        Modified,
+        Created,
+        Accessed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum TimeZoneChoice {
+        #[default]
+        Local,
+        Utc,
+        Named(Tz),
+}
+
+impl FileMeta {
+        pub fn read(path: &Path) -> Option<FileMeta> {
+                std::fs::metadata(path).ok().map(|meta| Self::from_metadata(&meta))
+        }
+
+        pub fn from_metadata(meta: &std::fs::Metadata) -> FileMeta {
+                let (owner, group) = owner_and_group(meta);
+                FileMeta {
+                        modified: meta.modified().ok(),
+                        created: meta.created().ok(),
+                        accessed: meta.accessed().ok(),
+                        size: meta.len(),
+                        owner,
+                        group,
+                }
+        }
+
+        pub fn time(&self, field: TimeField) -> Option<SystemTime> {
+                match field {
+                        TimeField::Modified => self.modified,
+                        TimeField::Created => self.created,
+                        TimeField::Accessed => self.accessed,
+                }
+        }
+}
+
+impl TimeZoneChoice {
+        /// Accepts `Local`, `UTC` or an IANA name such as `Europe/Paris`.
+        pub fn parse(input: &str) -> Option<TimeZoneChoice> {
+                let input = input.trim();
+                if input.is_empty() || input.eq_ignore_ascii_case("local") {
+                        Some(TimeZoneChoice::Local)
+                } else if input.eq_ignore_ascii_case("utc") {
+                        Some(TimeZoneChoice::Utc)
+                } else {
+                        input.parse::<Tz>().ok().map(TimeZoneChoice::Named)
+                }
+        }
+}
+
+/// Date the photo was taken, from the EXIF `DateTimeOriginal` field.
+pub fn exif_date(path: &Path) -> Option<NaiveDateTime> {
+        let file = std::fs::File::open(path).ok()?;
+        let exif = exif::Reader::new().read_from_container(&mut std::io::BufReader::new(file)).ok()?;
+        let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+        let exif::Value::Ascii(values) = &field.value else { return None; };
+        let value = std::str::from_utf8(values.first()?).ok()?;
+        NaiveDateTime::parse_from_str(value.trim(), "%Y:%m:%d %H:%M:%S").ok()
+}
+
+/// Formats `time` with a strftime pattern, returns `None` if the pattern is invalid.
+pub fn format_time(time: SystemTime, format: &str, time_zone: TimeZoneChoice) -> Option<String> {
+        let utc: DateTime<Utc> = time.into();
+        let mut formatted = String::new();
+        let result = match time_zone {
+                TimeZoneChoice::Local => write!(formatted, "{}", utc.with_timezone(&Local).format(format)),
+                TimeZoneChoice::Utc => write!(formatted, "{}", utc.format(format)),
+                TimeZoneChoice::Named(tz) => write!(formatted, "{}", utc.with_timezone(&tz).format(format)),
+        };
+        result.ok().map(|_| formatted)
+}
+
+pub fn human_size(bytes: u64) -> String {
+        let units = ["B", "KiB", "MiB", "GiB", "TiB"];
+        let mut size = bytes as f64;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < units.len() - 1 {
+                size /= 1024.0;
+                unit += 1;
+        }
+        if unit == 0 {
+                format!("{}{}", bytes, units[0])
+        } else {
+                format!("{:.1}{}", size, units[unit])
+        }
+}
+
+#[cfg(unix)]
+fn owner_and_group(meta: &std::fs::Metadata) -> (Option<String>, Option<String>) {
+        use std::os::unix::fs::MetadataExt;
+        let owner = uzers::get_user_by_uid(meta.uid())
+                .map(|user| user.name().to_string_lossy().into_owned());
+        let group = uzers::get_group_by_gid(meta.gid())
+                .map(|group| group.name().to_string_lossy().into_owned());
+        (owner, group)
+}
+
+#[cfg(not(unix))]
+fn owner_and_group(_meta: &std::fs::Metadata) -> (Option<String>, Option<String>) {
+        (None, None)
+}
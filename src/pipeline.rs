@@ -0,0 +1,134 @@
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+
+use crate::action::{Action, CleanupOptions, CommandOptions, CommandResults, Modify, RenumberOptions, TruncateOptions, UnicodeOptions};
+use crate::app::ActionOptions;
+use crate::condition::{Condition, ConditionKind};
+use crate::hash::{self, HashAlgorithm};
+use crate::mapping::Mapping;
+use crate::metadata::TimeZoneChoice;
+use crate::plugin;
+use crate::validate::TargetProfile;
+
+/// The steps and settings needed to rename files without the GUI, saved as JSON.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Pipeline {
+        pub steps: Vec<Step>,
+        pub time_zone: String,
+        pub target_profile: TargetProfile,
+}
+
+/// Inputs of one step, only the fields of its action are used.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Step {
+        pub action: ActionOptions,
+        pub pattern: String,
+        pub value: String,
+        pub unicode: UnicodeOptions,
+        pub profile: TargetProfile,
+        pub cleanup: CleanupOptions,
+        pub truncate: TruncateOptions,
+        pub renumber: RenumberOptions,
+        /// `(old, new)` rows of an imported mapping.
+        pub mapping: Vec<(String, String)>,
+        pub command: CommandOptions,
+        /// Id and field values of a plugin action.
+        pub plugin: String,
+        pub fields: Vec<String>,
+        pub condition: ConditionKind,
+        pub condition_input: String,
+        pub condition_negate: bool,
+}
+
+impl Pipeline {
+        pub fn load(path: &Path) -> Result<Pipeline, String> {
+                let content = std::fs::read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+                Pipeline::from_json(&content).map_err(|e| format!("{}: {}", path.display(), e))
+        }
+
+        pub fn from_json(content: &str) -> Result<Pipeline, String> {
+                serde_json::from_str(content).map_err(|e| e.to_string())
+        }
+
+        pub fn to_json(&self) -> String {
+                serde_json::to_string_pretty(self).expect("pipeline is always serializable")
+        }
+
+        /// True when a step runs an external command.
+        pub fn has_commands(&self) -> bool {
+                self.steps.iter().any(|step| step.action == ActionOptions::Command && !step.value.trim().is_empty())
+        }
+
+        /// Falls back to local time when the saved zone is unknown.
+        pub fn time_zone(&self) -> TimeZoneChoice {
+                TimeZoneChoice::parse(&self.time_zone).unwrap_or_default()
+        }
+}
+
+impl Step {
+        pub fn build(&self) -> Box<dyn Action> {
+                match self.action {
+                        ActionOptions::MatchAndReplace => {
+                                let mut action = Modify::new_op();
+                                action.set_pattern(&self.pattern).find_and_replace_op(&self.value);
+                                Box::new(action)
+                        },
+                        ActionOptions::RegexReplace => {
+                                let mut action = Modify::new_op();
+                                action.set_pattern(&self.pattern).regex_op(&self.value);
+                                Box::new(action)
+                        },
+                        ActionOptions::Prefix => {
+                                let mut action = Modify::new_affix();
+                                action.prefix_mode(&self.value);
+                                Box::new(action)
+                        },
+                        ActionOptions::Suffix => {
+                                let mut action = Modify::new_affix();
+                                action.suffix_mode(&self.value);
+                                Box::new(action)
+                        },
+                        ActionOptions::Template => {
+                                let mut action = Modify::new_template();
+                                action.set_template(&self.pattern, &self.value);
+                                Box::new(action)
+                        },
+                        ActionOptions::Unicode => Box::new(Modify::new_unicode(self.unicode)),
+                        ActionOptions::MakeSafe => Box::new(Modify::new_make_safe(self.profile)),
+                        ActionOptions::Cleanup => Box::new(Modify::new_cleanup(self.cleanup)),
+                        ActionOptions::Truncate => Box::new(Modify::new_truncate(self.truncate)),
+                        ActionOptions::Renumber => Box::new(Modify::new_renumber(self.renumber)),
+                        ActionOptions::Import => Box::new(Modify::new_import(Mapping::new(self.mapping.clone()))),
+                        ActionOptions::Script => Box::new(Modify::new_script(&self.value)),
+                        ActionOptions::Command => Box::new(Modify::new_command(&self.value, self.command)),
+                        ActionOptions::Plugin => plugin::build(&self.plugin, &self.fields),
+                }
+        }
+
+        /// Same as `build`, a command step reads and adds to `results` instead of running every name again.
+        pub fn build_cached(&self, results: CommandResults) -> Box<dyn Action> {
+                match self.action {
+                        ActionOptions::Command => Box::new(Modify::new_command(&self.value, self.command).with_results(results)),
+                        _ => self.build(),
+                }
+        }
+
+        /// Digests read by the step's tokens.
+        pub fn hash_algorithms(&self) -> Vec<HashAlgorithm> {
+                match self.action {
+                        ActionOptions::Prefix | ActionOptions::Suffix | ActionOptions::Template => hash::algorithms_in(&self.value),
+                        _ => Vec::new(),
+                }
+        }
+
+        pub fn condition(&self) -> Condition {
+                Condition::new(self.condition, self.condition_input.clone(), self.condition_negate)
+        }
+
+        /// True for steps whose value can hold tokens.
+        pub fn uses_tokens(&self) -> bool {
+                matches!(self.action, ActionOptions::Prefix | ActionOptions::Suffix | ActionOptions::Template)
+        }
+}
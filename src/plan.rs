@@ -0,0 +1,423 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use iced::futures::{SinkExt, Stream};
+
+use crate::filesystem::{FileSystem, MemoryFs, RealFs};
+use crate::hash::HashAlgorithm;
+
+/// Renames that run at the same time while applying a plan.
+const WORKERS: usize = 8;
+
+/// One rename of a plan. `origin` is where the file was before the plan,
+/// it differs from `old` once the file went through a temporary name.
+#[derive(Debug, Clone)]
+struct Move {
+        old: PathBuf,
+        new: PathBuf,
+        origin: PathBuf,
+        /// Moves the file to a temporary name, it gets its new name later.
+        temporary: bool,
+}
+
+/// Orders `(old, new)` renames so that no rename overwrites a file that still has to move.
+/// Chains like `b→c, a→b` run back to front, cycles like `a→b, b→a` go through a temporary name.
+/// Unchanged files are left out.
+pub fn order(files: &[(PathBuf, PathBuf)]) -> Vec<(PathBuf, PathBuf)> {
+        waves(&RealFs, files).into_iter().flatten().map(|step| (step.old, step.new)).collect()
+}
+
+/// The moves of `order`, grouped so the moves of one group can run in any order.
+fn waves(fs: &dyn FileSystem, files: &[(PathBuf, PathBuf)]) -> Vec<Vec<Move>> {
+        let mut pending: Vec<Move> = files.iter()
+                .filter(|(old, new)| old != new)
+                .map(|(old, new)| Move { old: old.clone(), new: new.clone(), origin: old.clone(), temporary: false })
+                .collect();
+        let mut taken: HashSet<PathBuf> = files.iter().flat_map(|(old, new)| [old.clone(), new.clone()]).collect();
+        let mut waves = Vec::new();
+
+        while !pending.is_empty() {
+                let sources: HashMap<&Path, usize> = pending.iter().enumerate()
+                        .map(|(i, step)| (step.old.as_path(), i))
+                        .collect();
+                // A rename is ready once nothing else still has to leave its target
+                let ready: Vec<usize> = (0..pending.len())
+                        .filter(|i| !sources.contains_key(pending[*i].new.as_path()))
+                        .collect();
+                if ready.is_empty() {
+                        // Every target is still in use, so the remaining renames form cycles
+                        let temp = temp_path(fs, &pending[0].old, &taken);
+                        taken.insert(temp.clone());
+                        let step = &mut pending[0];
+                        waves.push(vec![Move { old: step.old.clone(), new: temp.clone(), origin: step.origin.clone(), temporary: true }]);
+                        step.old = temp;
+                        continue;
+                }
+                let mut wave = Vec::new();
+                for i in ready.into_iter().rev() {
+                        wave.push(pending.remove(i));
+                }
+                wave.reverse();
+                waves.push(wave);
+        }
+        waves
+}
+
+/// Settings of `apply`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ApplyOptions {
+        /// Removes source folders left empty.
+        pub remove_empty_folders: bool,
+        /// Compares content hashes of files copied to another file system before deleting the original.
+        pub verify_hash: bool,
+        /// Runs the plan against a copy of the disk in memory and only reports what would happen.
+        pub dry_run: bool,
+}
+
+/// How a file got its new name.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Transfer {
+        Renamed,
+        /// Copied to another file system and deleted.
+        Moved,
+}
+
+/// What happened to each file of an applied plan, by its path before the plan.
+#[derive(Debug, Clone, Default)]
+pub struct ApplyReport {
+        /// `(old, new)` of the files that got their new name.
+        pub renamed: Vec<(PathBuf, PathBuf)>,
+        /// `(old, new)` of the files copied to another file system.
+        pub moved: Vec<(PathBuf, PathBuf)>,
+        pub failed: Vec<(PathBuf, String)>,
+        /// `(old, temporary)` of failed files that could not leave their temporary name.
+        pub stranded: Vec<(PathBuf, PathBuf)>,
+        /// Files left as they were because the run was cancelled.
+        pub skipped: Vec<PathBuf>,
+        pub cancelled: bool,
+        pub dry_run: bool,
+}
+
+/// Progress of a plan running in the background.
+#[derive(Debug, Clone)]
+pub enum ApplyEvent {
+        Progress { done: usize, total: usize, current: PathBuf },
+        Finished(ApplyReport),
+}
+
+/// Runs the renames in a safe order, a few at a time, and reports every file.
+/// Once `cancel` is set no further file is started, but a swap through a temporary
+/// name is always finished so no file is left under that name.
+pub fn apply(fs: &dyn FileSystem, files: &[(PathBuf, PathBuf)], options: ApplyOptions, cancel: &AtomicBool,
+        on_progress: impl Fn(usize, usize, &Path) + Sync) -> ApplyReport {
+        let waves = waves(fs, files);
+        let total = waves.iter().flatten().filter(|step| !step.temporary).count();
+        let done = AtomicUsize::new(0);
+        // Temporary names that currently hold a file
+        let parked: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+        let report = Mutex::new(ApplyReport::default());
+
+        for wave in &waves {
+                // Moves to the same name, also differently cased, run one after another on one
+                // worker, so the second one finds the first file and fails instead of replacing it
+                let mut groups: Vec<Vec<&Move>> = Vec::new();
+                let mut by_target: HashMap<String, usize> = HashMap::new();
+                for step in wave {
+                        let group = *by_target.entry(step.new.to_string_lossy().to_lowercase()).or_insert_with(|| {
+                                groups.push(Vec::new());
+                                groups.len() - 1
+                        });
+                        groups[group].push(step);
+                }
+                let next = AtomicUsize::new(0);
+                thread::scope(|scope| {
+                        for _ in 0..WORKERS.min(groups.len()) {
+                                scope.spawn(|| {
+                                        while let Some(group) = groups.get(next.fetch_add(1, Ordering::Relaxed)) {
+                                                for step in group {
+                                                        run_step(fs, step, options, cancel, &parked, &report);
+                                                        if !step.temporary {
+                                                                on_progress(done.fetch_add(1, Ordering::Relaxed) + 1, total, &step.origin);
+                                                        }
+                                                }
+                                        }
+                                });
+                        }
+                });
+        }
+
+        let mut report = report.into_inner().unwrap();
+        report.dry_run = options.dry_run;
+        if options.remove_empty_folders {
+                let mut source_folders: Vec<&Path> = report.renamed.iter().chain(&report.moved)
+                        .filter(|(old, new)| old.parent() != new.parent())
+                        .filter_map(|(old, _)| old.parent())
+                        .collect();
+                source_folders.sort();
+                source_folders.dedup();
+                for folder in source_folders {
+                        // remove_dir only succeeds on empty folders
+                        let _ = fs.remove_dir(folder);
+                }
+        }
+        report
+}
+
+/// One move of `apply`. A file that cannot leave its temporary name goes back to where it was,
+/// or is reported where it is.
+fn run_step(fs: &dyn FileSystem, step: &Move, options: ApplyOptions, cancel: &AtomicBool,
+        parked: &Mutex<HashSet<PathBuf>>, report: &Mutex<ApplyReport>) {
+        if cancel.load(Ordering::Relaxed) && parked.lock().unwrap().is_empty() {
+                let mut report = report.lock().unwrap();
+                report.cancelled = true;
+                if !step.temporary {
+                        report.skipped.push(step.origin.clone());
+                }
+                return;
+        }
+        let from_temporary = step.old != step.origin;
+        if from_temporary && !parked.lock().unwrap().contains(&step.old) {
+                // The move to the temporary name failed and was reported, the file is still at `origin`
+                return;
+        }
+        let result = rename_checked(fs, &step.old, &step.new, options.verify_hash);
+        let result = match result {
+                Err(error) if from_temporary => match rename_checked(fs, &step.old, &step.origin, false) {
+                        Ok(_) => Err(error),
+                        Err(_) => {
+                                report.lock().unwrap().stranded.push((step.origin.clone(), step.old.clone()));
+                                Err(format!("{}, the file is now {}", error, step.old.display()))
+                        },
+                },
+                result => result,
+        };
+        {
+                // A file leaves `parked` once it is out of its temporary name for good
+                let mut parked = parked.lock().unwrap();
+                if from_temporary {
+                        parked.remove(&step.old);
+                }
+                if step.temporary && result.is_ok() {
+                        parked.insert(step.new.clone());
+                }
+        }
+        let mut report = report.lock().unwrap();
+        match result {
+                Ok(_) if step.temporary => {},
+                Ok(Transfer::Renamed) => report.renamed.push((step.origin.clone(), step.new.clone())),
+                Ok(Transfer::Moved) => report.moved.push((step.origin.clone(), step.new.clone())),
+                Err(error) => report.failed.push((step.origin.clone(), error)),
+        }
+}
+
+/// `apply` on a blocking thread, with progress events for the UI.
+/// A dry run works on `MemoryFs::mirror` of the files.
+pub fn apply_in_background(files: Vec<(PathBuf, PathBuf)>, options: ApplyOptions, cancel: Arc<AtomicBool>) -> impl Stream<Item = ApplyEvent> {
+        iced::stream::channel(100, async move |output| {
+                let result = tokio::task::spawn_blocking(move || {
+                        let output = Mutex::new(output);
+                        let fs: Box<dyn FileSystem> = if options.dry_run { Box::new(MemoryFs::mirror(&files)) } else { Box::new(RealFs) };
+                        let report = apply(fs.as_ref(), &files, options, &cancel, |done, total, current| {
+                                // Progress may be dropped when the UI falls behind, the next event has the new count
+                                let event = ApplyEvent::Progress { done, total, current: current.to_path_buf() };
+                                let _ = output.lock().unwrap().try_send(event);
+                        });
+                        let mut output = output.into_inner().unwrap();
+                        let _ = iced::futures::executor::block_on(output.send(ApplyEvent::Finished(report)));
+                }).await;
+                if let Err(error) = result {
+                        // This happens if a task panics
+                        eprintln!("Worker thread failed: {:?}", error);
+                }
+        })
+}
+
+/// Renames one file without ever replacing another one, creating the new folders it needs.
+/// A file whose new name is on another file system is copied there and deleted.
+pub fn rename_checked(fs: &dyn FileSystem, old: &Path, new: &Path, verify_hash: bool) -> Result<Transfer, String> {
+        // Check if the file still existing in case its moved before rename
+        if !fs.exists(old) {
+                return Err("Source no longer exists".into());
+        }
+        // Never overwrite, except a case only rename on a case insensitive file system
+        if fs.exists(new) && !fs.same_file(old, new) {
+                return Err(format!("{} already exists", new.display()));
+        }
+        // Create the destination folder when the new name includes sub folders
+        if let Some(parent) = new.parent() {
+                if !fs.exists(parent) {
+                        fs.create_dir_all(parent)
+                                .map_err(|e| format!("Unable to create destination folder: {}", e))?;
+                }
+        }
+        match fs.rename(old, new) {
+                Ok(()) => Ok(Transfer::Renamed),
+                Err(e) if e.kind() == io::ErrorKind::CrossesDevices => move_across(fs, old, new, verify_hash).map(|()| Transfer::Moved),
+                Err(e) => Err(e.to_string()),
+        }
+}
+
+/// Moves a file where `rename` cannot: copies it with its permissions and times, flushes the copy
+/// to disk, checks its size and optionally its hash, then deletes the original.
+pub fn move_across(fs: &dyn FileSystem, old: &Path, new: &Path, verify_hash: bool) -> Result<(), String> {
+        let entry = fs.entry(old).map_err(|e| e.to_string())?;
+        if entry.is_dir {
+                return Err(String::from("folders cannot be moved to another drive"));
+        }
+        fs.copy_new(old, new).map_err(|e| format!("Unable to copy to {}: {}", new.display(), e))?;
+        if let Err(error) = verify_copy(fs, old, new, entry.len, verify_hash) {
+                let _ = fs.remove_file(new);
+                return Err(error);
+        }
+        if let Err(e) = fs.remove_file(old) {
+                // The file stays where it was rather than existing twice
+                let _ = fs.remove_file(new);
+                return Err(format!("unable to delete the original: {}", e));
+        }
+        Ok(())
+}
+
+fn verify_copy(fs: &dyn FileSystem, old: &Path, new: &Path, len: u64, verify_hash: bool) -> Result<(), String> {
+        let copied = fs.entry(new).map_err(|e| e.to_string())?.len;
+        if copied != len {
+                return Err(format!("copy has {} bytes, the original {}", copied, len));
+        }
+        if verify_hash {
+                let digest = |path: &Path| fs.hash(path, HashAlgorithm::Blake3).map_err(|e| e.to_string());
+                if digest(old)? != digest(new)? {
+                        return Err(String::from("copy differs from the original"));
+                }
+        }
+        Ok(())
+}
+
+/// Renames that undo `steps`, in the order they have to run.
+pub fn undo(steps: &[(PathBuf, PathBuf)]) -> Vec<(PathBuf, PathBuf)> {
+        steps.iter().rev().map(|(old, new)| (new.clone(), old.clone())).collect()
+}
+
+/// Hidden name next to `path` that is not used by any file of the plan.
+fn temp_path(fs: &dyn FileSystem, path: &Path, taken: &HashSet<PathBuf>) -> PathBuf {
+        let name = path.file_name().unwrap_or_default();
+        (1..).map(|n| {
+                let mut temp = OsString::from(".");
+                temp.push(name);
+                temp.push(format!(".rename-{}", n));
+                path.with_file_name(temp)
+        })
+        .find(|temp| !taken.contains(temp) && !fs.exists(temp))
+        .expect("unbounded range")
+}
+
+/// Script language for an exported plan.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptKind {
+        Shell,
+        PowerShell,
+}
+
+impl ScriptKind {
+        pub fn from_path(path: &Path) -> ScriptKind {
+                match path.extension().map(|ext| ext.to_string_lossy().to_lowercase()).as_deref() {
+                        Some("ps1") => ScriptKind::PowerShell,
+                        _ => ScriptKind::Shell,
+                }
+        }
+}
+
+/// Script running `steps` in order. Existing targets are never overwritten,
+/// the script reports them and exits with an error once it is done.
+pub fn script(kind: ScriptKind, steps: &[(PathBuf, PathBuf)]) -> Vec<u8> {
+        match kind {
+                ScriptKind::Shell => shell_script(steps),
+                ScriptKind::PowerShell => powershell_script(steps),
+        }
+}
+
+fn shell_script(steps: &[(PathBuf, PathBuf)]) -> Vec<u8> {
+        let mut script = b"#!/bin/sh\n# Generated by File Renamer\nstatus=0\n".to_vec();
+        for (folder, old, new) in with_new_folders(steps) {
+                if let Some(folder) = folder {
+                        script.extend_from_slice(b"mkdir -p -- ");
+                        script.extend_from_slice(&shell_quote(folder));
+                        script.extend_from_slice(b" || status=1\n");
+                }
+                let (old, new) = (shell_quote(old), shell_quote(new));
+                script.extend_from_slice(b"if [ -e ");
+                script.extend_from_slice(&new);
+                script.extend_from_slice(b" ]; then echo \"exists: \"");
+                script.extend_from_slice(&new);
+                script.extend_from_slice(b" >&2; status=1; else mv -n -- ");
+                script.extend_from_slice(&old);
+                script.push(b' ');
+                script.extend_from_slice(&new);
+                script.extend_from_slice(b" || status=1; fi\n");
+        }
+        script.extend_from_slice(b"exit $status\n");
+        script
+}
+
+fn powershell_script(steps: &[(PathBuf, PathBuf)]) -> Vec<u8> {
+        let mut script = String::from("# Generated by File Renamer\n$status = 0\n");
+        for (folder, old, new) in with_new_folders(steps) {
+                if let Some(folder) = folder {
+                        script.push_str(&format!("New-Item -ItemType Directory -Force -Path {} | Out-Null\n", powershell_quote(folder)));
+                }
+                let (old, new) = (powershell_quote(old), powershell_quote(new));
+                script.push_str(&format!(
+                        "if (Test-Path -LiteralPath {new}) {{ Write-Error (\"exists: \" + {new}); $status = 1 }} \
+                        else {{ try {{ Move-Item -LiteralPath {old} -Destination {new} -ErrorAction Stop }} catch {{ Write-Error $_; $status = 1 }} }}\n",
+                ));
+        }
+        script.push_str("exit $status\n");
+        // UTF-8 with BOM so Windows PowerShell reads non ASCII names correctly
+        let mut bytes = "\u{feff}".as_bytes().to_vec();
+        bytes.extend_from_slice(script.as_bytes());
+        bytes
+}
+
+/// Pairs each step with the folder to create first, when it moves into a new folder.
+fn with_new_folders(steps: &[(PathBuf, PathBuf)]) -> Vec<(Option<&Path>, &Path, &Path)> {
+        let mut created: HashSet<&Path> = HashSet::new();
+        steps.iter().map(|(old, new)| {
+                let folder = new.parent()
+                        .filter(|folder| Some(*folder) != old.parent() && created.insert(folder));
+                (folder, old.as_path(), new.as_path())
+        }).collect()
+}
+
+/// Single quoted for `sh`, works for any bytes but NUL, which paths cannot contain.
+fn shell_quote(path: &Path) -> Vec<u8> {
+        #[cfg(unix)]
+        let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+        #[cfg(not(unix))]
+        let bytes = path.to_string_lossy().into_owned().into_bytes();
+
+        let mut quoted = vec![b'\''];
+        for byte in bytes {
+                if byte == b'\'' {
+                        quoted.extend_from_slice(b"'\\''");
+                } else {
+                        quoted.push(byte);
+                }
+        }
+        quoted.push(b'\'');
+        quoted
+}
+
+/// Single quoted for PowerShell, which also ends strings on typographic single quotes.
+fn powershell_quote(path: &Path) -> String {
+        let mut quoted = String::from("'");
+        for c in path.to_string_lossy().chars() {
+                if matches!(c, '\'' | '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}') {
+                        quoted.push(c);
+                }
+                quoted.push(c);
+        }
+        quoted.push('\'');
+        quoted
+}
@@ -0,0 +1,302 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, RwLock};
+use wasmi::{Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::action::{self, Action, FileContext, StateValue};
+
+/// Instructions a WASM plugin may run per file.
+const WASM_FUEL: u64 = 10_000_000;
+/// Largest linear memory a WASM plugin may grow to.
+const WASM_MEMORY: usize = 64 << 20;
+
+/// One input of a plugin action, a text field or a pick list when `choices` is set.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Field {
+        pub label: String,
+        pub placeholder: String,
+        pub choices: Vec<String>,
+}
+
+impl Field {
+        pub fn text(label: &str, placeholder: &str) -> Field {
+                Field { label: label.to_owned(), placeholder: placeholder.to_owned(), choices: Vec::new() }
+        }
+
+        /// Value of a new step, the first choice of a pick list.
+        pub fn default_value(&self) -> String {
+                self.choices.first().cloned().unwrap_or_default()
+        }
+}
+
+/// An action type known by its id, listed in the action picker next to the built-in ones.
+pub trait ActionPlugin: Send + Sync {
+        /// Saved in pipelines, so it should never change.
+        fn id(&self) -> &str;
+        /// Shown in the action picker.
+        fn name(&self) -> &str;
+        fn fields(&self) -> Vec<Field>;
+        /// Action for the field values, in the order of `fields`.
+        fn build(&self, values: &[String]) -> Box<dyn Action>;
+}
+
+struct Registry {
+        plugins: Vec<Arc<dyn ActionPlugin>>,
+        /// Id and reason of every plugin file that failed to load.
+        load_errors: Vec<(String, String)>,
+}
+
+static REGISTRY: LazyLock<RwLock<Registry>> = LazyLock::new(|| {
+        let mut registry = Registry { plugins: Vec::new(), load_errors: Vec::new() };
+        if let Some(folder) = plugins_folder() {
+                for (id, result) in load_folder(&folder) {
+                        match result {
+                                Ok(plugin) => registry.plugins.push(Arc::new(plugin)),
+                                Err(error) => registry.load_errors.push((id, error)),
+                        }
+                }
+        }
+        RwLock::new(registry)
+});
+
+/// Adds `plugin` to the picker, replacing a plugin with the same id.
+#[cfg(test)]
+pub fn register(plugin: Arc<dyn ActionPlugin>) {
+        let mut registry = REGISTRY.write().expect("plugin registry poisoned");
+        registry.plugins.retain(|registered| registered.id() != plugin.id());
+        registry.plugins.push(plugin);
+}
+
+/// Removes plugin `id` from the picker, steps using it report it as missing.
+#[cfg(test)]
+pub fn unregister(id: &str) {
+        REGISTRY.write().expect("plugin registry poisoned").plugins.retain(|registered| registered.id() != id);
+}
+
+pub fn plugins() -> Vec<Arc<dyn ActionPlugin>> {
+        REGISTRY.read().expect("plugin registry poisoned").plugins.clone()
+}
+
+/// Id and reason of every plugin file of `plugins_folder` that failed to load.
+pub fn load_errors() -> Vec<(String, String)> {
+        REGISTRY.read().expect("plugin registry poisoned").load_errors.clone()
+}
+
+pub fn find(id: &str) -> Option<Arc<dyn ActionPlugin>> {
+        plugins().into_iter().find(|plugin| plugin.id() == id)
+}
+
+/// Action of plugin `id`, or one that reports the plugin as missing.
+pub fn build(id: &str, values: &[String]) -> Box<dyn Action> {
+        match find(id) {
+                Some(plugin) => plugin.build(values),
+                None => Box::new(Missing { id: id.to_owned() }),
+        }
+}
+
+/// `FILE_RENAMER_PLUGINS`, or `file-renamer/plugins` in the user's config folder.
+pub fn plugins_folder() -> Option<PathBuf> {
+        if let Some(folder) = std::env::var_os("FILE_RENAMER_PLUGINS") {
+                return Some(PathBuf::from(folder));
+        }
+        let config = if cfg!(windows) {
+                std::env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+                std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+                        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        };
+        config.map(|config| config.join("file-renamer").join("plugins"))
+}
+
+/// Every `.wasm` file of `folder` with the id it is saved under, a missing folder has no plugins.
+pub fn load_folder(folder: &Path) -> Vec<(String, Result<WasmPlugin, String>)> {
+        let Ok(entries) = std::fs::read_dir(folder) else { return Vec::new(); };
+        let mut paths: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("wasm")))
+                .collect();
+        paths.sort();
+        paths.iter().map(|path| {
+                let id = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+                let plugin = std::fs::read(path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|bytes| WasmPlugin::new(&id, &bytes))
+                        .map_err(|e| format!("{}: {}", path.display(), e));
+                (id, plugin)
+        }).collect()
+}
+
+/// Step whose plugin is not installed, it keeps every name and says why.
+struct Missing {
+        id: String,
+}
+
+impl Action for Missing {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                file_name.to_owned()
+        }
+
+        fn try_action(&self, _file_name: &PathBuf, _ctx: &FileContext) -> Result<PathBuf, String> {
+                Err(format!("plugin `{}` {}", self.id, self.reason()))
+        }
+
+        fn update_values(&mut self,_data: StateValue) {}
+
+        fn validation_errors(&self) -> Vec<String> {
+                vec![format!("Plugin `{}` {}", self.id, self.reason())]
+        }
+}
+
+impl Missing {
+        fn reason(&self) -> String {
+                match load_errors().into_iter().find(|(id, _)| *id == self.id) {
+                        Some((_, error)) => format!("failed to load: {}", error),
+                        None => String::from("is not installed"),
+                }
+        }
+}
+
+/// Action type loaded from a WebAssembly module, which gets no imports and so no file system access.
+///
+/// The module exports `memory`, `alloc(len: i32) -> i32` and `rename(ptr: i32, len: i32) -> i64`.
+/// `rename` reads the file name followed by the field values, all separated by NUL bytes,
+/// and returns `ptr << 32 | len` of its output: a status byte, `0` for a new name or `1` for an error
+/// message, followed by UTF-8 text. Optional `name() -> i64` and `fields() -> i64` return text the same
+/// way without a status byte, `fields` as one `label|placeholder` per line.
+pub struct WasmPlugin {
+        id: String,
+        name: String,
+        fields: Vec<Field>,
+        module: Arc<WasmModule>,
+}
+
+/// Compiled module shared by the plugin and the actions built from it.
+struct WasmModule {
+        engine: Engine,
+        module: Module,
+}
+
+impl WasmPlugin {
+        pub fn new(id: &str, wasm: &[u8]) -> Result<WasmPlugin, String> {
+                let mut config = wasmi::Config::default();
+                config.consume_fuel(true);
+                let engine = Engine::new(&config);
+                let module = Module::new(&engine, wasm).map_err(|e| e.to_string())?;
+                let module = Arc::new(WasmModule { engine, module });
+                let mut plugin = WasmPlugin { id: id.to_owned(), name: id.to_owned(), fields: Vec::new(), module };
+
+                let (mut store, instance) = plugin.module.instantiate()?;
+                if let Ok(name) = instance.get_typed_func::<(), i64>(&store, "name") {
+                        let packed = name.call(&mut store, ()).map_err(|e| e.to_string())?;
+                        plugin.name = read_text(&store, &instance, packed)?;
+                }
+                if let Ok(fields) = instance.get_typed_func::<(), i64>(&store, "fields") {
+                        let packed = fields.call(&mut store, ()).map_err(|e| e.to_string())?;
+                        plugin.fields = read_text(&store, &instance, packed)?
+                                .lines()
+                                .filter(|line| !line.trim().is_empty())
+                                .map(|line| {
+                                        let (label, placeholder) = line.split_once('|').unwrap_or((line, ""));
+                                        Field::text(label.trim(), placeholder.trim())
+                                })
+                                .collect();
+                }
+                Ok(plugin)
+        }
+}
+
+impl WasmModule {
+        /// A fresh instance for every call, so no state leaks from one file to the next.
+        fn instantiate(&self) -> Result<(Store<StoreLimits>, wasmi::Instance), String> {
+                let mut store = Store::new(&self.engine, StoreLimitsBuilder::new().memory_size(WASM_MEMORY).build());
+                store.limiter(|limits| limits);
+                store.set_fuel(WASM_FUEL).map_err(|e| e.to_string())?;
+                let instance = Linker::<StoreLimits>::new(&self.engine)
+                        .instantiate(&mut store, &self.module)
+                        .and_then(|instance| instance.start(&mut store))
+                        .map_err(|e| e.to_string())?;
+                Ok((store, instance))
+        }
+
+        fn rename(&self, input: &[u8]) -> Result<String, String> {
+                let (mut store, instance) = self.instantiate()?;
+                let memory = instance.get_memory(&store, "memory").ok_or("plugin exports no memory")?;
+                let len = i32::try_from(input.len()).map_err(|_| "name is too long")?;
+                let ptr = instance.get_typed_func::<i32, i32>(&store, "alloc")
+                        .and_then(|alloc| alloc.call(&mut store, len))
+                        .map_err(|e| format!("alloc: {}", e))?;
+                memory.write(&mut store, ptr as u32 as usize, input).map_err(|e| e.to_string())?;
+                let packed = instance.get_typed_func::<(i32, i32), i64>(&store, "rename")
+                        .and_then(|rename| rename.call(&mut store, (ptr, len)))
+                        .map_err(|e| format!("rename: {}", e))?;
+                let output = read_text(&store, &instance, packed)?;
+                match output.split_at_checked(1) {
+                        Some(("\0", name)) => Ok(name.to_owned()),
+                        Some(("\u{1}", error)) => Err(error.to_owned()),
+                        _ => Err(String::from("plugin returned no status byte")),
+                }
+        }
+}
+
+/// UTF-8 text at `ptr << 32 | len` in the module's memory.
+fn read_text(store: &Store<StoreLimits>, instance: &wasmi::Instance, packed: i64) -> Result<String, String> {
+        let memory = instance.get_memory(store, "memory").ok_or("plugin exports no memory")?;
+        let ptr = (packed as u64 >> 32) as usize;
+        let len = (packed as u64 & 0xffff_ffff) as usize;
+        let bytes = memory.data(store).get(ptr..ptr + len).ok_or("plugin returned text outside its memory")?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| String::from("plugin returned invalid UTF-8"))
+}
+
+impl ActionPlugin for WasmPlugin {
+        fn id(&self) -> &str {
+                &self.id
+        }
+
+        fn name(&self) -> &str {
+                &self.name
+        }
+
+        fn fields(&self) -> Vec<Field> {
+                self.fields.clone()
+        }
+
+        fn build(&self, values: &[String]) -> Box<dyn Action> {
+                let mut input = Vec::new();
+                for value in values {
+                        input.push(0);
+                        input.extend_from_slice(value.as_bytes());
+                }
+                Box::new(WasmAction { module: self.module.clone(), values: input })
+        }
+}
+
+struct WasmAction {
+        module: Arc<WasmModule>,
+        /// Field values, each after a NUL byte, sent after the file name.
+        values: Vec<u8>,
+}
+
+impl Action for WasmAction {
+        fn action(&self,file_name: &PathBuf) -> PathBuf {
+                self.action_with(file_name, &FileContext::default())
+        }
+
+        /// Files the plugin fails on keep their name.
+        fn action_with(&self,file_name: &PathBuf, ctx: &FileContext) -> PathBuf {
+                self.try_action(file_name, ctx).unwrap_or_else(|_| file_name.to_owned())
+        }
+
+        fn try_action(&self, file_name: &PathBuf, _ctx: &FileContext) -> Result<PathBuf, String> {
+                let Some(name) = file_name.file_name() else { return Ok(file_name.to_owned()); };
+                let mut input = name.to_string_lossy().into_owned().into_bytes();
+                input.extend_from_slice(&self.values);
+                let new_name = self.module.rename(&input)?;
+                if new_name.trim().is_empty() {
+                        return Err(String::from("plugin returned an empty name"));
+                }
+                let mut new_path = file_name.to_owned();
+                action::set_stem(&mut new_path, new_name, None);
+                Ok(new_path)
+        }
+
+        fn update_values(&mut self,_data: StateValue) {}
+}
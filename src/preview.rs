@@ -0,0 +1,216 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::action::{self, CommandResults, FileContext};
+use crate::companion::{self, Companion};
+use crate::duplicate::{self, Duplicate, DuplicateHandling};
+use crate::hash::HashCache;
+use crate::metadata::{FileMeta, TimeZoneChoice};
+use crate::app::ActionOptions;
+use crate::pipeline::Step;
+use crate::validate::{self, TargetProfile};
+
+/// Rows between two checks for a newer preview request.
+const CANCEL_CHECK: usize = 1024;
+
+/// What the preview needs to know about one row of the file list.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PreviewRow {
+        pub old_path: PathBuf,
+        pub meta: Option<FileMeta>,
+        pub companion: Option<Companion>,
+        pub duplicate: Option<Duplicate>,
+}
+
+/// Everything a preview run reads, so it can run away from the UI thread.
+pub struct PreviewJob {
+        pub rows: Arc<Vec<PreviewRow>>,
+        pub steps: Vec<Step>,
+        pub time_zone: TimeZoneChoice,
+        pub hashes: Arc<HashCache>,
+        /// Changes whenever `hashes` gains a digest.
+        pub hash_version: u64,
+        pub duplicate_handling: DuplicateHandling,
+        pub profile: TargetProfile,
+        /// Cache of the last finished run.
+        pub previous: Option<Arc<PreviewCache>>,
+}
+
+/// Names of the primary rows after each step, errors included.
+#[derive(Debug)]
+struct Stage {
+        paths: Vec<PathBuf>,
+        errors: Vec<Vec<String>>,
+}
+
+/// Steps of a finished run, reused up to the first step that changed.
+#[derive(Debug)]
+pub struct PreviewCache {
+        rows: Arc<Vec<PreviewRow>>,
+        steps: Vec<Step>,
+        time_zone: TimeZoneChoice,
+        hash_version: u64,
+        stages: Vec<Arc<Stage>>,
+        /// Output of each command step, kept while the step is unchanged.
+        commands: Vec<(Step, CommandResults)>,
+}
+
+impl PreviewCache {
+        /// The rows this run was computed for.
+        pub fn rows(&self) -> &[PreviewRow] {
+                &self.rows
+        }
+
+        /// Leading steps whose stored output is still valid for `job`, new digests only
+        /// invalidate the steps from the first one that reads them.
+        fn reusable_steps(&self, job: &PreviewJob) -> usize {
+                if !Arc::ptr_eq(&self.rows, &job.rows) || self.time_zone != job.time_zone {
+                        return 0;
+                }
+                let hashes_changed = self.hash_version != job.hash_version;
+                self.steps.iter().zip(&job.steps)
+                        .take_while(|(old, new)| old == new && !(hashes_changed && !new.hash_algorithms().is_empty()))
+                        .count()
+                        .min(self.stages.len())
+        }
+}
+
+/// New path, step errors and problems for every row.
+#[derive(Debug, Clone)]
+pub struct PreviewOutput {
+        pub new_paths: Vec<PathBuf>,
+        pub errors: Vec<Vec<String>>,
+        pub problems: Vec<Vec<String>>,
+        pub cache: Arc<PreviewCache>,
+}
+
+/// Runs the steps, duplicate handling, companions and validation.
+/// Returns `None` as soon as `cancel` is set.
+pub fn compute(job: &PreviewJob, cancel: &AtomicBool) -> Option<PreviewOutput> {
+        let primaries: Vec<usize> = (0..job.rows.len()).filter(|row| job.rows[*row].companion.is_none()).collect();
+        let reused = job.previous.as_ref().map_or(0, |previous| previous.reusable_steps(job));
+        let mut stages: Vec<Arc<Stage>> = job.previous.as_ref()
+                .map(|previous| previous.stages[..reused].to_vec())
+                .unwrap_or_default();
+        let mut current = stages.last().cloned().unwrap_or_else(|| Arc::new(Stage {
+                paths: primaries.iter().map(|row| job.rows[*row].old_path.clone()).collect(),
+                errors: vec![Vec::new(); primaries.len()],
+        }));
+
+        let commands: Vec<(Step, CommandResults)> = job.steps.iter()
+                .filter(|step| step.action == ActionOptions::Command)
+                .map(|step| {
+                        let results = job.previous.iter()
+                                .flat_map(|previous| &previous.commands)
+                                .find(|(previous, _)| previous == step)
+                                .map(|(_, results)| results.clone())
+                                .unwrap_or_default();
+                        (step.clone(), results)
+                })
+                .collect();
+
+        let context = |k: usize, index: usize| {
+                let row = &job.rows[primaries[k]];
+                FileContext {
+                        original: Some(&row.old_path),
+                        index,
+                        meta: row.meta.as_ref(),
+                        time_zone: job.time_zone,
+                        hashes: Some(&job.hashes),
+                }
+        };
+
+        // One step at a time over the whole batch, so steps can look at the other names
+        for (i, step) in job.steps.iter().enumerate().skip(reused) {
+                let mut action = match commands.iter().find(|(command, _)| command == step) {
+                        Some((_, results)) => step.build_cached(results.clone()),
+                        None => step.build(),
+                };
+                let condition = step.condition();
+                let mut paths = current.paths.clone();
+                let mut errors = current.errors.clone();
+
+                let mut matching = Vec::new();
+                for k in 0..paths.len() {
+                        if k % CANCEL_CHECK == 0 && cancel.load(Ordering::Relaxed) {
+                                return None;
+                        }
+                        if condition.matches(&paths[k], &context(k, 0)) {
+                                matching.push(k);
+                        }
+                }
+                let inputs: Vec<&Path> = matching.iter().map(|k| paths[*k].as_path()).collect();
+                action.prepare(&inputs);
+
+                // Counters of a conditional step only count the files it runs on
+                for (index, k) in matching.into_iter().enumerate() {
+                        if index % CANCEL_CHECK == 0 && cancel.load(Ordering::Relaxed) {
+                                return None;
+                        }
+                        let result = action.try_action(&paths[k], &context(k, index)).and_then(|new_path| {
+                                match action::empty_stem_error(&paths[k], &new_path) {
+                                        Some(error) => Err(error),
+                                        None => Ok(new_path),
+                                }
+                        });
+                        match result {
+                                Ok(new_path) => paths[k] = new_path,
+                                Err(error) => errors[k].push(format!("Step {}: {}", i + 1, error)),
+                        }
+                }
+                current = Arc::new(Stage { paths, errors });
+                stages.push(current.clone());
+        }
+
+        let mut new_paths: Vec<PathBuf> = job.rows.iter().map(|row| row.old_path.clone()).collect();
+        let mut errors = vec![Vec::new(); job.rows.len()];
+        for (k, &row) in primaries.iter().enumerate() {
+                new_paths[row] = current.paths[k].clone();
+                errors[row] = current.errors[k].clone();
+        }
+
+        // Extra copies of the same content are left alone or marked
+        for (row, new_path) in job.rows.iter().zip(new_paths.iter_mut()) {
+                if let (Some(Duplicate { copy: copy @ 1.., .. }), None) = (row.duplicate, &row.companion) {
+                        match job.duplicate_handling {
+                                DuplicateHandling::Keep => {},
+                                DuplicateHandling::Exclude => *new_path = row.old_path.clone(),
+                                DuplicateHandling::Suffix => *new_path = duplicate::dup_path(new_path, copy),
+                        }
+                }
+        }
+
+        // Companions take the new stem of their primary file
+        let primary_paths: HashMap<&Path, PathBuf> = primaries.iter()
+                .map(|row| (job.rows[*row].old_path.as_path(), new_paths[*row].clone()))
+                .collect();
+        for (row, new_path) in job.rows.iter().zip(new_paths.iter_mut()) {
+                let Some(link) = &row.companion else { continue; };
+                *new_path = match primary_paths.get(link.primary.as_path()) {
+                        Some(primary_new) => companion::companion_path(primary_new, link),
+                        None => row.old_path.clone(),
+                };
+        }
+        if cancel.load(Ordering::Relaxed) {
+                return None;
+        }
+
+        let files: Vec<(&Path, &Path)> = job.rows.iter().zip(&new_paths)
+                .map(|(row, new_path)| (row.old_path.as_path(), new_path.as_path()))
+                .collect();
+        let problems = validate::validate_batch(job.profile, &files).into_iter().zip(&errors)
+                .map(|(problems, errors)| errors.iter().cloned().chain(problems).collect())
+                .collect();
+
+        let cache = Arc::new(PreviewCache {
+                rows: job.rows.clone(),
+                steps: job.steps.clone(),
+                time_zone: job.time_zone,
+                hash_version: job.hash_version,
+                stages,
+                commands,
+        });
+        Some(PreviewOutput { new_paths, errors, problems, cache })
+}
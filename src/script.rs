@@ -0,0 +1,99 @@
+use rhai::{Dynamic, Engine, Map, Scope, AST};
+
+use crate::metadata::{self, FileMeta, TimeField, TimeZoneChoice};
+
+/// Steps a script may run per file before it is stopped, so endless loops cannot hang the preview.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// A compiled Rhai script that returns the new stem of a file.
+/// The engine has no file system, module or `eval` access and no output.
+pub struct Program {
+        engine: Engine,
+        /// `None` for an empty script, which keeps names as they are.
+        ast: Result<Option<AST>, String>,
+}
+
+impl std::fmt::Debug for Program {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.debug_struct("Program").field("ast", &self.ast.as_ref().map(|_| "compiled")).finish()
+        }
+}
+
+/// What a script can read about one file.
+pub struct ScriptInput<'a> {
+        pub stem: &'a str,
+        pub ext: Option<&'a str>,
+        /// File name before any step ran.
+        pub original: &'a str,
+        pub index: usize,
+        pub meta: Option<&'a FileMeta>,
+        pub time_zone: TimeZoneChoice,
+}
+
+impl Program {
+        pub fn new(source: &str) -> Program {
+                let engine = sandbox();
+                let ast = if source.trim().is_empty() {
+                        Ok(None)
+                } else {
+                        engine.compile(source).map(Some).map_err(|e| e.to_string())
+                };
+                Program { engine, ast }
+        }
+
+        pub fn error(&self) -> Option<&str> {
+                self.ast.as_ref().err().map(String::as_str)
+        }
+
+        /// Runs the script with `stem`, `ext`, `original`, `index` and `meta` in scope.
+        /// Returns the new stem, and the new extension when the script assigned `ext`.
+        pub fn run(&self, input: &ScriptInput) -> Result<(String, Option<String>), String> {
+                let Some(ast) = self.ast.as_ref()? else { return Ok((input.stem.to_owned(), None)); };
+                let ext = input.ext.unwrap_or_default();
+                let mut scope = Scope::new();
+                scope.push("stem", input.stem.to_owned());
+                scope.push("ext", ext.to_owned());
+                scope.push("original", input.original.to_owned());
+                scope.push("index", input.index as i64);
+                scope.push_constant("meta", meta_map(input.meta, input.time_zone));
+
+                let result: Dynamic = self.engine.eval_ast_with_scope(&mut scope, ast).map_err(|e| e.to_string())?;
+                let type_name = result.type_name();
+                let stem = result.into_string().map_err(|_| format!("script returned {}, expected a string", type_name))?;
+                if stem.trim().is_empty() {
+                        return Err(String::from("script returned an empty name"));
+                }
+                let new_ext = scope.get_value::<String>("ext").filter(|new_ext| new_ext != ext);
+                Ok((stem, new_ext))
+        }
+}
+
+fn sandbox() -> Engine {
+        let mut engine = Engine::new();
+        // `import` would read script files from disk
+        engine.set_module_resolver(rhai::module_resolvers::DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+        engine.on_print(|_| {});
+        engine.on_debug(|_, _, _| {});
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(10_000);
+        engine.set_max_map_size(10_000);
+        engine
+}
+
+/// `size`, `modified`, `created`, `accessed`, `owner` and `group`, missing values are `()`.
+fn meta_map(meta: Option<&FileMeta>, time_zone: TimeZoneChoice) -> Map {
+        let mut map = Map::new();
+        let Some(meta) = meta else { return map; };
+        map.insert("size".into(), (meta.size as i64).into());
+        for (name, field) in [("modified", TimeField::Modified), ("created", TimeField::Created), ("accessed", TimeField::Accessed)] {
+                let time = meta.time(field).and_then(|time| metadata::format_time(time, "%Y-%m-%d %H:%M:%S", time_zone));
+                map.insert(name.into(), time.map_or(Dynamic::UNIT, Dynamic::from));
+        }
+        map.insert("owner".into(), meta.owner.clone().map_or(Dynamic::UNIT, Dynamic::from));
+        map.insert("group".into(), meta.group.clone().map_or(Dynamic::UNIT, Dynamic::from));
+        map
+}
@@ -0,0 +1,74 @@
+use std::cmp::Ordering;
+
+/// Order of the file list, which is also the order used by counters and the rename.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum SortKey {
+        /// Keep the loaded order, rows can be dragged around.
+        #[default]
+        Manual,
+        Name,
+        Modified,
+        Size,
+        ExifDate,
+        Extension,
+}
+
+impl SortKey {
+        pub const ALL: [SortKey; 6] = [
+                SortKey::Manual,
+                SortKey::Name,
+                SortKey::Modified,
+                SortKey::Size,
+                SortKey::ExifDate,
+                SortKey::Extension,
+        ];
+}
+
+/// Compares names the way people read them, `img2` comes before `img10`.
+/// Letters are compared without case, ties fall back to the exact text.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+        let mut left = a.chars().peekable();
+        let mut right = b.chars().peekable();
+        loop {
+                match (left.peek().copied(), right.peek().copied()) {
+                        (None, None) => return a.cmp(b),
+                        (None, Some(_)) => return Ordering::Less,
+                        (Some(_), None) => return Ordering::Greater,
+                        (Some(l), Some(r)) if l.is_ascii_digit() && r.is_ascii_digit() => {
+                                let l = take_number(&mut left);
+                                let r = take_number(&mut right);
+                                // Longer runs without leading zeros are bigger numbers
+                                let ordering = l.trim_start_matches('0').len().cmp(&r.trim_start_matches('0').len())
+                                        .then_with(|| l.trim_start_matches('0').cmp(r.trim_start_matches('0')));
+                                if ordering != Ordering::Equal {
+                                        return ordering;
+                                }
+                        },
+                        (Some(l), Some(r)) => {
+                                let ordering = l.to_lowercase().cmp(r.to_lowercase());
+                                if ordering != Ordering::Equal {
+                                        return ordering;
+                                }
+                                left.next();
+                                right.next();
+                        },
+                }
+        }
+}
+
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+        let mut number = String::new();
+        while let Some(c) = chars.next_if(char::is_ascii_digit) {
+                number.push(c);
+        }
+        number
+}
+
+/// Moves `items[from]` so that it ends up at index `to`.
+pub fn move_item<T>(items: &mut Vec<T>, from: usize, to: usize) {
+        if from == to || from >= items.len() || to >= items.len() {
+                return;
+        }
+        let item = items.remove(from);
+        items.insert(to, item);
+}
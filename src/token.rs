@@ -0,0 +1,348 @@
+use std::path::Path;
+use regex::{Captures, Regex};
+
+use crate::action::{FileContext, INVALID_CHARS};
+use crate::hash::{self, HashAlgorithm};
+use crate::metadata::{self, TimeField, DEFAULT_DATE_FORMAT};
+
+/// A piece of a template, either text to copy or a `{token}` with its `|` fallbacks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+        Literal(String),
+        Token(Vec<TokenExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenExpr {
+        pub name: String,
+        pub argument: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TokenError {
+        pub token: String,
+        pub message: String,
+}
+
+/// Values a token can be resolved against for one file.
+pub struct TokenValues<'a> {
+        pub ctx: &'a FileContext<'a>,
+        /// Name of the file as it enters the step.
+        pub current: &'a Path,
+        pub captures: Option<Captures<'a>>,
+}
+
+/// Widest zero padding of `{counter}`.
+const MAX_COUNTER_WIDTH: usize = 32;
+
+const TOKEN_NAMES: [&str; 21] = [
+        "stem", "ext", "original", "parent", "counter",
+        "date", "modified", "created", "accessed", "year", "month", "day",
+        "size", "owner", "group", "name",
+        "crc32", "md5", "sha1", "sha256", "blake3",
+];
+
+/// Replaces `{token}` and `{token:argument}` with values from the file context.
+/// Unknown tokens, or tokens without a value for this file, are kept as typed.
+/// Dots are allowed in values, other invalid characters are removed.
+pub fn expand_tokens(input: &str, values: &TokenValues) -> String {
+        let mut output = String::new();
+        let mut rest = input;
+
+        while let Some(start) = rest.find('{') {
+                output.push_str(&rest[..start]);
+                let Some(len) = rest[start..].find('}') else {
+                        rest = &rest[start..];
+                        break;
+                };
+                let token = parse_token(&rest[start + 1..start + len]);
+                match resolve(&token, values) {
+                        Some(value) => output.push_str(&value),
+                        None => output.push_str(&rest[start..=start + len]),
+                }
+                rest = &rest[start + len + 1..];
+        }
+        output.push_str(rest);
+        output
+}
+
+/// Splits a template into literal text and tokens.
+pub fn parse(input: &str) -> Result<Vec<Segment>, Vec<TokenError>> {
+        let mut segments = Vec::new();
+        let mut errors = Vec::new();
+        let mut rest = input;
+
+        while let Some(start) = rest.find(['{', '}']) {
+                if start > 0 {
+                        segments.push(Segment::Literal(rest[..start].to_owned()));
+                }
+                if rest[start..].starts_with('}') {
+                        errors.push(TokenError { token: "}".into(), message: "no matching `{`".into() });
+                        rest = &rest[start + 1..];
+                        continue;
+                }
+                let Some(len) = rest[start..].find('}') else {
+                        errors.push(TokenError { token: rest[start..].to_owned(), message: "missing closing `}`".into() });
+                        rest = "";
+                        break;
+                };
+                segments.push(Segment::Token(parse_token(&rest[start + 1..start + len])));
+                rest = &rest[start + len + 1..];
+        }
+        if !rest.is_empty() {
+                segments.push(Segment::Literal(rest.to_owned()));
+        }
+
+        if errors.is_empty() { Ok(segments) } else { Err(errors) }
+}
+
+/// Checks every token of a parsed template, `regex` is used to check capture group names.
+pub fn validate(segments: &[Segment], regex: Option<&Regex>) -> Vec<TokenError> {
+        let mut errors = Vec::new();
+        for segment in segments {
+                let Segment::Token(alternatives) = segment else { continue; };
+                let has_fallback = alternatives.len() > 1;
+                for (i, token) in alternatives.iter().enumerate() {
+                        let is_last = i == alternatives.len() - 1;
+                        if is_last && has_fallback && !is_known(token, regex) {
+                                continue; // plain text fallback
+                        }
+                        if let Err(message) = check_token(token, regex, has_fallback) {
+                                errors.push(TokenError { token: token_text(token), message });
+                        }
+                }
+        }
+        errors
+}
+
+/// Builds the text of a parsed template, tokens without a value become empty.
+pub fn render(segments: &[Segment], values: &TokenValues) -> String {
+        let mut output = String::new();
+        for segment in segments {
+                match segment {
+                        Segment::Literal(text) => output.push_str(text),
+                        Segment::Token(token) => output.push_str(&resolve(token, values).unwrap_or_default()),
+                }
+        }
+        output
+}
+
+/// Why a token of the template has no value for this file, when that is an error rather than
+/// a missing value, like a counter that runs out of range.
+pub fn render_error(segments: &[Segment], values: &TokenValues) -> Option<String> {
+        segments.iter().find_map(|segment| {
+                let Segment::Token(alternatives) = segment else { return None; };
+                if resolve(alternatives, values).is_some() {
+                        return None;
+                }
+                alternatives.iter()
+                        .filter(|token| token.name == "counter")
+                        .find_map(|token| counter(token.argument.as_deref(), values.ctx.index).err()
+                                .map(|message| format!("{}: {}", token_text(token), message)))
+        })
+}
+
+/// True for the names of built in tokens like `owner` or `sha256`.
+pub fn is_token_name(name: &str) -> bool {
+        TOKEN_NAMES.contains(&name)
+}
+
+/// Inside of a `{}` token with `clean` applied to its last alternative when that is plain fallback
+/// text rather than a token, so `{artist|a:b*?}` falls back to text that is valid in a name.
+pub fn clean_fallback(token: &str, regex: Option<&Regex>, clean: impl Fn(&str) -> String) -> String {
+        match token.rsplit_once('|') {
+                Some((alternatives, last)) if !is_known(&parse_token(last)[0], regex) => format!("{}|{}", alternatives, clean(last)),
+                _ => token.to_owned(),
+        }
+}
+
+/// True when `{name}` has a non empty value for this file.
+pub fn has_value(name: &str, values: &TokenValues) -> bool {
+        let token = TokenExpr { name: name.to_owned(), argument: None };
+        token_value(&token, values).is_some_and(|value| !value.is_empty())
+}
+
+fn parse_token(token: &str) -> Vec<TokenExpr> {
+        token.split('|').map(|alternative| {
+                let alternative = alternative.trim();
+                match alternative.split_once(':') {
+                        Some((name, argument)) => TokenExpr { name: name.to_owned(), argument: Some(argument.to_owned()) },
+                        None => TokenExpr { name: alternative.to_owned(), argument: None },
+                }
+        }).collect()
+}
+
+fn token_text(token: &TokenExpr) -> String {
+        match &token.argument {
+                Some(argument) => format!("{{{}:{}}}", token.name, argument),
+                None => format!("{{{}}}", token.name),
+        }
+}
+
+fn is_known(token: &TokenExpr, regex: Option<&Regex>) -> bool {
+        is_capture(&token.name, regex) || TOKEN_NAMES.contains(&token.name.as_str())
+}
+
+fn is_capture(name: &str, regex: Option<&Regex>) -> bool {
+        let Some(regex) = regex else { return false; };
+        match name.parse::<usize>() {
+                Ok(group) => group < regex.captures_len(),
+                Err(_) => regex.capture_names().flatten().any(|group| group == name),
+        }
+}
+
+fn check_token(token: &TokenExpr, regex: Option<&Regex>, has_fallback: bool) -> Result<(), String> {
+        if is_capture(&token.name, regex) {
+                return Ok(());
+        }
+        if token.name.parse::<usize>().is_ok() {
+                return Err(match regex {
+                        Some(_) => "capture group does not exist in the regex".into(),
+                        None => "capture groups need a regex".into(),
+                });
+        }
+        let argument = token.argument.as_deref();
+        if let Some(algorithm) = HashAlgorithm::from_token(&token.name) {
+                return match argument.map(str::parse::<usize>) {
+                        None => Ok(()),
+                        Some(Ok(length)) if (1..=algorithm.hex_len()).contains(&length) => Ok(()),
+                        _ => Err(format!("length must be between 1 and {}", algorithm.hex_len())),
+                };
+        }
+        match token.name.as_str() {
+                "stem" | "ext" | "original" | "owner" | "group" | "name" | "year" | "month" | "day" => match argument {
+                        None => Ok(()),
+                        Some(_) => Err("takes no argument".into()),
+                },
+                "parent" => match argument.map(str::parse::<usize>) {
+                        None | Some(Ok(1..)) => Ok(()),
+                        _ => Err("level must be a number of 1 or more".into()),
+                },
+                "counter" => argument.map_or(Ok(()), |argument| parse_counter(argument).map(|_| ())),
+                "date" | "modified" | "created" | "accessed" => {
+                        let format = argument.unwrap_or(DEFAULT_DATE_FORMAT);
+                        match metadata::format_time(std::time::UNIX_EPOCH, format, Default::default()) {
+                                Some(_) => Ok(()),
+                                None => Err(format!("invalid date format `{}`", format)),
+                        }
+                },
+                "size" => match argument {
+                        None | Some("bytes") | Some("human") => Ok(()),
+                        Some(_) => Err("argument must be `bytes` or `human`".into()),
+                },
+                // Absent values fall back, so unknown names are allowed with a fallback
+                _ if has_fallback => Ok(()),
+                _ => Err("unknown token".into()),
+        }
+}
+
+/// `{counter:WIDTH,START,STEP}`, every part is optional.
+fn parse_counter(argument: &str) -> Result<(usize, i64, i64), String> {
+        let mut parts = argument.split(',').map(str::trim);
+        let width = match parts.next() {
+                Some("") | None => 0,
+                Some(width) => match width.parse() {
+                        Ok(width) if width <= MAX_COUNTER_WIDTH => width,
+                        Ok(_) => return Err(format!("width must be at most {}", MAX_COUNTER_WIDTH)),
+                        Err(_) => return Err(format!("invalid width `{}`", width)),
+                },
+        };
+        let start = match parts.next() {
+                Some(start) => start.parse().map_err(|_| format!("invalid start `{}`", start))?,
+                None => 1,
+        };
+        let step = match parts.next() {
+                Some(step) => step.parse().map_err(|_| format!("invalid step `{}`", step))?,
+                None => 1,
+        };
+        if parts.next().is_some() {
+                return Err("expected `width,start,step`".into());
+        }
+        Ok((width, start, step))
+}
+
+/// Counter of the file at `index`, an error once it no longer fits in an `i64`.
+fn counter(argument: Option<&str>, index: usize) -> Result<String, String> {
+        let (width, start, step) = parse_counter(argument.unwrap_or(""))?;
+        let value = i64::try_from(index).ok()
+                .and_then(|index| step.checked_mul(index))
+                .and_then(|offset| start.checked_add(offset))
+                .ok_or_else(|| String::from("counter is out of range"))?;
+        Ok(format!("{:0width$}", value, width = width))
+}
+
+fn resolve(alternatives: &[TokenExpr], values: &TokenValues) -> Option<String> {
+        for (i, token) in alternatives.iter().enumerate() {
+                let is_last = i == alternatives.len() - 1;
+                let value = token_value(token, values).or_else(|| {
+                        // The last alternative of `{a|b|Fallback}` can be plain text
+                        let is_text = is_last && alternatives.len() > 1 && token.argument.is_none()
+                                && !TOKEN_NAMES.contains(&token.name.as_str())
+                                && token.name.parse::<usize>().is_err();
+                        is_text.then(|| token.name.clone())
+                });
+                if let Some(value) = value.filter(|value| !value.is_empty()) {
+                        return Some(value.chars().filter(|c| *c == '.' || !INVALID_CHARS.contains(c)).collect());
+                }
+        }
+        None
+}
+
+fn token_value(token: &TokenExpr, values: &TokenValues) -> Option<String> {
+        let argument = token.argument.as_deref();
+
+        if let Some(captures) = &values.captures {
+                let group = match token.name.parse::<usize>() {
+                        Ok(index) => captures.get(index),
+                        Err(_) => captures.name(&token.name),
+                };
+                if let Some(group) = group {
+                        return Some(group.as_str().to_owned());
+                }
+        }
+
+        let ctx = values.ctx;
+        if let Some(algorithm) = HashAlgorithm::from_token(&token.name) {
+                let modified = ctx.meta.and_then(|meta| meta.modified);
+                let digest = hash::lookup(ctx.hashes?, ctx.original?, modified, algorithm)?;
+                let length = argument.and_then(|length| length.parse().ok()).unwrap_or(digest.len());
+                return Some(digest[..length.min(digest.len())].to_owned());
+        }
+        match token.name.as_str() {
+                "stem" => values.current.file_stem().map(|stem| stem.to_string_lossy().into_owned()),
+                "ext" => values.current.extension().map(|ext| ext.to_string_lossy().into_owned()),
+                "original" => ctx.original?.file_stem().map(|stem| stem.to_string_lossy().into_owned()),
+                "parent" => {
+                        let level = argument.map_or(Some(1), |level| level.parse().ok())?;
+                        ctx.original?.ancestors().nth(level)?
+                                .file_name().map(|name| name.to_string_lossy().into_owned())
+                },
+                "counter" => counter(argument, ctx.index).ok(),
+                "date" | "modified" | "created" | "accessed" => {
+                        let field = match token.name.as_str() {
+                                "created" => TimeField::Created,
+                                "accessed" => TimeField::Accessed,
+                                _ => TimeField::Modified,
+                        };
+                        let format = argument.unwrap_or(DEFAULT_DATE_FORMAT);
+                        metadata::format_time(ctx.meta?.time(field)?, format, ctx.time_zone)
+                },
+                "year" | "month" | "day" => {
+                        let format = match token.name.as_str() {
+                                "year" => "%Y",
+                                "month" => "%m",
+                                _ => "%d",
+                        };
+                        metadata::format_time(ctx.meta?.modified?, format, ctx.time_zone)
+                },
+                "size" => match argument {
+                        None | Some("bytes") => Some(ctx.meta?.size.to_string()),
+                        Some("human") => Some(metadata::human_size(ctx.meta?.size)),
+                        _ => None,
+                },
+                "owner" => ctx.meta?.owner.clone(),
+                "group" => ctx.meta?.group.clone(),
+                "name" => ctx.original?.file_name().map(|name| name.to_string_lossy().into_owned()),
+                _ => None,
+        }
+}
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+const WINDOWS_FORBIDDEN: [char; 9] = ['<','>',':','"','/','\\','|','?','*'];
+const RESERVED_NAMES: [&str; 22] = [
+        "CON", "PRN", "AUX", "NUL",
+        "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+        "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// File system the renamed files are meant for.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TargetProfile {
+        Linux,
+        Windows,
+        Fat,
+        MacOs,
+}
+
+impl Default for TargetProfile {
+        fn default() -> Self {
+                if cfg!(windows) {
+                        TargetProfile::Windows
+                } else if cfg!(target_os = "macos") {
+                        TargetProfile::MacOs
+                } else {
+                        TargetProfile::Linux
+                }
+        }
+}
+
+impl TargetProfile {
+        pub const ALL: [TargetProfile; 4] = [
+                TargetProfile::Linux,
+                TargetProfile::Windows,
+                TargetProfile::Fat,
+                TargetProfile::MacOs,
+        ];
+
+        fn is_forbidden(&self, c: char) -> bool {
+                match self {
+                        TargetProfile::Linux => c == '/' || c == '\0',
+                        TargetProfile::Windows | TargetProfile::Fat => WINDOWS_FORBIDDEN.contains(&c) || c.is_control(),
+                        TargetProfile::MacOs => c == '/' || c == ':' || c == '\0',
+                }
+        }
+
+        fn max_length(&self, name: &str) -> (usize, usize, &'static str) {
+                match self {
+                        TargetProfile::Linux | TargetProfile::MacOs => (name.len(), 255, "bytes"),
+                        TargetProfile::Windows | TargetProfile::Fat => (name.encode_utf16().count(), 255, "characters"),
+                }
+        }
+
+        /// Key used to find names that end up as the same file on this file system,
+        /// Windows, FAT and default macOS volumes do not tell `a.txt` and `A.txt` apart.
+        pub fn collision_key(&self, path: &Path) -> String {
+                let path = path.to_string_lossy();
+                match self {
+                        TargetProfile::Linux => path.into_owned(),
+                        TargetProfile::Windows | TargetProfile::Fat => path.to_lowercase(),
+                        TargetProfile::MacOs => path.nfc().collect::<String>().to_lowercase(),
+                }
+        }
+}
+
+/// Problems with a single file or folder name on the target file system.
+pub fn check_name(profile: TargetProfile, name: &str) -> Vec<String> {
+        let mut problems = Vec::new();
+        if name.is_empty() || name == "." || name == ".." {
+                problems.push(String::from("empty name"));
+                return problems;
+        }
+
+        let mut forbidden: Vec<char> = name.chars().filter(|c| profile.is_forbidden(*c)).collect();
+        forbidden.sort_unstable();
+        forbidden.dedup();
+        if !forbidden.is_empty() {
+                let shown: String = forbidden.iter().map(|c| if c.is_control() { '\u{FFFD}' } else { *c }).collect();
+                problems.push(format!("forbidden characters `{}`", shown));
+        }
+
+        if matches!(profile, TargetProfile::Windows | TargetProfile::Fat) {
+                if name.ends_with(' ') || name.ends_with('.') {
+                        problems.push(String::from("ends with a space or dot"));
+                }
+                if is_reserved(name) {
+                        problems.push(String::from("reserved device name"));
+                }
+        }
+
+        let (length, max, unit) = profile.max_length(name);
+        if length > max {
+                problems.push(format!("too long ({} of {} {})", length, max, unit));
+        }
+        problems
+}
+
+/// Rewrites a file name so that `check_name` finds no problem, the extension is kept when possible.
+pub fn make_safe(profile: TargetProfile, name: &str) -> String {
+        let mut safe: String = name.chars().map(|c| if profile.is_forbidden(c) { '_' } else { c }).collect();
+
+        if matches!(profile, TargetProfile::Windows | TargetProfile::Fat) {
+                safe = safe.trim_end_matches([' ', '.']).to_owned();
+                if is_reserved(&safe) {
+                        safe.insert(0, '_');
+                }
+        }
+        if safe.is_empty() || safe == "." || safe == ".." {
+                safe = String::from("_");
+        }
+
+        // Shorten the stem and keep the extension
+        let (stem, ext) = match safe.rfind('.') {
+                Some(dot) if dot > 0 && profile.max_length(&safe[dot..]).0 < 255 => (safe[..dot].to_owned(), safe[dot..].to_owned()),
+                _ => (safe.clone(), String::new()),
+        };
+        let mut stem = stem;
+        while profile.max_length(&format!("{}{}", stem, ext)).0 > 255 && stem.pop().is_some() {}
+        // Shortening can end the stem on a space or dot again, or leave nothing of it
+        if matches!(profile, TargetProfile::Windows | TargetProfile::Fat) {
+                stem.truncate(stem.trim_end_matches([' ', '.']).len());
+        }
+        if stem.is_empty() {
+                stem.push('_');
+        }
+        format!("{}{}", stem, ext)
+}
+
+/// Collision keys of everything in `folder`, empty when it does not exist yet.
+fn existing_keys(profile: TargetProfile, folder: &Path) -> HashSet<String> {
+        std::fs::read_dir(folder)
+                .map(|entries| entries.filter_map(Result::ok).map(|entry| profile.collision_key(&folder.join(entry.file_name()))).collect())
+                .unwrap_or_default()
+}
+
+fn is_reserved(name: &str) -> bool {
+        let base = name.split('.').next().unwrap_or_default().trim_end();
+        RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(base))
+}
+
+/// Problems for every `(old, new)` pair: invalid names, duplicates inside the batch
+/// and existing files that would be overwritten.
+pub fn validate_batch(profile: TargetProfile, files: &[(&Path, &Path)]) -> Vec<Vec<String>> {
+        let mut problems: Vec<Vec<String>> = files.iter().map(|(old, new)| {
+                let mut problems = Vec::new();
+                if let Some(name) = new.file_name() {
+                        problems.extend(check_name(profile, &name.to_string_lossy()));
+                }
+                // New folders created by the rename
+                if let (Some(old_parent), Some(new_parent)) = (old.parent(), new.parent()) {
+                        if let Ok(folders) = new_parent.strip_prefix(old_parent) {
+                                for folder in folders.iter() {
+                                        problems.extend(check_name(profile, &folder.to_string_lossy())
+                                                .into_iter()
+                                                .map(|problem| format!("folder `{}`: {}", folder.to_string_lossy(), problem)));
+                                }
+                        }
+                }
+                problems
+        }).collect();
+
+        let old_keys: HashMap<String, usize> = files.iter().enumerate()
+                .map(|(i, (old, _))| (profile.collision_key(old), i))
+                .collect();
+        let mut new_keys: HashMap<String, usize> = HashMap::new();
+        // Each target folder is listed once instead of asking for every new name
+        let mut existing: HashMap<&Path, HashSet<String>> = HashMap::new();
+        for (i, (old, new)) in files.iter().enumerate() {
+                let key = profile.collision_key(new);
+                if let Some(first) = new_keys.get(&key) {
+                        let other = files[*first].0.file_name().unwrap_or_default().to_string_lossy();
+                        problems[i].push(format!("same new name as `{}`", other));
+                        let this = old.file_name().unwrap_or_default().to_string_lossy();
+                        problems[*first].push(format!("same new name as `{}`", this));
+                        continue;
+                }
+                new_keys.insert(key.clone(), i);
+
+                // Another file that is not part of the batch already has this name
+                if old != new && !old_keys.contains_key(&key) && new.parent().is_some_and(|folder| existing
+                        .entry(folder)
+                        .or_insert_with(|| existing_keys(profile, folder))
+                        .contains(&key)) {
+                        problems[i].push(String::from("a file with this name already exists"));
+                }
+        }
+        problems
+}
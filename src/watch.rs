@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant, SystemTime};
+use iced::futures::{SinkExt, Stream};
+use notify::{EventKind, RecursiveMode, Watcher};
+
+use crate::action::{self, Action, FileContext};
+use crate::condition::Condition;
+use crate::filesystem::RealFs;
+use crate::hash::{self, CachedHash, HashAlgorithm, HashCache};
+use crate::metadata::{FileMeta, TimeZoneChoice};
+use crate::pipeline::Pipeline;
+use crate::plan;
+use crate::validate::{self, TargetProfile};
+
+/// How long a new file has to keep the same size before it is renamed.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// One entry of the watch log.
+#[derive(Debug, Clone)]
+pub enum WatchEvent {
+        Started(PathBuf),
+        Renamed { old: PathBuf, new: PathBuf },
+        Skipped { path: PathBuf, reason: String },
+        Failed(String),
+}
+
+impl WatchEvent {
+        /// Files that were left alone and errors, logged apart from the renames.
+        pub fn is_failure(&self) -> bool {
+                matches!(self, WatchEvent::Skipped { .. } | WatchEvent::Failed(_))
+        }
+}
+
+impl std::fmt::Display for WatchEvent {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                match self {
+                        WatchEvent::Started(folder) => write!(f, "watching {}", folder.display()),
+                        WatchEvent::Renamed { old, new } => write!(f, "renamed {} -> {}", old.display(), new.display()),
+                        WatchEvent::Skipped { path, reason } => write!(f, "skipped {}: {}", path.display(), reason),
+                        WatchEvent::Failed(error) => write!(f, "error: {}", error),
+                }
+        }
+}
+
+/// Runs the pipeline on one file at a time, the counter keeps going for the whole session.
+pub struct Renamer {
+        steps: Vec<(Condition, Box<dyn Action>)>,
+        algorithms: Vec<HashAlgorithm>,
+        time_zone: TimeZoneChoice,
+        profile: TargetProfile,
+        index: usize,
+}
+
+impl Renamer {
+        pub fn new(pipeline: &Pipeline) -> Renamer {
+                let mut algorithms = Vec::new();
+                for step in pipeline.steps.iter().filter(|step| step.uses_tokens()) {
+                        for algorithm in hash::algorithms_in(&step.value) {
+                                if !algorithms.contains(&algorithm) {
+                                        algorithms.push(algorithm);
+                                }
+                        }
+                }
+                Renamer {
+                        steps: pipeline.steps.iter().map(|step| (step.condition(), step.build())).collect(),
+                        algorithms,
+                        time_zone: pipeline.time_zone(),
+                        profile: pipeline.target_profile,
+                        index: 0,
+                }
+        }
+
+        /// New path of `path` after every step, or the error of the first step that failed.
+        pub fn new_path(&mut self, path: &Path) -> Result<PathBuf, String> {
+                let meta = FileMeta::read(path);
+                let modified = meta.as_ref().and_then(|meta| meta.modified);
+                let mut hashes = HashCache::new();
+                for &algorithm in &self.algorithms {
+                        if let Ok(digest) = hash::hash_file(path, algorithm) {
+                                hashes.insert((path.to_path_buf(), algorithm), CachedHash { modified, digest });
+                        }
+                }
+                let ctx = FileContext {
+                        original: Some(path),
+                        index: self.index,
+                        meta: meta.as_ref(),
+                        time_zone: self.time_zone,
+                        hashes: Some(&hashes),
+                };
+
+                let mut new_path = path.to_path_buf();
+                for (condition, action) in &mut self.steps {
+                        if condition.matches(&new_path, &ctx) {
+                                action.prepare(&[new_path.as_path()]);
+                                let next = action.try_action(&new_path, &ctx)?;
+                                if let Some(error) = action::empty_stem_error(&new_path, &next) {
+                                        return Err(error);
+                                }
+                                new_path = next;
+                        }
+                }
+                Ok(new_path)
+        }
+
+        /// Renames `path` with the same checks as the GUI, files with problems are left alone.
+        pub fn rename(&mut self, path: &Path) -> Option<WatchEvent> {
+                let new_path = match self.new_path(path) {
+                        Ok(new_path) => new_path,
+                        Err(error) => return Some(WatchEvent::Skipped { path: path.to_path_buf(), reason: error }),
+                };
+                if new_path == path {
+                        return None;
+                }
+                let problems = validate::validate_batch(self.profile, &[(path, new_path.as_path())]).remove(0);
+                if !problems.is_empty() {
+                        return Some(WatchEvent::Skipped { path: path.to_path_buf(), reason: problems.join(", ") });
+                }
+                Some(match plan::rename_checked(&RealFs, path, &new_path, false) {
+                        Ok(_) => {
+                                self.index += 1;
+                                WatchEvent::Renamed { old: path.to_path_buf(), new: new_path }
+                        },
+                        Err(error) => WatchEvent::Skipped { path: path.to_path_buf(), reason: error },
+                })
+        }
+}
+
+/// Watches `folder` until `stop` is set, renaming every new file once it stops growing.
+/// Blocks the calling thread.
+pub fn run(folder: &Path, pipeline: &Pipeline, stop: &AtomicBool, mut on_event: impl FnMut(WatchEvent)) -> Result<(), String> {
+        // The watcher reports absolute paths, compare them with the absolute folder
+        let folder = &std::fs::canonicalize(folder).map_err(|e| format!("{}: {}", folder.display(), e))?;
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender).map_err(|e| e.to_string())?;
+        watcher.watch(folder, RecursiveMode::NonRecursive).map_err(|e| format!("{}: {}", folder.display(), e))?;
+        on_event(WatchEvent::Started(folder.to_path_buf()));
+
+        let mut renamer = Renamer::new(pipeline);
+        // Size and time of the last change of files that are still being written
+        let mut pending: HashMap<PathBuf, (u64, Instant)> = HashMap::new();
+        // Names given by the watcher and the modification time they kept, so renamed files are not
+        // picked up again while a later file that arrives under the same name is
+        let mut renamed: HashMap<PathBuf, Option<SystemTime>> = HashMap::new();
+
+        while !stop.load(Ordering::Relaxed) {
+                match receiver.recv_timeout(POLL_INTERVAL) {
+                        Ok(Ok(event)) => {
+                                if let EventKind::Remove(_) = event.kind {
+                                        for path in &event.paths {
+                                                renamed.remove(path);
+                                        }
+                                        continue;
+                                }
+                                if !matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                                        continue;
+                                }
+                                for path in event.paths {
+                                        let is_hidden = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with('.'));
+                                        if path.parent() != Some(folder) || is_hidden || !path.is_file() {
+                                                continue;
+                                        }
+                                        if let Some(modified) = renamed.get(&path) {
+                                                if *modified == modified_time(&path) {
+                                                        continue;
+                                                }
+                                                renamed.remove(&path);
+                                        }
+                                        let size = std::fs::metadata(&path).map(|meta| meta.len()).unwrap_or(0);
+                                        pending.insert(path, (size, Instant::now()));
+                                }
+                        },
+                        Ok(Err(error)) => on_event(WatchEvent::Failed(error.to_string())),
+                        Err(mpsc::RecvTimeoutError::Timeout) => {},
+                        Err(mpsc::RecvTimeoutError::Disconnected) => return Err(String::from("file watcher stopped")),
+                }
+
+                let mut ready = Vec::new();
+                pending.retain(|path, (size, changed)| {
+                        let Ok(meta) = std::fs::metadata(path) else { return false; };
+                        if meta.len() != *size {
+                                *size = meta.len();
+                                *changed = Instant::now();
+                        } else if changed.elapsed() >= SETTLE_TIME {
+                                ready.push(path.clone());
+                                return false;
+                        }
+                        true
+                });
+                ready.sort();
+                for path in ready {
+                        if let Some(event) = renamer.rename(&path) {
+                                if let WatchEvent::Renamed { new, .. } = &event {
+                                        renamed.insert(new.clone(), modified_time(new));
+                                }
+                                on_event(event);
+                        }
+                }
+        }
+        Ok(())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// `run` on a blocking thread, for the GUI.
+pub fn watch(folder: PathBuf, pipeline: Pipeline, stop: Arc<AtomicBool>) -> impl Stream<Item = WatchEvent> {
+        iced::stream::channel(100, async move |output| {
+                let result = tokio::task::spawn_blocking(move || {
+                        let mut output = output;
+                        let result = run(&folder, &pipeline, &stop, |event| {
+                                let _ = iced::futures::executor::block_on(output.send(event));
+                        });
+                        if let Err(error) = result {
+                                let _ = iced::futures::executor::block_on(output.send(WatchEvent::Failed(error)));
+                        }
+                }).await;
+                if let Err(error) = result {
+                        eprintln!("Watch thread failed: {:?}", error);
+                }
+        })
+}